@@ -13,12 +13,7 @@
 // ---------------------------------------
 // ---------------------------------------
 
-#import "shaders/sampling.wgsl" as sampling
-#import "shaders/sampling.wgsl"::TAU
 #import bevy_pbr::view_transformations as vt
-#import "shaders/rgb9e5.wgsl"::{vec3_to_rgb9e5_, rgb9e5_to_vec3_}
-#import "shaders/xyz8e5.wgsl"::{vec3_to_xyz8e5_, xyz8e5_to_vec3_}
-#import "shaders/util.wgsl" as util
 #import bevy_pbr::utils::{octahedral_encode, octahedral_decode}
 
 #import bevy_pbr::{
@@ -27,6 +22,10 @@
 }
 
 #import bevy_pbr::mesh_view_bindings as view_bindings
+#import bevy_pbr::lighting::F_AB
+#ifdef SSGI_ENVIRONMENT_DIFFUSE
+#import bevy_pbr::{environment_map, lighting}
+#endif
 #import bevy_pbr::prepass_utils::prepass_motion_vector
 
 @group(1) @binding(0) var<uniform> depth_id: PbrDeferredLightingDepthId;
@@ -34,6 +33,7 @@
 @group(1) @binding(5) var nearest_sampler: sampler;
 @group(1) @binding(6) var linear_sampler: sampler;
 @group(1) @binding(12) var ssgi_resolve: texture_2d<f32>;
+@group(1) @binding(13) var ssgi_resolve_specular: texture_2d<f32>;
 
 // ---------------------------------------
 // ---------------------------------------
@@ -44,6 +44,7 @@
 #ifdef SCREEN_SPACE_AMBIENT_OCCLUSION
 #import bevy_pbr::mesh_view_bindings::screen_space_ambient_occlusion_texture
 #import bevy_pbr::gtao_utils::gtao_multibounce
+#import bevy_pbr::lighting::perceptualRoughnessToRoughness
 #endif
 
 struct FullscreenVertexOutput {
@@ -102,20 +103,58 @@ fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
 #ifdef SCREEN_SPACE_AMBIENT_OCCLUSION
         let ssao = textureLoad(screen_space_ambient_occlusion_texture, vec2<i32>(in.position.xy), 0i).r;
         let ssao_multibounce = gtao_multibounce(ssao, pbr_input.material.base_color.rgb);
-        pbr_input.occlusion = min(pbr_input.occlusion, ssao_multibounce);
+        pbr_input.diffuse_occlusion = min(pbr_input.diffuse_occlusion, ssao_multibounce);
+
+        // Same as bevy's deferred lighting, from "Moving Frostbite to Physically Based Rendering"
+        let ssao_NdotV = max(dot(pbr_input.N, pbr_input.V), 0.0001);
+        let ssao_roughness = perceptualRoughnessToRoughness(pbr_input.material.perceptual_roughness);
+        pbr_input.specular_occlusion = saturate(pow(ssao_NdotV + ssao, exp2(-16.0 * ssao_roughness - 1.0)) - 1.0 + ssao);
 #endif // SCREEN_SPACE_AMBIENT_OCCLUSION
 
+#ifdef SSGI_SPECULAR
+        // The SSGI indirect specular replaces the environment map's, which is the only thing
+        // specular_occlusion is applied to
+        pbr_input.specular_occlusion = 0.0;
+#endif
+
         output_color = pbr_functions::apply_pbr_lighting(pbr_input);
         
 
 // ----------------------------------------------------
 // ----------------------------------------------------
 // ----------------------------------------------------
-    let diffuse_color = pbr_input.material.base_color;// * (1.0 - pbr_input.material.metallic);
+    let metallic = pbr_input.material.metallic;
+    let reflectance = pbr_input.material.reflectance;
+    let diffuse_color = pbr_input.material.base_color.rgb * (1.0 - metallic);
     //let indirect_light = read_cascade_radiance(pbr_input, pbr_input.N, pbr_input.frag_coord, pbr_input.world_position.xyz);
-    let indirect_light = textureLoad(ssgi_resolve, vec2<i32>(frag_coord.xy), 0).rgb;
+    // The SSGI textures are the size of the viewport, the deferred prepass the size of the render target
+    let iviewport_coord = vec2<i32>(frag_coord.xy - view.viewport.xy);
+    let indirect_light = textureLoad(ssgi_resolve, iviewport_coord, 0).rgb;
+
+    // Same F0 as apply_pbr_lighting
+    let F0 = 0.16 * reflectance * reflectance * (1.0 - metallic) + pbr_input.material.base_color.rgb * metallic;
+    let NdotV = max(dot(pbr_input.N, pbr_input.V), 0.0001);
+    let f_ab = F_AB(pbr_input.material.perceptual_roughness, NdotV);
+#ifdef SSGI_ENVIRONMENT_DIFFUSE
+    // The SSGI samples the environment map diffuse where its rays escape, with their occlusion, so
+    // the unoccluded environment map diffuse apply_pbr_lighting added is taken back out
+    let environment_light = environment_map::environment_map_light(
+        pbr_input.material.perceptual_roughness,
+        lighting::perceptualRoughnessToRoughness(pbr_input.material.perceptual_roughness),
+        diffuse_color,
+        NdotV,
+        f_ab,
+        pbr_input.N,
+        reflect(-pbr_input.V, pbr_input.N),
+        F0,
+        pbr_input.world_position.xyz,
+        false,
+    );
+    output_color = vec4(max(output_color.rgb - view.exposure * environment_light.diffuse * pbr_input.diffuse_occlusion, vec3(0.0)), output_color.a);
+#endif
+    let indirect_specular = textureLoad(ssgi_resolve_specular, iviewport_coord, 0).rgb;
     
-    output_color += vec4(diffuse_color.rgb * indirect_light, 0.0);
+    output_color += vec4(diffuse_color * indirect_light + (F0 * f_ab.x + f_ab.y) * indirect_specular, 0.0);
 // ----------------------------------------------------
 // ----------------------------------------------------
 // ----------------------------------------------------
@@ -42,9 +42,10 @@ struct SSGIConfig {
     cascade_0_dist: f32,
     divide_steps_by_square_of_cascade_exp: u32,
     horizon_occlusion: f32,
+    environment_fallback: f32,
+    // 0.0 for infinite
+    thickness: f32,
     _webgl2_padding_1: f32,
-    _webgl2_padding_2: f32,
-    _webgl2_padding_3: f32,
 }
 
 @group(0) @binding(101) var prev_frame_tex: texture_2d<f32>;
@@ -53,23 +54,116 @@ struct SSGIConfig {
 @group(0) @binding(104) var prepass_downsample_motion: texture_2d<f32>;
 @group(0) @binding(105) var nearest_sampler: sampler;
 @group(0) @binding(106) var linear_sampler: sampler;
-// todo webgl @group(0) @binding(108) var disocclusion_texture: texture_2d<f32>;
+@group(0) @binding(107) var environment_map: texture_cube<f32>;
+#ifdef DISOCCLUSION
+@group(0) @binding(108) var disocclusion_texture: texture_2d<f32>;
+#endif
 @group(0) @binding(109) var<uniform> config: SSGIConfig;
 @group(0) @binding(110) var higher_cascade_data1: texture_2d<u32>;
 @group(0) @binding(111) var higher_cascade_data2: texture_2d<u32>;
+// Closest back faces with BACK_FACE_DEPTH, reversed Z and 0.0 where there are none
+@group(0) @binding(114) var back_face_depth_texture: texture_2d<f32>;
+
+#ifdef COMPUTE
+@group(0) @binding(112) var cascade_data1_out: texture_storage_2d<rgba32uint, write>;
+@group(0) @binding(113) var cascade_data2_out: texture_storage_2d<rgba32uint, write>;
+
+// Same as @workgroup_size and SSGI_WORKGROUP_SIZE in ssgi.rs
+const WORKGROUP_SIZE: u32 = 8u;
+// Depth around the workgroup's probes at the mip the march samples, most steps land inside it
+const DEPTH_TILE_SIZE: u32 = 32u;
+#ifdef DEPTH_MIN_MAX
+var<workgroup> depth_tile: array<vec2<f32>, 1024>; // DEPTH_TILE_SIZE * DEPTH_TILE_SIZE
+#else
+var<workgroup> depth_tile: array<f32, 1024>; // DEPTH_TILE_SIZE * DEPTH_TILE_SIZE
+#endif
+var<private> depth_tile_origin: vec2<i32>;
+#endif
+
+struct CascadeData {
+    data1: vec4<u32>,
+    data2: vec4<u32>,
+}
+
+#ifdef COMPUTE
+@compute @workgroup_size(8, 8, 1)
+fn compute(
+    @builtin(global_invocation_id) global_id: vec3<u32>,
+    @builtin(workgroup_id) workgroup_id: vec3<u32>,
+    @builtin(local_invocation_index) local_index: u32,
+) {
+    load_depth_tile(workgroup_id.xy, local_index);
+    workgroupBarrier();
+
+    let dims = textureDimensions(cascade_data1_out);
+    if any(global_id.xy >= dims) {
+        return;
+    }
 
+    let uv = (vec2<f32>(global_id.xy) + 0.5) / vec2<f32>(dims);
+    let data = cascade_probe(global_id.xy, uv);
+    textureStore(cascade_data1_out, global_id.xy, data.data1);
+    textureStore(cascade_data2_out, global_id.xy, data.data2);
+}
+
+fn load_depth_tile(workgroup_id: vec2<u32>, local_index: u32) {
+    // Signed, naga only accepts an i32 mip level in textureLoad
+    let level = i32(round(march_depth_mip()));
+    let dims = vec2<i32>(textureDimensions(prepass_downsample_depth, level));
+
+    // Centered on the middle probe of the workgroup
+    let probe_texels = vec2(4u, config.directions / 4u);
+    let center_probe = (workgroup_id * WORKGROUP_SIZE + WORKGROUP_SIZE / 2u) / probe_texels;
+    let render_scale = f32(config.cas_0_render_scale << config.cascade_n);
+    let center_uv = (vec2<f32>(center_probe) + 0.5) * render_scale / view.viewport.zw;
+    depth_tile_origin = vec2<i32>(center_uv * vec2<f32>(dims)) - i32(DEPTH_TILE_SIZE / 2u);
+
+    let texels_per_thread = DEPTH_TILE_SIZE * DEPTH_TILE_SIZE / (WORKGROUP_SIZE * WORKGROUP_SIZE);
+    for (var i = 0u; i < texels_per_thread; i += 1u) {
+        let index = local_index + i * WORKGROUP_SIZE * WORKGROUP_SIZE;
+        let tile_texel = vec2(index % DEPTH_TILE_SIZE, index / DEPTH_TILE_SIZE);
+        let texel = clamp(depth_tile_origin + vec2<i32>(tile_texel), vec2(0), dims - 1);
+        let depth = textureLoad(prepass_downsample_depth, texel, level);
+#ifdef DEPTH_MIN_MAX
+        depth_tile[index] = depth.xy;
+#else
+        depth_tile[index] = depth.x;
+#endif
+    }
+}
+#else
 struct FragmentOutput {
     @location(0) data1: vec4<u32>,
     @location(1) data2: vec4<u32>,
 }
 
-
-
 @fragment
 fn fragment(in: FullscreenVertexOutput) -> FragmentOutput {
+    let data = cascade_probe(vec2<u32>(in.position.xy), in.uv);
+    return FragmentOutput(data.data1, data.data2);
+}
+#endif
+
+fn march_depth_mip() -> f32 {
+    return clamp(f32(config.cascade_n) - 1.0, config.depth_mip_min, config.mip_max);
+}
+
+// The closest depth, and with DEPTH_MIN_MAX the farthest depth in y
+fn march_depth(uv: vec2<f32>, depth_mip: f32) -> vec2<f32> {
+#ifdef COMPUTE
+    let level = u32(round(depth_mip));
+    let dims = textureDimensions(prepass_downsample_depth, level);
+    let tile_texel = vec2<i32>(uv * vec2<f32>(dims)) - depth_tile_origin;
+    if all(tile_texel >= vec2(0)) && all(tile_texel < vec2(i32(DEPTH_TILE_SIZE))) {
+        return vec2<f32>(depth_tile[u32(tile_texel.y) * DEPTH_TILE_SIZE + u32(tile_texel.x)]);
+    }
+#endif
+    return textureSampleLevel(prepass_downsample_depth, nearest_sampler, uv, depth_mip).xy;
+}
+
+fn cascade_probe(uposition: vec2<u32>, uv: vec2<f32>) -> CascadeData {
     var out = vec4(0.0);
 
-    let uposition = vec2<u32>(in.position.xy);
     let cas_xy = uposition / vec2(4u, config.directions / 4u);
 
     var frag_coord = vec4<f32>(common::frag_coord_for_cas(config.cascade_n, vec2<i32>(cas_xy), config.cas_0_render_scale), 0.0, 0.0);
@@ -77,7 +171,7 @@ fn fragment(in: FullscreenVertexOutput) -> FragmentOutput {
     frag_coord.z = textureLoad(prepass_downsample_depth, vec2<i32>(frag_coord.xy), 0).x;
     let normal = octahedral_decode(textureLoad(prepass_downsample_normals, vec2<i32>(frag_coord.xy), 0).xy);
 
-    let ws_pos = vt::position_ndc_to_world(vec3(vt::uv_to_ndc(in.uv), frag_coord.z));
+    let ws_pos = vt::position_ndc_to_world(vec3(vt::uv_to_ndc(uv), frag_coord.z));
 
     var pixel_radius = sampling::world_space_pixel_radius(-vt::depth_ndc_to_view_z(frag_coord.z));
     // limit minimum pixel radius for things really close to the camera
@@ -99,8 +193,8 @@ fn fragment(in: FullscreenVertexOutput) -> FragmentOutput {
 // ----------------------------------------------------
 // ----------------------------------------------------
 
-fn ssgi(frag_coord_in: vec4<f32>, cas_xy: vec2<u32>, uposition: vec2<u32>, world_position: vec3<f32>, normal: vec3<f32>) -> FragmentOutput {
-    var out: FragmentOutput;
+fn ssgi(frag_coord_in: vec4<f32>, cas_xy: vec2<u32>, uposition: vec2<u32>, world_position: vec3<f32>, normal: vec3<f32>) -> CascadeData {
+    var out: CascadeData;
 
     var color = vec3(0.0);
 
@@ -168,8 +262,18 @@ fn ssgi(frag_coord_in: vec4<f32>, cas_xy: vec2<u32>, uposition: vec2<u32>, world
     var march_gather8 = vec3(0.0);
 
     var march_gather_weight = 0.0;
+    var escaped = false;
     var bitmask = 0u;
     let bitmask_steps = 32.0;
+    // Angles between the front and back of the samples so far, when they aren't infinitely thick
+    var occluded_bitmask = 0u;
+#ifdef BACK_FACE_DEPTH
+    let finite_thickness = true;
+#else
+    // The depth range of DEPTH_MIN_MAX only thickens a constant thickness, on flat surfaces
+    // it's about zero and would make every sample paper thin
+    let finite_thickness = config.thickness > 0.0;
+#endif
 
     // TODO needs more testing, for scaling light contribution with distance so really close things don't contribute disproportionately
     // Makes light contribution independant of screen res
@@ -182,6 +286,7 @@ fn ssgi(frag_coord_in: vec4<f32>, cas_xy: vec2<u32>, uposition: vec2<u32>, world
         let samp_screen_uv = samp_frag_coord * texel_size;
 
         if (samp_screen_uv.x <= 0.0 || samp_screen_uv.y <= 0.0 || samp_screen_uv.x >= 1.0 || samp_screen_uv.y >= 1.0) {
+            escaped = true;
             break;
         }
 
@@ -189,16 +294,18 @@ fn ssgi(frag_coord_in: vec4<f32>, cas_xy: vec2<u32>, uposition: vec2<u32>, world
         let ss_dist = distance(samp_frag_coord, frag_coord.xy);
 
         var mip = clamp(fcascade - 1.0, config.mip_min, config.mip_max);
-        var depth_mip = clamp(fcascade - 1.0, config.depth_mip_min, config.mip_max);
+        var depth_mip = march_depth_mip();
 
         let closest_motion_vector = textureSampleLevel(prepass_downsample_motion, nearest_sampler, samp_screen_uv, mip).xy;
         let history_uv = samp_screen_uv - closest_motion_vector;
 
         if (history_uv.x <= 0.0 || history_uv.y <= 0.0 || history_uv.x >= 1.0 || history_uv.y >= 1.0) {
+            escaped = true;
             break;
         }
 
-        var samp_depth = textureSampleLevel(prepass_downsample_depth, nearest_sampler, samp_screen_uv, depth_mip).x;
+        let samp_depths = march_depth(samp_screen_uv, depth_mip);
+        var samp_depth = samp_depths.x;
 
         let samp_ndc = vec3(vt::uv_to_ndc(samp_screen_uv), max(samp_depth, 0.00000001));
         var samp_ws_pos = vt::position_ndc_to_world(samp_ndc);
@@ -210,15 +317,31 @@ fn ssgi(frag_coord_in: vec4<f32>, cas_xy: vec2<u32>, uposition: vec2<u32>, world
 
         //let visible = hit_angle > max_occluded_angle;
         var samp_bitmask = 1u << u32(round(bitmask_steps * hit_angle));
-        let visible = (bitmask & samp_bitmask) == 0u || hit_angle > max_occluded_angle;
-        max_occluded_angle = max(max_occluded_angle, hit_angle);
+        var visible: bool;
+        if finite_thickness {
+            // Each sample only occludes the angles between its front and back, instead of
+            // everything below the horizon, so light can pass behind thin geometry
+            let samp_back_ws_pos = sample_back(samp_ws_pos, samp_screen_uv, samp_depths);
+            let back_angle = saturate(dot(V, normalize(samp_back_ws_pos - ws_pos)) * 0.5 + 0.5);
+            visible = (occluded_bitmask & samp_bitmask) == 0u;
+            occluded_bitmask |= angle_range_bits(hit_angle, back_angle, bitmask_steps);
+        } else {
+            visible = (bitmask & samp_bitmask) == 0u || hit_angle > max_occluded_angle;
+            // Thin samples don't raise the horizon, they only occlude what's directly behind them
+            max_occluded_angle = max(max_occluded_angle, hit_angle);
+        }
 
         let inside_current_interval = distance(frag_coord.xy, samp_frag_coord) > prev_interval_dist;
 
         if visible {
             // Don't contribute light on the overlap
             if inside_current_interval {
-                let samp_color = textureSampleLevel(prev_frame_tex, nearest_sampler, history_uv, mip).xyz;
+                var samp_color = textureSampleLevel(prev_frame_tex, nearest_sampler, history_uv, mip).xyz;
+#ifdef DISOCCLUSION
+                // The previous frame has no valid color for newly revealed areas
+                let disocclusion = textureSampleLevel(disocclusion_texture, nearest_sampler, samp_screen_uv, 0.0);
+                samp_color *= 1.0 - common::disocclusion_amount(disocclusion);
+#endif
                 let dist = length(to_sample);
                 let samp_normal = octahedral_decode(textureSampleLevel(prepass_downsample_normals, nearest_sampler, samp_screen_uv, mip).xy);
                 var hit_facing_sample = saturate(dot(samp_normal, -dir_to_sample) + config.backside_illumination);
@@ -270,7 +393,8 @@ fn ssgi(frag_coord_in: vec4<f32>, cas_xy: vec2<u32>, uposition: vec2<u32>, world
     var gather8 = vec3(0.0);
 
     // If we're not the highest cascase_n then sample from the next cascade up
-    if config.cascade_n < config.cascade_count - 1u { // 
+    // If the ray left the screen the higher cascade won't have anything better than the environment map
+    if config.cascade_n < config.cascade_count - 1u && !escaped { // 
 
         let higher_directions = config.directions * 2u; // The next cascade will have 2x the directions of this one
 
@@ -369,16 +493,30 @@ fn ssgi(frag_coord_in: vec4<f32>, cas_xy: vec2<u32>, uposition: vec2<u32>, world
         //gather2 /= max(weight, 1.0);
         //gather3 /= max(weight, 1.0);
         //gather4 /= max(weight, 1.0);
-  }
+  } else if config.environment_fallback > 0.0 {
+        // Ray left the screen, or this is the last cascade. Use the environment map for whatever isn't occluded.
+        var ws_dir = common::ss_dir_to_ws_dir(world_position, screen_uv, ss_dir, frag_coord.z);
+        gather1 += sample_environment(common::reconstruct_dir_to_sample(V, ws_dir, 0.111));
+        gather2 += sample_environment(common::reconstruct_dir_to_sample(V, ws_dir, 0.222));
+        gather3 += sample_environment(common::reconstruct_dir_to_sample(V, ws_dir, 0.333));
+        gather4 += sample_environment(common::reconstruct_dir_to_sample(V, ws_dir, 0.444));
+        gather5 += sample_environment(common::reconstruct_dir_to_sample(V, ws_dir, 0.555));
+        gather6 += sample_environment(common::reconstruct_dir_to_sample(V, ws_dir, 0.666));
+        gather7 += sample_environment(common::reconstruct_dir_to_sample(V, ws_dir, 0.777));
+        gather8 += sample_environment(common::reconstruct_dir_to_sample(V, ws_dir, 0.888));
+    }
     
-    gather1 *= get_vis(bitmask, max_occluded_angle, 0.111, bitmask_steps);
-    gather2 *= get_vis(bitmask, max_occluded_angle, 0.222, bitmask_steps);
-    gather3 *= get_vis(bitmask, max_occluded_angle, 0.333, bitmask_steps);
-    gather4 *= get_vis(bitmask, max_occluded_angle, 0.444, bitmask_steps);
-    gather5 *= get_vis(bitmask, max_occluded_angle, 0.555, bitmask_steps);
-    gather6 *= get_vis(bitmask, max_occluded_angle, 0.666, bitmask_steps);
-    gather7 *= get_vis(bitmask, max_occluded_angle, 0.777, bitmask_steps);
-    gather8 *= get_vis(bitmask, max_occluded_angle, 0.888, bitmask_steps);
+    // With finite thickness, the higher cascades and environment are only occluded between the
+    // front and back of the samples, instead of below the horizon
+    let vis_bitmask = select(bitmask, occluded_bitmask, finite_thickness);
+    gather1 *= get_vis(vis_bitmask, max_occluded_angle, 0.111, bitmask_steps, finite_thickness);
+    gather2 *= get_vis(vis_bitmask, max_occluded_angle, 0.222, bitmask_steps, finite_thickness);
+    gather3 *= get_vis(vis_bitmask, max_occluded_angle, 0.333, bitmask_steps, finite_thickness);
+    gather4 *= get_vis(vis_bitmask, max_occluded_angle, 0.444, bitmask_steps, finite_thickness);
+    gather5 *= get_vis(vis_bitmask, max_occluded_angle, 0.555, bitmask_steps, finite_thickness);
+    gather6 *= get_vis(vis_bitmask, max_occluded_angle, 0.666, bitmask_steps, finite_thickness);
+    gather7 *= get_vis(vis_bitmask, max_occluded_angle, 0.777, bitmask_steps, finite_thickness);
+    gather8 *= get_vis(vis_bitmask, max_occluded_angle, 0.888, bitmask_steps, finite_thickness);
 
     //if config.cascade_n == 5u {
         gather1 += march_gather1;
@@ -391,6 +529,18 @@ fn ssgi(frag_coord_in: vec4<f32>, cas_xy: vec2<u32>, uposition: vec2<u32>, world
         gather8 += march_gather8;
     //}
 
+#ifdef DEBUG_OCCLUSION
+    // For SSGIDebugView::OcclusionBitmask
+    gather1 = vec3(get_vis(vis_bitmask, max_occluded_angle, 0.111, bitmask_steps, finite_thickness));
+    gather2 = vec3(get_vis(vis_bitmask, max_occluded_angle, 0.222, bitmask_steps, finite_thickness));
+    gather3 = vec3(get_vis(vis_bitmask, max_occluded_angle, 0.333, bitmask_steps, finite_thickness));
+    gather4 = vec3(get_vis(vis_bitmask, max_occluded_angle, 0.444, bitmask_steps, finite_thickness));
+    gather5 = vec3(get_vis(vis_bitmask, max_occluded_angle, 0.555, bitmask_steps, finite_thickness));
+    gather6 = vec3(get_vis(vis_bitmask, max_occluded_angle, 0.666, bitmask_steps, finite_thickness));
+    gather7 = vec3(get_vis(vis_bitmask, max_occluded_angle, 0.777, bitmask_steps, finite_thickness));
+    gather8 = vec3(get_vis(vis_bitmask, max_occluded_angle, 0.888, bitmask_steps, finite_thickness));
+#endif
+
     out.data1.x = vec3_to_rgb9e5_(gather1);
     out.data1.y = vec3_to_rgb9e5_(gather2);
     out.data1.z = vec3_to_rgb9e5_(gather3);
@@ -403,6 +553,12 @@ fn ssgi(frag_coord_in: vec4<f32>, cas_xy: vec2<u32>, uposition: vec2<u32>, world
     return out;
 }
 
+fn sample_environment(dir: vec3<f32>) -> vec3<f32> {
+    // Same orientation as bevy's environment_map.wgsl
+    let irradiance = textureSampleLevel(environment_map, linear_sampler, vec3(dir.xy, -dir.z), 0.0).rgb;
+    return irradiance * config.environment_fallback;
+}
+
 fn count_bits(val_in: u32) -> u32 {
     var val = val_in;
     // Counts the number of 1:s
@@ -415,20 +571,58 @@ fn count_bits(val_in: u32) -> u32 {
     return val;
 }
 
-fn get_vis(bitmask: u32, max_occluded_angle: f32, angle: f32, bitmask_steps: f32) -> f32 {
+// Thickness used when nothing limits it, far enough that the back is along the view ray
+const INFINITE_THICKNESS: f32 = 1.0e10;
+
+// World space position of the back of a march sample. SSGIPass::thickness, at least as thick as
+// the depth range under the sample with DEPTH_MIN_MAX, and no further than the back face behind the
+// sample with BACK_FACE_DEPTH.
+fn sample_back(samp_ws_pos: vec3<f32>, samp_screen_uv: vec2<f32>, samp_depths: vec2<f32>) -> vec3<f32> {
+    let view_dir = normalize(samp_ws_pos - view.world_position.xyz);
+    var thickness = select(INFINITE_THICKNESS, config.thickness, config.thickness > 0.0);
+#ifdef DEPTH_MIN_MAX
+    // There's geometry from the closest to the farthest depth of the sample, like across an edge
+    let farthest_ws_pos = vt::position_ndc_to_world(vec3(vt::uv_to_ndc(samp_screen_uv), max(samp_depths.y, 0.00000001)));
+    thickness = max(thickness, distance(farthest_ws_pos, samp_ws_pos));
+#endif
+#ifdef BACK_FACE_DEPTH
+    let back_face_coords = vec2<i32>(samp_screen_uv * vec2<f32>(textureDimensions(back_face_depth_texture)));
+    let back_face_depth = textureLoad(back_face_depth_texture, back_face_coords, 0).x;
+    // Back faces in front of the sample belong to something else, like from a coarser depth mip
+    if back_face_depth > 0.0 && back_face_depth <= samp_depths.x {
+        let back_face_ws_pos = vt::position_ndc_to_world(vec3(vt::uv_to_ndc(samp_screen_uv), back_face_depth));
+        thickness = min(thickness, distance(back_face_ws_pos, samp_ws_pos));
+    }
+#endif
+    return samp_ws_pos + view_dir * thickness;
+}
+
+// Bits of the bitmask from the lower to the higher of the two angles
+fn angle_range_bits(a: f32, b: f32, bitmask_steps: f32) -> u32 {
+    let first = min(u32(round(bitmask_steps * min(a, b))), 31u);
+    let last = min(u32(round(bitmask_steps * max(a, b))), 31u);
+    return (0xFFFFFFFFu >> (31u - (last - first))) << first;
+}
+
+fn get_vis(bitmask: u32, max_occluded_angle: f32, angle: f32, bitmask_steps: f32, finite_thickness: bool) -> f32 {
     var uangle = u32(round(angle * bitmask_steps));
     var bitvis = count_bits(((bitmask >> uangle) & 31u)); // 4 bit wide
     bitvis += count_bits(((bitmask >> (uangle - 1u)) & 127u)); // 6 bit wide, if using this divide vis by twice as much
     var vis = f32(bitvis);
     vis = mix(vis, 0.0, saturate(config.horizon_occlusion * 0.01));
 
-    //vis = select(vis, 0.0, angle > max_occluded_angle);
-    vis = mix(vis, 0.0, saturate((angle - max_occluded_angle) * 30.0)); // similar to above but with a softer cutoff
+    // Finite thickness has no horizon, the bitmask is all that's occluded
+    if !finite_thickness {
+        //vis = select(vis, 0.0, angle > max_occluded_angle);
+        vis = mix(vis, 0.0, saturate((angle - max_occluded_angle) * 30.0)); // similar to above but with a softer cutoff
+    }
 
     vis /= 3.0; // TODO this could be between /1.0 and /8.0
 
-    // include raw angle occlusion
-    vis += saturate(saturate(max_occluded_angle - angle) * config.horizon_occlusion);
+    if !finite_thickness {
+        // include raw angle occlusion
+        vis += saturate(saturate(max_occluded_angle - angle) * config.horizon_occlusion);
+    }
 
     return 1.0 - saturate(vis); 
 }
\ No newline at end of file
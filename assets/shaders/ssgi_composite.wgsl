@@ -0,0 +1,42 @@
+#import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
+#import bevy_pbr::{
+    mesh_view_bindings::view,
+    pbr_types::STANDARD_MATERIAL_FLAGS_UNLIT_BIT,
+    pbr_deferred_functions::pbr_input_from_deferred_gbuffer,
+    lighting::F_AB,
+}
+
+@group(0) @binding(101) var deferred_prepass_texture: texture_2d<u32>;
+@group(0) @binding(102) var ssgi_resolve: texture_2d<f32>;
+@group(0) @binding(103) var prepass_downsample_depth: texture_2d<f32>;
+@group(0) @binding(104) var ssgi_resolve_specular: texture_2d<f32>;
+
+// Output is additively blended on top of the lit frame from Bevy's deferred lighting pass
+@fragment
+fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    let ifrag_coord = vec2<i32>(in.position.xy);
+    // The deferred prepass is the size of the render target, the SSGI textures the size of the viewport
+    let iviewport_coord = vec2<i32>(in.position.xy - view.viewport.xy);
+    var frag_coord = vec4(in.position.xy, 0.0, 0.0);
+    frag_coord.z = textureLoad(prepass_downsample_depth, iviewport_coord, 0).x;
+
+    let deferred_data = textureLoad(deferred_prepass_texture, ifrag_coord, 0);
+    let pbr_input = pbr_input_from_deferred_gbuffer(frag_coord, deferred_data);
+
+    if (pbr_input.material.flags & STANDARD_MATERIAL_FLAGS_UNLIT_BIT) != 0u {
+        return vec4(0.0);
+    }
+
+    let metallic = pbr_input.material.metallic;
+    let reflectance = pbr_input.material.reflectance;
+    let diffuse_color = pbr_input.material.base_color.rgb * (1.0 - metallic);
+    let indirect_light = textureLoad(ssgi_resolve, iviewport_coord, 0).rgb;
+
+    // Same F0 as apply_pbr_lighting
+    let F0 = 0.16 * reflectance * reflectance * (1.0 - metallic) + pbr_input.material.base_color.rgb * metallic;
+    let NdotV = max(dot(pbr_input.N, pbr_input.V), 0.0001);
+    let f_ab = F_AB(pbr_input.material.perceptual_roughness, NdotV);
+    let indirect_specular = textureLoad(ssgi_resolve_specular, iviewport_coord, 0).rgb;
+
+    return vec4(diffuse_color * indirect_light + (F0 * f_ab.x + f_ab.y) * indirect_specular, 0.0);
+}
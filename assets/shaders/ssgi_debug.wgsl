@@ -0,0 +1,119 @@
+#import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
+#import bevy_pbr::view_transformations as vt
+#import bevy_pbr::mesh_view_bindings::view
+#import bevy_pbr::utils::octahedral_decode
+#import ssgi::rgb9e5::rgb9e5_to_vec3_
+#import ssgi::xyz8e5::xyz8e5_to_vec3_
+
+struct SSGIDebugConfig {
+    mode: u32,
+    index: u32,
+    directions: u32,
+    _webgl2_padding_1: f32,
+}
+
+// Same as SSGIDebugView::mode_index()
+const MODE_CASCADE_RADIANCE: u32 = 1u;
+const MODE_SH_IRRADIANCE: u32 = 2u;
+const MODE_RESOLVED_DIFFUSE: u32 = 3u;
+const MODE_RESOLVED_SPECULAR: u32 = 4u;
+const MODE_DEPTH: u32 = 5u;
+const MODE_NORMALS: u32 = 6u;
+const MODE_MOTION: u32 = 7u;
+const MODE_HISTORY_REJECTION: u32 = 8u;
+const MODE_OCCLUSION_BITMASK: u32 = 9u;
+
+@group(0) @binding(101) var cascade_data1: texture_2d<u32>;
+@group(0) @binding(102) var cascade_data2: texture_2d<u32>;
+@group(0) @binding(103) var sh_data: texture_2d<u32>;
+@group(0) @binding(104) var ssgi_resolve: texture_2d<f32>;
+@group(0) @binding(105) var ssgi_resolve_specular: texture_2d<f32>;
+@group(0) @binding(106) var prepass_downsample_depth: texture_2d<f32>;
+@group(0) @binding(107) var prepass_downsample_normals: texture_2d<f32>;
+@group(0) @binding(108) var prepass_downsample_motion: texture_2d<f32>;
+@group(0) @binding(109) var<uniform> config: SSGIDebugConfig;
+#ifdef DISOCCLUSION
+@group(0) @binding(110) var disocclusion_texture: texture_2d<f32>;
+#endif
+
+@fragment
+fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    // Every texture shown is the size of the viewport
+    let ifrag_coord = vec2<i32>(in.position.xy - view.viewport.xy);
+    var color = vec3(0.0);
+
+    switch config.mode {
+        case MODE_CASCADE_RADIANCE, MODE_OCCLUSION_BITMASK: {
+            color = cascade_probe_average(in.uv);
+        }
+        case MODE_SH_IRRADIANCE: {
+            let N = octahedral_decode(textureLoad(prepass_downsample_normals, ifrag_coord, 0).xy);
+            let sh = textureLoad(sh_data, texel_for_uv(in.uv, textureDimensions(sh_data)), 0);
+            color = rgb9e5_to_vec3_(sh.x)
+                + xyz8e5_to_vec3_(sh.y) * N.x
+                + xyz8e5_to_vec3_(sh.z) * N.y
+                + xyz8e5_to_vec3_(sh.w) * N.z;
+        }
+        case MODE_RESOLVED_DIFFUSE: {
+            color = textureLoad(ssgi_resolve, ifrag_coord, 0).rgb;
+        }
+        case MODE_RESOLVED_SPECULAR: {
+            color = textureLoad(ssgi_resolve_specular, ifrag_coord, 0).rgb;
+        }
+        case MODE_DEPTH: {
+            let depth = textureLoad(prepass_downsample_depth, mip_texel(in.uv, prepass_downsample_depth), i32(config.index)).x;
+            let view_z = -vt::depth_ndc_to_view_z(max(depth, 0.00000001));
+            color = vec3(exp(-view_z * 0.1));
+        }
+        case MODE_NORMALS: {
+            let normal = textureLoad(prepass_downsample_normals, mip_texel(in.uv, prepass_downsample_normals), i32(config.index)).xy;
+            color = octahedral_decode(normal) * 0.5 + 0.5;
+        }
+        case MODE_MOTION: {
+            let motion = textureLoad(prepass_downsample_motion, mip_texel(in.uv, prepass_downsample_motion), i32(config.index)).xy;
+            color = vec3(abs(motion) * 50.0, 0.0);
+        }
+        case MODE_HISTORY_REJECTION: {
+            // Same as ssgi_resolve.wgsl
+            let motion = textureLoad(prepass_downsample_motion, ifrag_coord, 0).xy;
+            let history_uv = in.uv - motion;
+            var history_rejection = f32(any(history_uv <= vec2(0.0)) || any(history_uv >= vec2(1.0)));
+#ifdef DISOCCLUSION
+            let d = textureLoad(disocclusion_texture, ifrag_coord, 0);
+            let two_of_three = min(min(max(d.x, d.y), max(d.y, d.z)), max(d.x, d.z));
+            history_rejection = max(history_rejection, saturate(two_of_three * 3.0));
+#endif
+            color = vec3(history_rejection, 0.0, 0.0);
+        }
+        default: {}
+    }
+
+    return vec4(color, 1.0);
+}
+
+fn texel_for_uv(uv: vec2<f32>, dims: vec2<u32>) -> vec2<i32> {
+    return clamp(vec2<i32>(uv * vec2<f32>(dims)), vec2(0), vec2<i32>(dims) - 1);
+}
+
+fn mip_texel(uv: vec2<f32>, tex: texture_2d<f32>) -> vec2<i32> {
+    return texel_for_uv(uv, textureDimensions(tex, config.index));
+}
+
+// Each probe has a 4 x (directions / 4) tile of texels, each texel has 8 angle bins
+fn cascade_probe_average(uv: vec2<f32>) -> vec3<f32> {
+    let tile = vec2(4u, max(config.directions / 4u, 1u));
+    let probes = max(textureDimensions(cascade_data1) / tile, vec2(1u));
+    let probe = vec2<u32>(texel_for_uv(uv, probes));
+
+    var sum = vec3(0.0);
+    for (var x = 0u; x < tile.x; x += 1u) {
+        for (var y = 0u; y < tile.y; y += 1u) {
+            let coord = vec2<i32>(probe * tile + vec2(x, y));
+            let c1 = textureLoad(cascade_data1, coord, 0);
+            let c2 = textureLoad(cascade_data2, coord, 0);
+            sum += rgb9e5_to_vec3_(c1.x) + rgb9e5_to_vec3_(c1.y) + rgb9e5_to_vec3_(c1.z) + rgb9e5_to_vec3_(c1.w);
+            sum += rgb9e5_to_vec3_(c2.x) + rgb9e5_to_vec3_(c2.y) + rgb9e5_to_vec3_(c2.z) + rgb9e5_to_vec3_(c2.w);
+        }
+    }
+    return sum / f32(tile.x * tile.y * 8u);
+}
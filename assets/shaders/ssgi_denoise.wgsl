@@ -0,0 +1,93 @@
+#import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
+#import bevy_pbr::view_transformations as vt
+#import ssgi::common as common
+#import ssgi::rgb9e5::{vec3_to_rgb9e5_, rgb9e5_to_vec3_}
+#import ssgi::xyz8e5::{vec3_to_xyz8e5_, xyz8e5_to_vec3_}
+#import bevy_pbr::mesh_view_bindings::view
+#import ssgi::sampling as sampling
+
+struct SSGIDenoiseConfig {
+    render_scale: u32,
+    step_size: u32,
+    normal_rejection: f32,
+    distance_rejection: f32,
+    luminance_rejection: f32,
+    _webgl2_padding_1: f32,
+    _webgl2_padding_2: f32,
+    _webgl2_padding_3: f32,
+}
+
+@group(0) @binding(102) var prepass_downsample_normals: texture_2d<f32>;
+@group(0) @binding(103) var prepass_downsample_depth: texture_2d<f32>;
+@group(0) @binding(105) var sh_texture: texture_2d<u32>;
+@group(0) @binding(109) var<uniform> config: SSGIDenoiseConfig;
+
+// B3 spline, the kernel of the à-trous wavelet transform: 1/16, 1/4, 3/8, 1/4, 1/16
+fn kernel_weight(i: i32) -> f32 {
+    return select(select(0.0625, 0.25, abs(i) == 1), 0.375, i == 0);
+}
+
+fn load_probe(icas_coord: vec2<i32>) -> common::ProbeGeometry {
+    return common::load_probe_geometry(icas_coord, 0u, config.render_scale, prepass_downsample_normals, prepass_downsample_depth);
+}
+
+@fragment
+fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<u32> {
+    let icas_coord = vec2<i32>(in.position.xy);
+    let sh_size = vec2<i32>(textureDimensions(sh_texture));
+
+    let center = load_probe(icas_coord);
+    let view_z = vt::position_world_to_view(center.world_position).z;
+    // limit minimum pixel radius for things really close to the camera
+    let pixel_radius = max(sampling::world_space_pixel_radius(-view_z), 0.001);
+    let center_luma = sampling::RGB_to_YCoCg(rgb9e5_to_vec3_(textureLoad(sh_texture, icas_coord, 0).x)).x;
+
+    var sh0 = vec3(0.0);
+    var sh1 = vec3(0.0);
+    var sh2 = vec3(0.0);
+    var sh3 = vec3(0.0);
+    var total_weight = 0.0;
+
+    let step_size = i32(config.step_size);
+    for (var y = -2; y <= 2; y += 1) {
+        for (var x = -2; x <= 2; x += 1) {
+            let offset = vec2(x, y) * step_size;
+            let tap_coord = icas_coord + offset;
+            if any(tap_coord < vec2(0)) || any(tap_coord >= sh_size) {
+                continue;
+            }
+
+            let c = textureLoad(sh_texture, tap_coord, 0);
+            let tap_sh0 = rgb9e5_to_vec3_(c.x);
+            let tap = load_probe(tap_coord);
+
+            var weight = kernel_weight(x) * kernel_weight(y);
+            weight *= pow(saturate(dot(center.normal, tap.normal)), config.normal_rejection);
+            // Distance from the plane of the center probe, relative to how far apart they are on
+            // screen, so slanted surfaces are still filtered
+            let plane_distance = abs(dot(center.normal, tap.world_position - center.world_position));
+            let screen_distance = max(length(vec2<f32>(offset)) * f32(config.render_scale), 1.0);
+            weight *= exp(-plane_distance / (pixel_radius * screen_distance) * config.distance_rejection);
+            let tap_luma = sampling::RGB_to_YCoCg(tap_sh0).x;
+            let luma_difference = abs(tap_luma - center_luma) / max(tap_luma + center_luma, sampling::F32_EPSILON);
+            weight *= exp(-luma_difference * config.luminance_rejection);
+
+            sh0 += tap_sh0 * weight;
+            sh1 += xyz8e5_to_vec3_(c.y) * weight;
+            sh2 += xyz8e5_to_vec3_(c.z) * weight;
+            sh3 += xyz8e5_to_vec3_(c.w) * weight;
+            total_weight += weight;
+        }
+    }
+
+    // The center tap always has a weight, unless it's underflowed
+    if total_weight <= 0.0 {
+        return textureLoad(sh_texture, icas_coord, 0);
+    }
+    sh0 /= total_weight;
+    sh1 /= total_weight;
+    sh2 /= total_weight;
+    sh3 /= total_weight;
+
+    return vec4(vec3_to_rgb9e5_(sh0), vec3_to_xyz8e5_(sh1), vec3_to_xyz8e5_(sh2), vec3_to_xyz8e5_(sh3));
+}
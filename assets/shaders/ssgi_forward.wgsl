@@ -0,0 +1,62 @@
+#import bevy_pbr::{
+    mesh_view_bindings::view,
+    pbr_functions::alpha_discard,
+    pbr_fragment::pbr_input_from_standard_material,
+}
+
+#ifdef PREPASS_PIPELINE
+#import bevy_pbr::{
+    prepass_io::{VertexOutput, FragmentOutput},
+    pbr_deferred_functions::deferred_output,
+}
+#else
+#import bevy_pbr::{
+    forward_io::{VertexOutput, FragmentOutput},
+    pbr_functions::{apply_pbr_lighting, main_pass_post_lighting_processing},
+    pbr_types::STANDARD_MATERIAL_FLAGS_UNLIT_BIT,
+}
+#endif
+
+// Resolved SSGI for the forward camera, copied out of SSGIResolveTextures each frame
+@group(2) @binding(100) var ssgi_indirect: texture_2d<f32>;
+
+@fragment
+fn fragment(
+    in: VertexOutput,
+    @builtin(front_facing) is_front: bool,
+) -> FragmentOutput {
+    var pbr_input = pbr_input_from_standard_material(in, is_front);
+
+    pbr_input.material.base_color = alpha_discard(pbr_input.material, pbr_input.material.base_color);
+
+#ifdef PREPASS_PIPELINE
+    let out = deferred_output(in, pbr_input);
+#else
+    var out: FragmentOutput;
+    if (pbr_input.material.flags & STANDARD_MATERIAL_FLAGS_UNLIT_BIT) == 0u {
+        out.color = apply_pbr_lighting(pbr_input);
+
+// ----------------------------------------------------
+// ----------------------------------------------------
+// ----------------------------------------------------
+        // Same diffuse color as the deferred lighting pass, metals have no diffuse
+        let diffuse_color = pbr_input.material.base_color.rgb * (1.0 - pbr_input.material.metallic);
+        let dims = vec2<i32>(textureDimensions(ssgi_indirect).xy) - 1;
+        // The indirect light is the size of the viewport
+        let iviewport_coord = vec2<i32>(in.position.xy - view.viewport.xy);
+        let indirect_light = textureLoad(ssgi_indirect, clamp(iviewport_coord, vec2(0), dims), 0).rgb;
+
+        out.color += vec4(diffuse_color * indirect_light, 0.0);
+// ----------------------------------------------------
+// ----------------------------------------------------
+// ----------------------------------------------------
+
+    } else {
+        out.color = pbr_input.material.base_color;
+    }
+
+    out.color = main_pass_post_lighting_processing(pbr_input, out.color);
+#endif
+
+    return out;
+}
@@ -15,9 +15,9 @@ struct SSGIGenerateSHConfig {
     directions: u32,
     render_scale: u32,
     cascade_count: u32,
-    hysteresis: f32,
+    min_history_length: f32,
+    max_history_length: f32,
     _webgl2_padding_1: f32,
-    _webgl2_padding_2: f32,
 }
 
 @group(0) @binding(101) var cascade_0_data1: texture_2d<u32>;
@@ -27,11 +27,22 @@ struct SSGIGenerateSHConfig {
 @group(0) @binding(104) var prepass_downsample_motion: texture_2d<f32>;
 @group(0) @binding(105) var prev_sh_texture: texture_2d<u32>;
 @group(0) @binding(106) var prev_pos_texture: texture_2d<f32>;
+#ifdef DISOCCLUSION
+@group(0) @binding(107) var disocclusion_texture: texture_2d<f32>;
+#endif
 @group(0) @binding(109) var<uniform> config: SSGIGenerateSHConfig;
+@group(0) @binding(110) var prev_history_length_texture: texture_2d<f32>;
+
+#ifndef HISTORY_CLAMP_NONE
+// Standard deviations around the mean the history is clipped to with HISTORY_CLAMP_VARIANCE. The
+// neighborhood is of single directions, which spread further than the neighboring probes.
+const HISTORY_CLAMP_GAMMA: f32 = 1.0;
+#endif
 
 struct FragmentOutput {
     @location(0) sh: vec4<u32>,
     @location(1) pos: vec4<f32>,
+    @location(2) history_length: f32,
 }
 
 @fragment
@@ -84,6 +95,11 @@ fn fragment(in: FullscreenVertexOutput) -> FragmentOutput {
 
     let phase_offset = common::get_phase_noise_offset(fdirections);
 
+#ifndef HISTORY_CLAMP_NONE
+    // The radiance gathered from each direction this frame, that the history is clamped to
+    var neighborhood = sampling::neighborhood_new();
+#endif
+
     for (var i = 0u; i < config.directions; i += 1u) {
         let phase = fract(f32(i) / fdirections);
         let phase_with_offset = fract(phase + phase_offset);
@@ -122,6 +138,17 @@ fn fragment(in: FullscreenVertexOutput) -> FragmentOutput {
         var gather7 = rgb9e5_to_vec3_(c2.z) * saturate(dot(N, dir7) * 8.0); 
         var gather8 = rgb9e5_to_vec3_(c2.w) * saturate(dot(N, dir8) * 8.0); 
 
+#ifndef HISTORY_CLAMP_NONE
+        sampling::neighborhood_add(&neighborhood, gather1);
+        sampling::neighborhood_add(&neighborhood, gather2);
+        sampling::neighborhood_add(&neighborhood, gather3);
+        sampling::neighborhood_add(&neighborhood, gather4);
+        sampling::neighborhood_add(&neighborhood, gather5);
+        sampling::neighborhood_add(&neighborhood, gather6);
+        sampling::neighborhood_add(&neighborhood, gather7);
+        sampling::neighborhood_add(&neighborhood, gather8);
+#endif
+
         sh0 += gather1;
         sh0 += gather2;
         sh0 += gather3;
@@ -225,15 +252,42 @@ fn fragment(in: FullscreenVertexOutput) -> FragmentOutput {
 //    }
 
     let prev_sh = textureLoad(prev_sh_texture, vec2<i32>(history_uv_no_jitter * sh_res) + closest_offset, 0);
+    let prev_history_length = textureLoad(prev_history_length_texture, vec2<i32>(history_uv_no_jitter * sh_res) + closest_offset, 0).x;
     
-    let prev_sh0 = rgb9e5_to_vec3_(prev_sh.x);
-    let prev_sh1 = xyz8e5_to_vec3_(prev_sh.y);
-    let prev_sh2 = xyz8e5_to_vec3_(prev_sh.z);
-    let prev_sh3 = xyz8e5_to_vec3_(prev_sh.w);
+    var prev_sh0 = rgb9e5_to_vec3_(prev_sh.x);
+    var prev_sh1 = xyz8e5_to_vec3_(prev_sh.y);
+    var prev_sh2 = xyz8e5_to_vec3_(prev_sh.z);
+    var prev_sh3 = xyz8e5_to_vec3_(prev_sh.w);
+
+#ifndef HISTORY_CLAMP_NONE
+    // sh0 is the 8 gathers of each direction summed, averaged over the directions, times AY0.
+    // Only it is clamped, in the units of the gathers.
+    let gather_scale = 8.0 * AY0;
+    var clamped_sh0 = prev_sh0 / gather_scale;
+#ifdef HISTORY_CLAMP_MIN_MAX
+    clamped_sh0 = sampling::neighborhood_clamp(neighborhood, clamped_sh0);
+#else ifdef HISTORY_CLAMP_VARIANCE
+    clamped_sh0 = sampling::neighborhood_clip_variance(neighborhood, clamped_sh0, HISTORY_CLAMP_GAMMA);
+#endif
+    clamped_sh0 *= gather_scale;
+    // The directional terms are scaled down with the luma of sh0, so the history keeps its direction
+    let prev_luma = sampling::RGB_to_YCoCg(prev_sh0).x;
+    let directional_scale = saturate(sampling::RGB_to_YCoCg(clamped_sh0).x / max(prev_luma, sampling::F32_EPSILON));
+    prev_sh0 = clamped_sh0;
+    prev_sh1 *= directional_scale;
+    prev_sh2 *= directional_scale;
+    prev_sh3 *= directional_scale;
+#endif // HISTORY_CLAMP_NONE
     
-    let reprojection_fail = f32(any(history_uv <= vec2(0.0)) || any(history_uv >= vec2(1.0)));
-
-    let hysteresis = mix(config.hysteresis, saturate(config.hysteresis + 0.4), reprojection_fail);
+    var reprojection_fail = f32(any(history_uv <= vec2(0.0)) || any(history_uv >= vec2(1.0)));
+#ifdef DISOCCLUSION
+    let disocclusion = textureLoad(disocclusion_texture, ifrag_coord_no_jitter, 0);
+    reprojection_fail = max(reprojection_fail, common::disocclusion_amount(disocclusion));
+#endif
+
+    let history_length = common::next_history_length(prev_history_length, reprojection_fail, config.min_history_length, config.max_history_length);
+    out.history_length = history_length;
+    let hysteresis = 1.0 / history_length;
 
     sh0 = mix(prev_sh0, sh0, hysteresis);
     sh1 = mix(prev_sh1, sh1, hysteresis);
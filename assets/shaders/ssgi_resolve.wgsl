@@ -8,7 +8,8 @@
 #import bevy_pbr::mesh_view_bindings::{globals, view}
 #import ssgi::sampling as sampling
 #import ssgi::sampling::TAU
-#import bevy_pbr::lighting::{specular, F_AB, Fd_Burley, perceptualRoughnessToRoughness}
+#import bevy_pbr::lighting::{F_AB, Fd_Burley, perceptualRoughnessToRoughness}
+#import bevy_pbr::pbr_deferred_types::unpack_unorm4x8_
 
 struct DisocclusionUniform {
     inverse_view_proj: mat4x4<f32>, // not jittered
@@ -26,11 +27,11 @@ struct SSGIResolveConfig {
     cascade_count: u32,
     distance_rejection: f32,
     normal_rejection: f32,
-    hysteresis: f32,
-    rough_specular: f32,
-    rough_specular_sharpness: f32,
-    _webgl2_padding_1: f32,
-    _webgl2_padding_2: f32,
+    min_history_length: f32,
+    max_history_length: f32,
+    specular: f32,
+    specular_samples: u32,
+    history_valid: u32,
 };
 
 @group(0) @binding(101) var cascade_0_data: texture_2d<u32>;
@@ -39,11 +40,32 @@ struct SSGIResolveConfig {
 @group(0) @binding(104) var prepass_downsample_motion: texture_2d<f32>;
 @group(0) @binding(105) var cascade_0_sh_data: texture_2d<u32>;
 @group(0) @binding(106) var prev_resolve: texture_2d<f32>;
-//@group(0) @binding(107) var disocclusion_texture: texture_2d<f32>;
+#ifdef DISOCCLUSION
+@group(0) @binding(107) var disocclusion_texture: texture_2d<f32>;
+#endif
 @group(0) @binding(108) var linear_sampler: sampler;
 @group(0) @binding(109) var<uniform> config: SSGIResolveConfig;
 @group(0) @binding(110) var pos_refl: texture_2d<f32>;
 //@group(0) @binding(111) var<uniform> duni: DisocclusionUniform;
+@group(0) @binding(112) var prev_specular_resolve: texture_2d<f32>;
+#ifdef DEFERRED_PREPASS
+@group(0) @binding(113) var deferred_prepass_texture: texture_2d<u32>;
+#endif
+
+struct FragmentOutput {
+    // Diffuse irradiance, multiplied by the diffuse color in the lighting pass. Alpha is the
+    // number of frames accumulated in the pixel, for its blend weight next frame.
+    @location(0) diffuse: vec4<f32>,
+    // GGX weighted radiance, multiplied by the env brdf from F0/roughness in the lighting pass
+    @location(1) specular: vec4<f32>,
+}
+
+struct Radiance {
+    diffuse: vec3<f32>,
+    specular: vec3<f32>,
+    // False if no probe matched the pixel
+    valid: bool,
+}
 
 /// Convert a ndc space position to world space
 // todo webgl
@@ -53,7 +75,7 @@ struct SSGIResolveConfig {
 //}
 
 @fragment
-fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {    
+fn fragment(in: FullscreenVertexOutput) -> FragmentOutput {    
     var frag_coord = vec4(in.position.xy, 0.0, 0.0);
     var ifrag_coord = vec2<i32>(frag_coord.xy);
 
@@ -63,15 +85,18 @@ fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
     let world_position = vt::position_ndc_to_world(vec3(vt::uv_to_ndc(in.uv), frag_coord.z));
 
     var out = vec4(0.0);
-    //let d = textureLoad(disocclusion_texture, vec2<i32>(frag_coord.xy), 0);
-    //let two_of_three = min(min(max(d.x, d.y), max(d.y, d.z)), max(d.x, d.z));
-    //let all_three = max(max(d.x, d.y), d.z);
     let closest_motion_vector = textureLoad(prepass_downsample_motion, ifrag_coord, 0).xy;
     let history_frag_coord = vec2<i32>((in.uv - closest_motion_vector) * view.viewport.zw);
     let history_uv = in.uv - closest_motion_vector;
-    let reprojection_fail = any(history_uv <= vec2(0.0)) || any(history_uv >= vec2(1.0)); //TODO webgl max( , saturate(two_of_three * 3.0))
-
-    out = vec4(read_cascade_radiance(world_position, N, frag_coord, select(history_uv, in.uv.xy, reprojection_fail)), 1.0);
+    let reprojection_fail = any(history_uv <= vec2(0.0)) || any(history_uv >= vec2(1.0));
+    var history_rejection = f32(reprojection_fail);
+#ifdef DISOCCLUSION
+    let disocclusion = textureLoad(disocclusion_texture, ifrag_coord, 0);
+    history_rejection = max(history_rejection, common::disocclusion_amount(disocclusion));
+#endif
+
+    var radiance = read_cascade_radiance(world_position, N, frag_coord, select(history_uv, in.uv.xy, reprojection_fail));
+    out = vec4(radiance.diffuse, 1.0);
     
     let frender_scale = f32(config.render_scale);
     let cas_coord = vec2(
@@ -132,14 +157,91 @@ fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
 
     //let prev_frame = textureSampleLevel(prev_resolve, linear_sampler, history_uv + vec2<f32>(closest_offset) / view.viewport.zw, 0.0);
     let prev_frame = texture_sample_bicubic_catmull_rom(prev_resolve, linear_sampler, history_uv, view.viewport.zw);
-    let hysteresis = mix(config.hysteresis, saturate(config.hysteresis + 0.4), f32(reprojection_fail));
-    let blend = mix(clamp(prev_frame.rgb, vec3(0.0), vec3(10000.0)), out.rgb, hysteresis);
-    out = vec4(blend, out.a);
+    let prev_specular = texture_sample_bicubic_catmull_rom(prev_specular_resolve, linear_sampler, history_uv, view.viewport.zw);
+    var history = clamp(prev_frame.rgb, vec3(0.0), vec3(10000.0));
+    var specular_history = clamp(prev_specular.rgb, vec3(0.0), vec3(10000.0));
+#ifdef UPSAMPLE_FALLBACK_PREVIOUS_RESOLVE
+    // No probe matches the pixel, it keeps what it had last frame instead
+    if !radiance.valid && history_rejection < 1.0 && config.history_valid != 0u {
+        radiance.diffuse = history;
+        radiance.specular = specular_history;
+        out = vec4(radiance.diffuse, 1.0);
+    }
+#endif
+#ifndef HISTORY_CLAMP_NONE
+    let R = reflect(-normalize(view.world_position.xyz - world_position), N);
+    let neighborhoods = history_neighborhoods(frag_coord.xy, N, R, radiance);
+#ifdef HISTORY_CLAMP_MIN_MAX
+    history = sampling::neighborhood_clamp(neighborhoods.diffuse, history);
+    specular_history = sampling::neighborhood_clamp(neighborhoods.specular, specular_history);
+#else ifdef HISTORY_CLAMP_VARIANCE
+    history = sampling::neighborhood_clip_variance(neighborhoods.diffuse, history, HISTORY_CLAMP_GAMMA);
+    specular_history = sampling::neighborhood_clip_variance(neighborhoods.specular, specular_history, HISTORY_CLAMP_GAMMA);
+#endif
+#endif // HISTORY_CLAMP_NONE
+
+    // The history length isn't a color, filtering it would blend the lengths of unrelated texels
+    // across edges, so only the rgb is resampled
+    let prev_history_length = textureLoad(prev_resolve, clamp(history_frag_coord, vec2(0), vec2<i32>(view.viewport.zw) - 1), 0).a;
+    let history_length = common::next_history_length(prev_history_length, history_rejection, config.min_history_length, config.max_history_length);
+    let hysteresis = 1.0 / history_length;
+    let blend = mix(history, out.rgb, hysteresis);
+    out = vec4(blend, history_length);
+
+    let specular_blend = mix(specular_history, radiance.specular, hysteresis);
+
+    var output: FragmentOutput;
+    output.diffuse = out;
+    output.specular = vec4(specular_blend, 1.0);
+    return output;
+}
 
-    return out;
+#ifndef HISTORY_CLAMP_NONE
+// Standard deviations around the mean the history is clipped to with HISTORY_CLAMP_VARIANCE
+const HISTORY_CLAMP_GAMMA: f32 = 1.25;
+
+struct HistoryNeighborhoods {
+    diffuse: sampling::Neighborhood,
+    specular: sampling::Neighborhood,
+}
+
+// The SH of the 3x3 probes closest to the pixel, evaluated in the normal and reflection directions.
+// The radiance of the pixel is included too, so the history can always reach the current frame.
+fn history_neighborhoods(frag_coord: vec2<f32>, N: vec3<f32>, R: vec3<f32>, radiance: Radiance) -> HistoryNeighborhoods {
+    var diffuse = sampling::neighborhood_new();
+    var specular = sampling::neighborhood_new();
+    sampling::neighborhood_add(&diffuse, radiance.diffuse);
+    sampling::neighborhood_add(&specular, radiance.specular);
+
+    let frender_scale = f32(config.render_scale);
+    let icas_coord = vec2<i32>(round((frag_coord - frender_scale * 0.5) / frender_scale));
+    let max_coord = vec2<i32>(textureDimensions(cascade_0_sh_data)) - 1;
+    for (var x = -1; x <= 1; x += 1) {
+        for (var y = -1; y <= 1; y += 1) {
+            let c = textureLoad(cascade_0_sh_data, clamp(icas_coord + vec2(x, y), vec2(0), max_coord), 0);
+            let sh0 = rgb9e5_to_vec3_(c.x);
+            let sh1 = xyz8e5_to_vec3_(c.y);
+            let sh2 = xyz8e5_to_vec3_(c.z);
+            let sh3 = xyz8e5_to_vec3_(c.w);
+            sampling::neighborhood_add(&diffuse, max(sh0 + sh1 * N.x + sh2 * N.y + sh3 * N.z, vec3(0.0)));
+            // Forward has no specular, the neighborhood is only the black pixel itself
+#ifdef DEFERRED_PREPASS
+            if config.specular > 0.0 {
+                let specular_radiance = max(sh0 + sh1 * R.x + sh2 * R.y + sh3 * R.z, vec3(0.0));
+                sampling::neighborhood_add(&specular, specular_radiance * config.specular);
+            }
+#endif
+        }
+    }
+
+    var neighborhoods: HistoryNeighborhoods;
+    neighborhoods.diffuse = diffuse;
+    neighborhoods.specular = specular;
+    return neighborhoods;
 }
+#endif // HISTORY_CLAMP_NONE
 
-fn read_cascade_radiance(world_position: vec3<f32>, N: vec3<f32>, frag_coord: vec4<f32>, history_uv: vec2<f32>) -> vec3<f32> {
+fn read_cascade_radiance(world_position: vec3<f32>, N: vec3<f32>, frag_coord: vec4<f32>, history_uv: vec2<f32>) -> Radiance {
     var ufrag_coord = vec2<u32>(frag_coord.xy);
     
     var pixel_radius = sampling::world_space_pixel_radius(-vt::depth_ndc_to_view_z(frag_coord.z));
@@ -148,10 +250,6 @@ fn read_cascade_radiance(world_position: vec3<f32>, N: vec3<f32>, frag_coord: ve
 
     let V = normalize(view.world_position.xyz - world_position);
     let R = reflect(-V, N);
-    let NdotV = max(dot(N, V), 0.0001);
-
-    // TODO Non-PBR
-    var fresnel = pow(clamp(1.0 - NdotV, 0.0, 1.0) * config.rough_specular, config.rough_specular_sharpness);
 
     let frender_scale = f32(config.render_scale);
 
@@ -161,59 +259,50 @@ fn read_cascade_radiance(world_position: vec3<f32>, N: vec3<f32>, frag_coord: ve
         (frag_coord.x - frender_scale * 0.5) / frender_scale,
         (frag_coord.y - frender_scale * 0.5) / frender_scale,
     );
-    let icas_coord = vec2<i32>(cas_coord);
-    let fd = abs(cas_coord - ceil(cas_coord));
-    let id = 1.0 - fd;
-
-    var aa = fd.x * fd.y;
-    var ba = id.x * fd.y;
-    var ab = fd.x * id.y;
-    var bb = id.x * id.y;
-
-    common::weight_bilinear(
-        &aa, &ba, &ab, &bb, 
-        N, world_position, frag_coord.z, 
-        config.normal_rejection, config.distance_rejection, 
-        icas_coord, 0u, config.render_scale, 
-        prepass_downsample_normals, prepass_downsample_depth,
-        pixel_radius,
-    );
 
-    var sh0 = vec3(0.0);
-    var sh1 = vec3(0.0);
-    var sh2 = vec3(0.0);
-    var sh3 = vec3(0.0);
-    var c1 = vec4(0u);
-
-    // TODO specular should be seperate so it's not multiplied by the albedo
-    c1 = textureLoad(cascade_0_sh_data, icas_coord + vec2(0, 0), 0);
-    sh0 = rgb9e5_to_vec3_(c1.x);
-    sh1 = xyz8e5_to_vec3_(c1.y);
-    sh2 = xyz8e5_to_vec3_(c1.z);
-    sh3 = xyz8e5_to_vec3_(c1.w);
-    out += (sh0 + sh1 * R.x + sh2 * R.y + sh3 * R.z) * aa * fresnel;
-    out += (sh0 + sh1 * N.x + sh2 * N.y + sh3 * N.z) * aa;
-    c1 = textureLoad(cascade_0_sh_data, icas_coord + vec2(1, 0), 0);
-    sh0 = rgb9e5_to_vec3_(c1.x);
-    sh1 = xyz8e5_to_vec3_(c1.y);
-    sh2 = xyz8e5_to_vec3_(c1.z);
-    sh3 = xyz8e5_to_vec3_(c1.w);
-    out += (sh0 + sh1 * R.x + sh2 * R.y + sh3 * R.z) * ba * fresnel;
-    out += (sh0 + sh1 * N.x + sh2 * N.y + sh3 * N.z) * ba;
-    c1 = textureLoad(cascade_0_sh_data, icas_coord + vec2(0, 1), 0);
-    sh0 = rgb9e5_to_vec3_(c1.x);
-    sh1 = xyz8e5_to_vec3_(c1.y);
-    sh2 = xyz8e5_to_vec3_(c1.z);
-    sh3 = xyz8e5_to_vec3_(c1.w);
-    out += (sh0 + sh1 * R.x + sh2 * R.y + sh3 * R.z) * ab * fresnel;
-    out += (sh0 + sh1 * N.x + sh2 * N.y + sh3 * N.z) * ab;
-    c1 = textureLoad(cascade_0_sh_data, icas_coord + vec2(1, 1), 0);
-    sh0 = rgb9e5_to_vec3_(c1.x);
-    sh1 = xyz8e5_to_vec3_(c1.y);
-    sh2 = xyz8e5_to_vec3_(c1.z);
-    sh3 = xyz8e5_to_vec3_(c1.w);
-    out += (sh0 + sh1 * R.x + sh2 * R.y + sh3 * R.z) * bb * fresnel;
-    out += (sh0 + sh1 * N.x + sh2 * N.y + sh3 * N.z) * bb;
+    // Interpolate the SH coefficients first, so they can be evaluated for any direction
+    let upsampled = upsample_sh(N, world_position, cas_coord, pixel_radius);
+    let sh0 = upsampled.sh.sh0;
+    let sh1 = upsampled.sh.sh1;
+    let sh2 = upsampled.sh.sh2;
+    let sh3 = upsampled.sh.sh3;
+
+    out = sh0 + sh1 * N.x + sh2 * N.y + sh3 * N.z;
+
+    var specular_radiance = vec3(0.0);
+#ifdef DEFERRED_PREPASS
+    if config.specular > 0.0 {
+        let deferred_data = textureLoad(deferred_prepass_texture, vec2<i32>(frag_coord.xy), 0);
+        let perceptual_roughness = unpack_unorm4x8_(deferred_data.r).a;
+        let roughness = perceptualRoughnessToRoughness(perceptual_roughness);
+
+        // Sample the GGX lobe in tangent space, with F0 of 1.0 so only the lobe shape is weighted.
+        // The lighting pass applies fresnel from the actual F0.
+        let basis = sampling::build_orthonormal_basis(N);
+        let wo = basis * V;
+        let white_frame_noise = sampling::white_frame_noise(789u);
+        let frame = globals.frame_count % #{NOISE_FRAME_PERIOD}u;
+        var weight = 0.0;
+        for (var i = 0u; i < config.specular_samples; i += 1u) {
+            let urand = vec2(
+                fract(sampling::blue_noise_for_pixel(ufrag_coord, frame + i) + white_frame_noise.x),
+                fract(sampling::blue_noise_for_pixel(ufrag_coord, frame + i + config.specular_samples) + white_frame_noise.y),
+            );
+            let brdf = sampling::brdf_sample(roughness, vec3(1.0), wo, urand);
+            let w = brdf.value_over_pdf.x;
+            let wi = brdf.wi * basis;
+            specular_radiance += max(sh0 + sh1 * wi.x + sh2 * wi.y + sh3 * wi.z, vec3(0.0)) * w;
+            weight += w;
+        }
+        if weight > 0.0 {
+            specular_radiance /= weight;
+        } else {
+            // All samples were below the horizon, use the mirror direction
+            specular_radiance = max(sh0 + sh1 * R.x + sh2 * R.y + sh3 * R.z, vec3(0.0));
+        }
+        specular_radiance *= config.specular;
+    }
+#endif // DEFERRED_PREPASS
     //out = xyz8e5_to_vec3_(textureLoad(cascade_0_sh_data, icas_coord + vec2(0, 0), 0).x);
 
     // For spec - Looks Blocky
@@ -254,7 +343,121 @@ fn read_cascade_radiance(world_position: vec3<f32>, N: vec3<f32>, frag_coord: ve
     //s1 = rgb9e5_to_vec3_(bitcast<u32>(textureLoad(pos_refl, icas_coord + vec2(1, 1), 0).w));
     //out += s1 * bb * 1.0;
 
-    return clamp(out, vec3(0.0), vec3(10000.0));
+    var radiance: Radiance;
+    radiance.diffuse = clamp(out, vec3(0.0), vec3(10000.0));
+    radiance.specular = clamp(specular_radiance, vec3(0.0), vec3(10000.0));
+    radiance.valid = upsampled.valid;
+    return radiance;
+}
+
+#ifdef UPSAMPLE_KERNEL_3X3
+// Half width of the tent in probes, the probes closer than this along both axes are interpolated
+const UPSAMPLE_RADIUS: f32 = 1.5;
+#else ifdef UPSAMPLE_KERNEL_4X4
+const UPSAMPLE_RADIUS: f32 = 2.0;
+#else
+const UPSAMPLE_RADIUS: f32 = 1.0;
+#endif
+// Probes around the closest one that are searched when all the probes of the kernel are rejected
+const UPSAMPLE_FALLBACK_RADIUS: i32 = 2;
+// Weight a probe needs to count as matching the pixel, relative to its weight in the kernel
+const UPSAMPLE_MIN_WEIGHT: f32 = 0.0001;
+
+struct ProbeSH {
+    sh0: vec3<f32>,
+    sh1: vec3<f32>,
+    sh2: vec3<f32>,
+    sh3: vec3<f32>,
+}
+
+struct UpsampledSH {
+    sh: ProbeSH,
+    // False if no probe matched the pixel, and the SH is of the best of the rejected ones
+    valid: bool,
+}
+
+fn load_probe_sh(icas_coord: vec2<i32>) -> ProbeSH {
+    let c = textureLoad(cascade_0_sh_data, icas_coord, 0);
+    var sh: ProbeSH;
+    sh.sh0 = rgb9e5_to_vec3_(c.x);
+    sh.sh1 = xyz8e5_to_vec3_(c.y);
+    sh.sh2 = xyz8e5_to_vec3_(c.z);
+    sh.sh3 = xyz8e5_to_vec3_(c.w);
+    return sh;
+}
+
+// Joint bilateral upsampling of the probes around the pixel, weighted by a tent on screen and how
+// well each probe matches the surface of the pixel
+fn upsample_sh(N: vec3<f32>, world_position: vec3<f32>, cas_coord: vec2<f32>, pixel_radius: f32) -> UpsampledSH {
+    let cas_size = vec2<i32>(textureDimensions(cascade_0_sh_data));
+    var out: UpsampledSH;
+    var total_weight = 0.0;
+    var kernel_weight = 0.0;
+
+    let first = vec2<i32>(floor(cas_coord - UPSAMPLE_RADIUS)) + 1;
+    let last = vec2<i32>(floor(cas_coord + UPSAMPLE_RADIUS));
+    for (var y = first.y; y <= last.y; y += 1) {
+        for (var x = first.x; x <= last.x; x += 1) {
+            let probe_coord = vec2(x, y);
+            if any(probe_coord < vec2(0)) || any(probe_coord >= cas_size) {
+                continue;
+            }
+            let tent = saturate(1.0 - abs(vec2<f32>(probe_coord) - cas_coord) / UPSAMPLE_RADIUS);
+            let spatial_weight = tent.x * tent.y;
+            let probe = common::load_probe_geometry(probe_coord, 0u, config.render_scale, prepass_downsample_normals, prepass_downsample_depth);
+            let weight = spatial_weight * common::probe_weight(probe, N, world_position, config.normal_rejection, config.distance_rejection, pixel_radius);
+
+            let sh = load_probe_sh(probe_coord);
+            out.sh.sh0 += sh.sh0 * weight;
+            out.sh.sh1 += sh.sh1 * weight;
+            out.sh.sh2 += sh.sh2 * weight;
+            out.sh.sh3 += sh.sh3 * weight;
+            total_weight += weight;
+            kernel_weight += spatial_weight;
+        }
+    }
+
+    if total_weight > UPSAMPLE_MIN_WEIGHT * kernel_weight && total_weight > 0.0 {
+        out.sh.sh0 /= total_weight;
+        out.sh.sh1 /= total_weight;
+        out.sh.sh2 /= total_weight;
+        out.sh.sh3 /= total_weight;
+        out.valid = true;
+        return out;
+    }
+
+    // All the probes were rejected. Use the closest probe in a wider footprint that does match
+    // the pixel, or the best of the ones that don't.
+    let closest = vec2<i32>(round(cas_coord));
+    var nearest_coord = vec2(-1);
+    var nearest_distance = sampling::F32_MAX;
+    var best_coord = clamp(closest, vec2(0), cas_size - 1);
+    var best_score = -1.0;
+    for (var y = -UPSAMPLE_FALLBACK_RADIUS; y <= UPSAMPLE_FALLBACK_RADIUS; y += 1) {
+        for (var x = -UPSAMPLE_FALLBACK_RADIUS; x <= UPSAMPLE_FALLBACK_RADIUS; x += 1) {
+            let probe_coord = closest + vec2(x, y);
+            if any(probe_coord < vec2(0)) || any(probe_coord >= cas_size) {
+                continue;
+            }
+            let probe = common::load_probe_geometry(probe_coord, 0u, config.render_scale, prepass_downsample_normals, prepass_downsample_depth);
+            let weight = common::probe_weight(probe, N, world_position, config.normal_rejection, config.distance_rejection, pixel_radius);
+            let screen_distance = length(vec2<f32>(probe_coord) - cas_coord);
+            if weight > UPSAMPLE_MIN_WEIGHT && screen_distance < nearest_distance {
+                nearest_distance = screen_distance;
+                nearest_coord = probe_coord;
+            }
+            // Facing the same way, and close in world space
+            let score = saturate(dot(N, probe.normal)) / (1.0 + distance(probe.world_position, world_position) / pixel_radius);
+            if score > best_score {
+                best_score = score;
+                best_coord = probe_coord;
+            }
+        }
+    }
+
+    out.valid = nearest_coord.x >= 0;
+    out.sh = load_probe_sh(select(best_coord, nearest_coord, out.valid));
+    return out;
 }
 
 // https://github.com/google/filament/blob/v1.49.1/filament/src/materials/antiAliasing/taa.mat#L147
@@ -16,7 +16,10 @@ use bevy_basic_camera::{CameraController, CameraControllerPlugin};
 use bevy_inspector_egui::quick::FilterQueryInspectorPlugin;
 use bevy_mod_mipmap_generator::{MipmapGeneratorPlugin, MipmapGeneratorSettings};
 use bevy_mod_taa::{TAABundle, TAAPlugin};
-use bevy_ridiculous_ssgi::{ssgi::SSGIPass, SSGIBundle, SSGIPlugin};
+use bevy_ridiculous_ssgi::{
+    ssgi::{SSGIPass, SSGIThickness},
+    SSGIBundle, SSGIPlugin,
+};
 
 fn main() {
     App::new()
@@ -120,7 +123,14 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
                 mouse_key_enable_mouse: MouseButton::Right,
                 ..default()
             },
-            SSGIBundle::default(),
+            SSGIBundle {
+                ssgi_pass: SSGIPass {
+                    // So the poles and railings don't occlude everything behind them
+                    thickness: SSGIThickness::Constant(0.5),
+                    ..default()
+                },
+                ..default()
+            },
         ))
         .insert(TAABundle::sample8());
 }
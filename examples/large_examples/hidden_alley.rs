@@ -136,6 +136,7 @@ pub fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
             SSGIBundle {
                 ssgi_pass: SSGIPass {
                     brightness: 5.0,
+                    environment_fallback: 1.0,
                     ..default()
                 },
                 ..default()
@@ -0,0 +1,203 @@
+//! Two SSGI cameras rendering to different viewports of the window, and a third rendering to a
+//! texture that's shown on a quad in the scene. Each view keeps its own history.
+//! Space toggles the right camera, to check its history is still valid when it's rendered again.
+
+use bevy::{
+    diagnostic::{FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin},
+    math::vec3,
+    pbr::{DefaultOpaqueRendererMethod, PbrPlugin},
+    prelude::*,
+    render::{
+        camera::{ClearColorConfig, Exposure, RenderTarget, Viewport},
+        render_resource::{
+            Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
+        },
+    },
+    window::{PresentMode, WindowResized},
+};
+use bevy_mod_taa::{TAABundle, TAAPlugin};
+use bevy_ridiculous_ssgi::{SSGIBundle, SSGIPlugin};
+
+#[derive(Component)]
+struct LeftCamera;
+
+#[derive(Component)]
+struct RightCamera;
+
+fn main() {
+    App::new()
+        .insert_resource(Msaa::Off)
+        .insert_resource(DefaultOpaqueRendererMethod::deferred())
+        .insert_resource(ClearColor(Color::BLACK))
+        .insert_resource(AmbientLight {
+            color: Color::rgb(1.0, 1.0, 1.0),
+            brightness: 0.0,
+        })
+        .add_plugins((
+            DefaultPlugins
+                .set(PbrPlugin {
+                    add_default_deferred_lighting_plugin: false,
+                    ..default()
+                })
+                .set(WindowPlugin {
+                    primary_window: Some(Window {
+                        present_mode: PresentMode::AutoNoVsync,
+                        ..default()
+                    }),
+                    ..default()
+                }),
+            TAAPlugin,
+            SSGIPlugin,
+            LogDiagnosticsPlugin::default(),
+            FrameTimeDiagnosticsPlugin::default(),
+        ))
+        .add_systems(Startup, setup)
+        .add_systems(Update, (set_camera_viewports, toggle_right_camera))
+        .run();
+}
+
+fn setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut images: ResMut<Assets<Image>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.spawn(SceneBundle {
+        scene: asset_server.load("models/cornell_box.glb#Scene0"),
+        ..default()
+    });
+
+    let projection = Projection::Perspective(PerspectiveProjection {
+        fov: std::f32::consts::PI / 4.0,
+        near: 0.1,
+        far: 1000.0,
+        aspect_ratio: 1.0,
+    });
+
+    // Left and right half of the window, the viewports are set in set_camera_viewports
+    commands.spawn((
+        Camera3dBundle {
+            camera: Camera {
+                hdr: true,
+                order: 0,
+                ..default()
+            },
+            transform: Transform::from_xyz(-0.6, 1.0, 4.6).looking_at(vec3(0.0, 1.0, 0.0), Vec3::Y),
+            projection: projection.clone(),
+            exposure: Exposure { ev100: 0.0 },
+            ..default()
+        },
+        SSGIBundle::default(),
+        TAABundle::sample8(),
+        LeftCamera,
+    ));
+    commands.spawn((
+        Camera3dBundle {
+            camera: Camera {
+                hdr: true,
+                order: 1,
+                // Don't clear the left half of the window
+                clear_color: ClearColorConfig::None,
+                ..default()
+            },
+            transform: Transform::from_xyz(0.6, 1.0, 4.6).looking_at(vec3(0.0, 1.0, 0.0), Vec3::Y),
+            projection: projection.clone(),
+            exposure: Exposure { ev100: 0.0 },
+            ..default()
+        },
+        SSGIBundle::default(),
+        TAABundle::sample8(),
+        RightCamera,
+    ));
+
+    // Render to texture, shown on a quad in front of the box
+    let size = Extent3d {
+        width: 512,
+        height: 512,
+        ..default()
+    };
+    let mut image = Image {
+        texture_descriptor: TextureDescriptor {
+            label: None,
+            size,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Bgra8UnormSrgb,
+            mip_level_count: 1,
+            sample_count: 1,
+            usage: TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_DST
+                | TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        },
+        ..default()
+    };
+    image.resize(size);
+    let image_handle = images.add(image);
+
+    commands.spawn((
+        Camera3dBundle {
+            camera: Camera {
+                hdr: true,
+                // Render before the window cameras, so they show this frame's texture
+                order: -1,
+                target: RenderTarget::Image(image_handle.clone()),
+                ..default()
+            },
+            transform: Transform::from_xyz(0.0, 1.8, 2.0).looking_at(vec3(0.0, 0.6, 0.0), Vec3::Y),
+            projection,
+            exposure: Exposure { ev100: 0.0 },
+            ..default()
+        },
+        SSGIBundle::default(),
+        TAABundle::sample8(),
+    ));
+
+    commands.spawn(PbrBundle {
+        mesh: meshes.add(Rectangle::new(0.4, 0.4)),
+        material: materials.add(StandardMaterial {
+            base_color_texture: Some(image_handle),
+            unlit: true,
+            ..default()
+        }),
+        transform: Transform::from_xyz(0.0, 0.4, 1.4),
+        ..default()
+    });
+}
+
+fn set_camera_viewports(
+    windows: Query<&Window>,
+    mut resize_events: EventReader<WindowResized>,
+    mut left_camera: Query<&mut Camera, (With<LeftCamera>, Without<RightCamera>)>,
+    mut right_camera: Query<&mut Camera, With<RightCamera>>,
+) {
+    for resize_event in resize_events.read() {
+        let Ok(window) = windows.get(resize_event.window) else {
+            continue;
+        };
+        let size = UVec2::new(
+            window.resolution.physical_width() / 2,
+            window.resolution.physical_height(),
+        );
+        left_camera.single_mut().viewport = Some(Viewport {
+            physical_position: UVec2::ZERO,
+            physical_size: size,
+            ..default()
+        });
+        right_camera.single_mut().viewport = Some(Viewport {
+            physical_position: UVec2::new(size.x, 0),
+            physical_size: size,
+            ..default()
+        });
+    }
+}
+
+fn toggle_right_camera(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut right_camera: Query<&mut Camera, With<RightCamera>>,
+) {
+    if keys.just_pressed(KeyCode::Space) {
+        let mut camera = right_camera.single_mut();
+        camera.is_active = !camera.is_active;
+    }
+}
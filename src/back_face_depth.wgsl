@@ -0,0 +1,28 @@
+// Depth only pass of the back faces of meshes, the far side of each occluder for the SSGI march.
+// Uses the vertex layout and bind groups of the mesh pipeline, but only reads the positions.
+
+#import bevy_pbr::{
+    mesh_functions,
+    skinning,
+    view_transformations::position_world_to_clip,
+}
+
+struct Vertex {
+    @builtin(instance_index) instance_index: u32,
+    @location(0) position: vec3<f32>,
+#ifdef SKINNED
+    @location(6) joint_indices: vec4<u32>,
+    @location(7) joint_weights: vec4<f32>,
+#endif
+}
+
+@vertex
+fn vertex(vertex: Vertex) -> @builtin(position) vec4<f32> {
+#ifdef SKINNED
+    let model = skinning::skin_model(vertex.joint_indices, vertex.joint_weights);
+#else
+    let model = mesh_functions::get_model_matrix(vertex.instance_index);
+#endif
+    let world_position = mesh_functions::mesh_position_local_to_world(model, vec4(vertex.position, 1.0));
+    return position_world_to_clip(world_position.xyz);
+}
@@ -1,15 +1,22 @@
-use bevy::ecs::world::World;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use bevy::ecs::entity::Entity;
+use bevy::ecs::system::Resource;
+use bevy::ecs::world::{FromWorld, World};
 use bevy::pbr::{GpuLights, LightMeta};
 
 use bevy::render::globals::{GlobalsBuffer, GlobalsUniform};
 use bevy::render::render_resource::encase::internal::WriteInto;
 use bevy::render::render_resource::{
-    self, BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferBindingType,
-    BufferInitDescriptor, BufferUsages, FilterMode, Sampler, SamplerBindingType, SamplerDescriptor,
-    ShaderStages, ShaderType, TextureSampleType, TextureViewDimension,
+    self, BindGroup, BindGroupEntry, BindGroupLayout, BindGroupLayoutEntry, BindingResource,
+    BindingType, BufferBindingType, DynamicUniformBuffer, FilterMode, Sampler, SamplerBindingType,
+    SamplerDescriptor, ShaderStages, ShaderType, StorageTextureAccess, TextureFormat,
+    TextureSampleType, TextureViewDimension,
 };
-use bevy::render::renderer::{RenderContext, RenderDevice};
+use bevy::render::renderer::{RenderDevice, RenderQueue};
 use bevy::render::view::{ViewUniform, ViewUniforms};
+use bevy::utils::HashMap;
 
 pub fn fsampler_layout_entry(binding: u32) -> BindGroupLayoutEntry {
     BindGroupLayoutEntry {
@@ -71,6 +78,19 @@ pub fn stexture_layout_entry(binding: u32, dim: TextureViewDimension) -> BindGro
     texture_layout_entry(binding, dim, TextureSampleType::Sint)
 }
 
+pub fn storage_texture_layout_entry(binding: u32, format: TextureFormat) -> BindGroupLayoutEntry {
+    BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::COMPUTE,
+        ty: BindingType::StorageTexture {
+            access: StorageTextureAccess::WriteOnly,
+            format,
+            view_dimension: TextureViewDimension::D2,
+        },
+        count: None,
+    }
+}
+
 pub fn view_layout_entry(binding: u32) -> BindGroupLayoutEntry {
     BindGroupLayoutEntry {
         binding,
@@ -123,6 +143,22 @@ pub fn uniform_layout_entry(binding: u32, min_size: std::num::NonZeroU64) -> Bin
     }
 }
 
+pub fn dynamic_uniform_layout_entry(
+    binding: u32,
+    min_size: std::num::NonZeroU64,
+) -> BindGroupLayoutEntry {
+    BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::VERTEX_FRAGMENT,
+        ty: BindingType::Buffer {
+            ty: render_resource::BufferBindingType::Uniform,
+            has_dynamic_offset: true,
+            min_binding_size: Some(min_size),
+        },
+        count: None,
+    }
+}
+
 #[macro_export]
 macro_rules! resource {
     ($world:expr, $resource_type:ty) => {
@@ -185,20 +221,162 @@ macro_rules! shader_def_uint {
     };
 }
 
-pub fn uniform_buffer<T>(data: T, render_context: &mut RenderContext, label: &str) -> Buffer
-where
-    T: ShaderType + WriteInto,
-{
-    let mut buffer = render_resource::encase::UniformBuffer::new(Vec::new());
-    buffer.write(&data).unwrap();
-
-    let config_uniform =
-        render_context
-            .render_device()
-            .create_buffer_with_data(&BufferInitDescriptor {
-                label: Some(label),
-                contents: buffer.as_ref(),
-                usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
-            });
-    config_uniform
+/// A uniform that has a matching struct in WGSL, implemented by [`wgsl_uniform!`]. The layouts are
+/// checked against each other in `tests/uniform_layout.rs`.
+pub trait WgslUniform: ShaderType {
+    /// Name of the WGSL struct
+    const WGSL_STRUCT: &'static str;
+    /// Name and byte offset of each field, in declaration order
+    fn field_offsets() -> Vec<(&'static str, u64)>;
+}
+
+/// Declares a uniform struct and implements [`WgslUniform`] for it. The struct needs to derive `ShaderType`.
+#[macro_export]
+macro_rules! wgsl_uniform {
+    (
+        $(#[$attr:meta])*
+        $vis:vis struct $name:ident {
+            $($field:ident: $field_ty:ty),* $(,)?
+        }
+    ) => {
+        $(#[$attr])*
+        $vis struct $name {
+            $($field: $field_ty),*
+        }
+
+        impl $crate::bind_group_utils::WgslUniform for $name {
+            const WGSL_STRUCT: &'static str = stringify!($name);
+
+            fn field_offsets() -> Vec<(&'static str, u64)> {
+                use bevy::render::render_resource::ShaderType;
+                [$(stringify!($field)),*]
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, name)| (name, <Self as ShaderType>::METADATA.offset(i)))
+                    .collect()
+            }
+        }
+    };
+}
+
+/// Samplers shared by the SSGI passes, created once instead of every frame
+#[derive(Resource)]
+pub struct SSGISamplers {
+    pub nearest: Sampler,
+    pub linear: Sampler,
+}
+
+impl FromWorld for SSGISamplers {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        SSGISamplers {
+            nearest: nearest_sampler(render_device),
+            linear: linear_sampler(render_device),
+        }
+    }
+}
+
+/// A bind group that's only recreated when the layout or any of the resources bound to it change.
+/// Keeps the last two, so ping-ponged history textures don't cause a new bind group every frame.
+#[derive(Default)]
+pub struct CachedBindGroup {
+    bind_groups: Vec<(Option<u64>, BindGroup)>,
+}
+
+impl CachedBindGroup {
+    pub fn update(
+        &mut self,
+        render_device: &RenderDevice,
+        label: &str,
+        layout: &BindGroupLayout,
+        entries: &[BindGroupEntry],
+    ) {
+        let key = bind_group_key(layout, entries);
+        if let Some(i) = self
+            .bind_groups
+            .iter()
+            .position(|(cached_key, _)| key.is_some() && *cached_key == key)
+        {
+            self.bind_groups.swap(0, i);
+        } else {
+            let bind_group = render_device.create_bind_group(label, layout, entries);
+            self.bind_groups.insert(0, (key, bind_group));
+            self.bind_groups.truncate(2);
+        }
+    }
+
+    pub fn get(&self) -> Option<&BindGroup> {
+        self.bind_groups.first().map(|(_, bind_group)| bind_group)
+    }
+}
+
+/// The uniforms and bind groups of a view. Kept across frames in [`PerViewBindGroups`], since
+/// render world entities are despawned every frame.
+pub struct ViewBindGroups<T: ShaderType> {
+    pub uniforms: DynamicUniformBuffer<T>,
+    /// Dynamic offset of each uniform written with [`ViewBindGroups::write_uniforms`]
+    pub offsets: Vec<u32>,
+    pub bind_groups: Vec<CachedBindGroup>,
+}
+
+impl<T: ShaderType> Default for ViewBindGroups<T> {
+    fn default() -> Self {
+        ViewBindGroups {
+            uniforms: DynamicUniformBuffer::default(),
+            offsets: Vec::new(),
+            bind_groups: Vec::new(),
+        }
+    }
+}
+
+impl<T: ShaderType> ViewBindGroups<T> {
+    pub fn bind_group(&self, n: usize) -> Option<&BindGroup> {
+        self.bind_groups.get(n)?.get()
+    }
+}
+
+impl<T: ShaderType + WriteInto> ViewBindGroups<T> {
+    /// Replaces this frame's uniforms. The buffer is only reallocated if it needs to grow.
+    pub fn write_uniforms(
+        &mut self,
+        render_device: &RenderDevice,
+        render_queue: &RenderQueue,
+        uniforms: impl IntoIterator<Item = T>,
+    ) {
+        self.uniforms.clear();
+        self.offsets.clear();
+        for uniform in uniforms {
+            self.offsets.push(self.uniforms.push(&uniform));
+        }
+        self.uniforms.write_buffer(render_device, render_queue);
+    }
+}
+
+#[derive(Resource)]
+pub struct PerViewBindGroups<T: ShaderType>(pub HashMap<Entity, ViewBindGroups<T>>);
+
+impl<T: ShaderType> Default for PerViewBindGroups<T> {
+    fn default() -> Self {
+        PerViewBindGroups(HashMap::default())
+    }
+}
+
+/// Hash of the ids of everything bound, None if there are bindings that can't be identified
+fn bind_group_key(layout: &BindGroupLayout, entries: &[BindGroupEntry]) -> Option<u64> {
+    let mut hasher = DefaultHasher::new();
+    layout.id().hash(&mut hasher);
+    for entry in entries {
+        entry.binding.hash(&mut hasher);
+        match &entry.resource {
+            BindingResource::Buffer(binding) => {
+                binding.buffer.global_id().hash(&mut hasher);
+                binding.offset.hash(&mut hasher);
+                binding.size.hash(&mut hasher);
+            }
+            BindingResource::TextureView(view) => view.global_id().hash(&mut hasher),
+            BindingResource::Sampler(sampler) => sampler.global_id().hash(&mut hasher),
+            _ => return None,
+        }
+    }
+    Some(hasher.finish())
 }
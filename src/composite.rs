@@ -0,0 +1,260 @@
+use bevy::{
+    asset::load_internal_asset,
+    core_pipeline::{
+        core_3d::graph::{Core3d, Node3d},
+        fullscreen_vertex_shader::fullscreen_shader_vertex_state,
+        prepass::ViewPrepassTextures,
+    },
+    ecs::query::QueryItem,
+    prelude::*,
+    render::{
+        camera::ExtractedCamera,
+        render_graph::{
+            NodeRunError, RenderGraphApp, RenderGraphContext, RenderLabel, ViewNode, ViewNodeRunner,
+        },
+        render_resource::{
+            BindGroupEntries, BindGroupLayout, BlendComponent, BlendFactor, BlendOperation,
+            BlendState, CachedRenderPipelineId, ColorTargetState, ColorWrites, FragmentState,
+            MultisampleState, PipelineCache, PrimitiveState, RenderPassDescriptor,
+            RenderPipelineDescriptor, TextureViewDimension,
+        },
+        renderer::{RenderContext, RenderDevice},
+        view::{ViewTarget, ViewUniformOffset, ViewUniforms},
+        Render, RenderApp, RenderSet,
+    },
+    utils::{warn_once, HashMap},
+};
+
+use crate::{
+    bind_group_utils::{
+        ftexture_layout_entry, utexture_layout_entry, view_layout_entry, CachedBindGroup,
+    },
+    copy_frame::FrameCopyLabel,
+    prepass_downsample::PrepassDownsampleTextures,
+    ssgi_resolve::SSGIResolveTextures,
+};
+
+const SHADER_HANDLE: Handle<Shader> = Handle::weak_from_u128(740958237409582374);
+
+/// Adds the resolved SSGI on top of the output of Bevy's default deferred lighting pass.
+/// Used instead of [`crate::lighting_pass::CustomDeferredPbrLightingPlugin`] when
+/// [`bevy::pbr::deferred::DeferredPbrLightingPlugin`] is already added.
+/// Only HDR cameras are supported, since the SSGI has to be added before tonemapping.
+pub struct SSGICompositePlugin;
+impl Plugin for SSGICompositePlugin {
+    fn build(&self, app: &mut App) {
+        load_internal_asset!(
+            app,
+            SHADER_HANDLE,
+            "../assets/shaders/ssgi_composite.wgsl",
+            Shader::from_wgsl
+        );
+
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .init_resource::<SSGICompositeBindGroups>()
+            .add_systems(
+                Render,
+                prepare_bind_groups.in_set(RenderSet::PrepareBindGroups),
+            )
+            .add_render_graph_node::<ViewNodeRunner<SSGICompositeNode>>(Core3d, SSGICompositeLabel)
+            .add_render_graph_edges(
+                Core3d,
+                (Node3d::MainOpaquePass, SSGICompositeLabel, FrameCopyLabel),
+            );
+    }
+
+    fn finish(&self, app: &mut App) {
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app.init_resource::<SSGICompositePipeline>();
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
+pub struct SSGICompositeLabel;
+
+#[derive(Default)]
+pub struct SSGICompositeNode;
+
+impl ViewNode for SSGICompositeNode {
+    type ViewQuery = (
+        &'static ExtractedCamera,
+        &'static ViewUniformOffset,
+        &'static ViewTarget,
+    );
+
+    fn run(
+        &self,
+        graph_context: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        (camera, view_uniform_offset, view_target): QueryItem<Self::ViewQuery>,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let Some(bind_group) = world
+            .resource::<SSGICompositeBindGroups>()
+            .0
+            .get(&graph_context.view_entity())
+            .and_then(CachedBindGroup::get)
+        else {
+            return Ok(());
+        };
+
+        let composite_pipeline = world.resource::<SSGICompositePipeline>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+
+        let Some(pipeline) = pipeline_cache.get_render_pipeline(composite_pipeline.pipeline_id)
+        else {
+            return Ok(());
+        };
+
+        let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+            label: Some("ssgi_composite_pass"),
+            color_attachments: &[Some(view_target.get_color_attachment())],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        if let Some(viewport) = camera.viewport.as_ref() {
+            render_pass.set_camera_viewport(viewport);
+        }
+        render_pass.set_render_pipeline(pipeline);
+        render_pass.set_bind_group(0, bind_group, &[view_uniform_offset.offset]);
+        render_pass.draw(0..3, 0..1);
+
+        Ok(())
+    }
+}
+
+/// Kept across frames, since render world entities are despawned every frame
+#[derive(Resource, Default)]
+struct SSGICompositeBindGroups(HashMap<Entity, CachedBindGroup>);
+
+#[allow(clippy::type_complexity)]
+fn prepare_bind_groups(
+    render_device: Res<RenderDevice>,
+    pipeline: Res<SSGICompositePipeline>,
+    view_uniforms: Res<ViewUniforms>,
+    mut bind_groups: ResMut<SSGICompositeBindGroups>,
+    views: Query<(
+        Entity,
+        &ViewTarget,
+        &ViewPrepassTextures,
+        &PrepassDownsampleTextures,
+        &SSGIResolveTextures,
+    )>,
+) {
+    bind_groups.0.retain(|entity, _| views.contains(*entity));
+
+    let Some(view_binding) = view_uniforms.uniforms.binding() else {
+        return;
+    };
+
+    for (entity, view_target, prepass_textures, prepass_downsample_texture, ssgi_resolve) in &views
+    {
+        let Some(deferred_texture) = &prepass_textures.deferred else {
+            continue;
+        };
+        if !view_target.is_hdr() {
+            warn_once!(
+                "SSGI is only composited on top of Bevy's deferred lighting for HDR cameras, \
+                {entity:?} won't have SSGI. Set Camera::hdr to true."
+            );
+            bind_groups.0.remove(&entity);
+            continue;
+        }
+
+        bind_groups.0.entry(entity).or_default().update(
+            &render_device,
+            "ssgi_composite_bind_group",
+            &pipeline.layout,
+            &BindGroupEntries::with_indices((
+                (0, view_binding.clone()),
+                (101, &deferred_texture.texture.default_view),
+                // Use write since it's the one resolve will have just written to
+                (102, &ssgi_resolve.write.default_view),
+                (103, &prepass_downsample_texture.depth.default_view),
+                (104, &ssgi_resolve.specular_write.default_view),
+            )),
+        );
+    }
+}
+
+#[derive(Resource)]
+struct SSGICompositePipeline {
+    layout: BindGroupLayout,
+    pipeline_id: CachedRenderPipelineId,
+}
+
+impl FromWorld for SSGICompositePipeline {
+    fn from_world(world: &mut World) -> Self {
+        let entries = vec![
+            view_layout_entry(0),
+            utexture_layout_entry(101, TextureViewDimension::D2), // Deferred gbuffer
+            ftexture_layout_entry(102, TextureViewDimension::D2), // SSGI Resolve
+            ftexture_layout_entry(103, TextureViewDimension::D2), // Prepass Downsample Depth
+            ftexture_layout_entry(104, TextureViewDimension::D2), // SSGI Resolve Specular
+        ];
+
+        let layout = world
+            .resource::<RenderDevice>()
+            .create_bind_group_layout(Some("ssgi_composite_bind_group_layout"), &entries);
+
+        #[cfg(not(all(feature = "file_watcher")))]
+        let shader = SHADER_HANDLE;
+        #[cfg(all(feature = "file_watcher"))]
+        let shader = {
+            let asset_server = world.resource_mut::<AssetServer>();
+            asset_server.load("shaders/ssgi_composite.wgsl")
+        };
+
+        #[allow(unused_mut)]
+        let mut shader_defs = Vec::new();
+
+        #[cfg(all(feature = "webgl", target_arch = "wasm32"))]
+        shader_defs.push("WEBGL2".into());
+
+        let additive = BlendComponent {
+            src_factor: BlendFactor::One,
+            dst_factor: BlendFactor::One,
+            operation: BlendOperation::Add,
+        };
+
+        let pipeline_id =
+            world
+                .resource_mut::<PipelineCache>()
+                .queue_render_pipeline(RenderPipelineDescriptor {
+                    label: Some("ssgi_composite_pipeline".into()),
+                    layout: vec![layout.clone()],
+                    vertex: fullscreen_shader_vertex_state(),
+                    fragment: Some(FragmentState {
+                        shader,
+                        shader_defs,
+                        entry_point: "fragment".into(),
+                        targets: vec![Some(ColorTargetState {
+                            format: ViewTarget::TEXTURE_FORMAT_HDR,
+                            blend: Some(BlendState {
+                                color: additive,
+                                alpha: additive,
+                            }),
+                            write_mask: ColorWrites::ALL,
+                        })],
+                    }),
+                    primitive: PrimitiveState::default(),
+                    depth_stencil: None,
+                    multisample: MultisampleState::default(),
+                    push_constant_ranges: vec![],
+                });
+
+        Self {
+            layout,
+            pipeline_id,
+        }
+    }
+}
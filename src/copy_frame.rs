@@ -1,4 +1,9 @@
-use crate::bind_group_utils::linear_sampler;
+use crate::{
+    bind_group_utils::{linear_sampler, view_binding, view_layout_entry},
+    view_history::ViewHistories,
+};
+#[cfg(all(feature = "webgl", target_arch = "wasm32"))]
+use bevy::render::texture::TextureCache;
 use bevy::{
     asset::load_internal_asset,
     core_pipeline::{
@@ -11,17 +16,17 @@ use bevy::{
         extract_component::{ExtractComponent, ExtractComponentPlugin},
         render_graph::{Node, NodeRunError, RenderGraphApp, RenderGraphContext, RenderLabel},
         render_resource::{
-            BindGroupEntries, BindGroupLayout, BindGroupLayoutEntry, BindingType,
+            BindGroupEntries, BindGroupLayout, BindGroupLayoutEntry, BindingResource, BindingType,
             CachedRenderPipelineId, ColorTargetState, ColorWrites, Extent3d, FragmentState,
             MultisampleState, Operations, PipelineCache, PrimitiveState, RenderPassColorAttachment,
             RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor, Sampler,
-            SamplerBindingType, ShaderStages, TextureAspect, TextureDescriptor, TextureDimension,
-            TextureFormat, TextureSampleType, TextureUsages, TextureView, TextureViewDescriptor,
-            TextureViewDimension,
+            SamplerBindingType, ShaderDefVal, ShaderStages, TextureAspect, TextureDescriptor,
+            TextureDimension, TextureFormat, TextureSampleType, TextureUsages, TextureView,
+            TextureViewDescriptor, TextureViewDimension,
         },
         renderer::{RenderContext, RenderDevice},
-        texture::{CachedTexture, TextureCache},
-        view::{ExtractedView, ViewTarget},
+        texture::CachedTexture,
+        view::{ExtractedView, ViewTarget, ViewUniformOffset},
         Render, RenderApp, RenderSet,
     },
 };
@@ -29,6 +34,8 @@ use bevy::{
 const DOWNSAMPLE_COLOR_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
 
 #[derive(Component, ExtractComponent, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct CopyFrame {
     mip_levels: u8,
 }
@@ -74,6 +81,7 @@ pub struct FrameCopyNode {
     query: QueryState<
         (
             &'static ViewTarget,
+            &'static ViewUniformOffset,
             &'static PrevFrameTexture,
             &'static CopyFrame,
         ),
@@ -106,7 +114,7 @@ impl Node for FrameCopyNode {
     ) -> Result<(), NodeRunError> {
         let view_entity = graph_context.view_entity();
 
-        let Ok((view_target, prev_frame_tex, copy_frame)) =
+        let Ok((view_target, view_uniform_offset, prev_frame_tex, copy_frame)) =
             self.query.get_manual(world, view_entity)
         else {
             return Ok(());
@@ -120,10 +128,15 @@ impl Node for FrameCopyNode {
         let copy_frame_pipeline = world.resource::<CopyFramePipeline>();
         let pipeline_cache = world.resource::<PipelineCache>();
 
-        let Some(pipeline) = pipeline_cache.get_render_pipeline(copy_frame_pipeline.pipeline_id)
-        else {
+        let (Some(pipeline), Some(viewport_pipeline)) = (
+            pipeline_cache.get_render_pipeline(copy_frame_pipeline.pipeline_id),
+            pipeline_cache.get_render_pipeline(copy_frame_pipeline.viewport_pipeline_id),
+        ) else {
             return Ok(());
         };
+        // The first pass copies the viewport out of the main texture, which is the size of the
+        // render target
+        let view_uniform = Some((view_binding(world), view_uniform_offset.offset));
 
         let mip_levels = copy_frame.mip_levels as u32;
 
@@ -144,7 +157,8 @@ impl Node for FrameCopyNode {
                         ..default()
                     }),
                 prev_frame_tex.temp_texture[0].default_view.clone(),
-                pipeline,
+                viewport_pipeline,
+                view_uniform,
             );
             for i in 0..mip_levels - 2 {
                 run_pass(
@@ -155,6 +169,7 @@ impl Node for FrameCopyNode {
                         .default_view
                         .clone(),
                     pipeline,
+                    None,
                 );
             }
             for i in 0..mip_levels - 1 {
@@ -176,6 +191,7 @@ impl Node for FrameCopyNode {
                             array_layer_count: Some(1),
                         }),
                     pipeline,
+                    None,
                 );
             }
         }
@@ -206,7 +222,8 @@ impl Node for FrameCopyNode {
                         base_array_layer: 0,
                         array_layer_count: Some(1),
                     }),
-                pipeline,
+                viewport_pipeline,
+                view_uniform,
             );
             for i in 0..mip_levels - 1 {
                 run_pass(
@@ -239,6 +256,7 @@ impl Node for FrameCopyNode {
                             array_layer_count: Some(1),
                         }),
                     pipeline,
+                    None,
                 );
             }
         }
@@ -253,13 +271,31 @@ fn run_pass(
     src_view: TextureView,
     dst_view: TextureView,
     pipeline: &RenderPipeline,
+    view_uniform: Option<(BindingResource, u32)>,
 ) {
-    let bind_group = render_context.render_device().create_bind_group(
-        "post_process_bind_group",
-        &copy_frame_pipeline.layout,
-        // It's important for this to match the BindGroupLayout defined in the PostProcessPipeline
-        &BindGroupEntries::sequential((&src_view, &copy_frame_pipeline.sampler)),
-    );
+    let (bind_group, dynamic_offsets) = match view_uniform {
+        Some((view_binding, view_uniform_offset)) => (
+            render_context.render_device().create_bind_group(
+                "copy_frame_viewport_bind_group",
+                &copy_frame_pipeline.viewport_layout,
+                &BindGroupEntries::sequential((
+                    &src_view,
+                    &copy_frame_pipeline.sampler,
+                    view_binding,
+                )),
+            ),
+            vec![view_uniform_offset],
+        ),
+        None => (
+            render_context.render_device().create_bind_group(
+                "post_process_bind_group",
+                &copy_frame_pipeline.layout,
+                // It's important for this to match the BindGroupLayout defined in the PostProcessPipeline
+                &BindGroupEntries::sequential((&src_view, &copy_frame_pipeline.sampler)),
+            ),
+            Vec::new(),
+        ),
+    };
     let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
         label: Some("mip_chain_pass"),
         color_attachments: &[Some(RenderPassColorAttachment {
@@ -272,15 +308,18 @@ fn run_pass(
         occlusion_query_set: None,
     });
     render_pass.set_render_pipeline(pipeline);
-    render_pass.set_bind_group(0, &bind_group, &[]);
+    render_pass.set_bind_group(0, &bind_group, &dynamic_offsets);
     render_pass.draw(0..3, 0..1);
 }
 
 #[derive(Resource)]
 struct CopyFramePipeline {
     layout: BindGroupLayout,
+    /// Also binds the view, to copy only the viewport out of the main texture
+    viewport_layout: BindGroupLayout,
     sampler: Sampler,
     pipeline_id: CachedRenderPipelineId,
+    viewport_pipeline_id: CachedRenderPipelineId,
 }
 
 impl FromWorld for CopyFramePipeline {
@@ -308,19 +347,22 @@ impl FromWorld for CopyFramePipeline {
         let layout = world
             .resource::<RenderDevice>()
             .create_bind_group_layout(Some("copy_frame_bind_group_layout"), &entries);
+        let viewport_layout = world.resource::<RenderDevice>().create_bind_group_layout(
+            Some("copy_frame_viewport_bind_group_layout"),
+            &[entries[0], entries[1], view_layout_entry(2)],
+        );
 
         let sampler = linear_sampler(render_device);
 
-        let pipeline_id =
-            world
-                .resource_mut::<PipelineCache>()
-                .queue_render_pipeline(RenderPipelineDescriptor {
-                    label: Some("copy_frame_pipeline".into()),
+        let pipeline_descriptor =
+            |label: &'static str, layout: &BindGroupLayout, shader_defs: Vec<ShaderDefVal>| {
+                RenderPipelineDescriptor {
+                    label: Some(label.into()),
                     layout: vec![layout.clone()],
                     vertex: fullscreen_shader_vertex_state(),
                     fragment: Some(FragmentState {
                         shader: SHADER_HANDLE,
-                        shader_defs: vec![],
+                        shader_defs,
                         entry_point: "fragment".into(),
                         targets: vec![Some(ColorTargetState {
                             format: DOWNSAMPLE_COLOR_FORMAT,
@@ -332,12 +374,27 @@ impl FromWorld for CopyFramePipeline {
                     depth_stencil: None,
                     multisample: MultisampleState::default(),
                     push_constant_ranges: vec![],
-                });
+                }
+            };
+
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let pipeline_id = pipeline_cache.queue_render_pipeline(pipeline_descriptor(
+            "copy_frame_pipeline",
+            &layout,
+            vec![],
+        ));
+        let viewport_pipeline_id = pipeline_cache.queue_render_pipeline(pipeline_descriptor(
+            "copy_frame_viewport_pipeline",
+            &viewport_layout,
+            vec!["VIEWPORT".into()],
+        ));
 
         Self {
             layout,
+            viewport_layout,
             sampler,
             pipeline_id,
+            viewport_pipeline_id,
         }
     }
 }
@@ -351,9 +408,10 @@ pub struct PrevFrameTexture {
 
 fn prepare_textures(
     mut commands: Commands,
-    mut texture_cache: ResMut<TextureCache>,
+    #[cfg(all(feature = "webgl", target_arch = "wasm32"))] mut texture_cache: ResMut<TextureCache>,
     render_device: Res<RenderDevice>,
     views: Query<(Entity, &ExtractedCamera, &ExtractedView, &CopyFrame)>,
+    mut view_histories: ResMut<ViewHistories>,
 ) {
     for (entity, camera, _view, copy_frame) in &views {
         if let Some(physical_viewport_size) = camera.physical_viewport_size {
@@ -401,7 +459,9 @@ fn prepare_textures(
             };
 
             texture_descriptor.label = Some("prev_frame_texture");
-            let prev_frame_texture = texture_cache.get(&render_device, texture_descriptor.clone());
+            // Read by the next frame of this view, so it can't be shared with other views
+            let prev_frame_texture =
+                view_histories.persistent(&render_device, entity, &texture_descriptor);
 
             commands.entity(entity).insert(PrevFrameTexture {
                 texture: prev_frame_texture,
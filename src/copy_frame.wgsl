@@ -1,10 +1,21 @@
 #import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
+#ifdef VIEWPORT
+#import bevy_render::view::View
+#endif
 
 @group(0) @binding(0) var screen_texture: texture_2d<f32>;
 @group(0) @binding(1) var texture_sampler: sampler;
+#ifdef VIEWPORT
+@group(0) @binding(2) var<uniform> view: View;
+#endif
 
 @fragment
 fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+#ifdef VIEWPORT
+    // The main texture is the size of the render target, only copy the viewport of this view
+    let uv = (view.viewport.xy + in.uv * view.viewport.zw) / vec2<f32>(textureDimensions(screen_texture));
+    return textureSample(screen_texture, texture_sampler, uv);
+#else
     return textureSample(screen_texture, texture_sampler, in.uv);
+#endif
 }
-
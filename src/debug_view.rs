@@ -0,0 +1,500 @@
+use bevy::{
+    asset::load_internal_asset,
+    core_pipeline::{
+        core_3d::graph::{Core3d, Node3d},
+        fullscreen_vertex_shader::fullscreen_shader_vertex_state,
+    },
+    ecs::query::QueryItem,
+    prelude::*,
+    render::{
+        camera::ExtractedCamera,
+        extract_component::{ExtractComponent, ExtractComponentPlugin},
+        render_graph::{
+            NodeRunError, RenderGraphApp, RenderGraphContext, RenderLabel, ViewNode, ViewNodeRunner,
+        },
+        render_resource::{
+            BindGroupEntries, BindGroupLayout, CachedRenderPipelineId, ColorTargetState,
+            ColorWrites, FragmentState, MultisampleState, PipelineCache, PrimitiveState,
+            RenderPassDescriptor, RenderPipelineDescriptor, ShaderType, SpecializedRenderPipeline,
+            SpecializedRenderPipelines, TextureFormat, TextureViewDimension,
+        },
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+        texture::BevyDefault,
+        view::{ExtractedView, ViewTarget, ViewUniformOffset, ViewUniforms},
+        Render, RenderApp, RenderSet,
+    },
+    utils::HashMap,
+};
+
+#[cfg(all(
+    feature = "disocclusion",
+    not(all(feature = "webgl", target_arch = "wasm32"))
+))]
+use bevy::render::render_resource::{BindGroupEntry, BindingResource};
+#[cfg(all(
+    feature = "disocclusion",
+    not(all(feature = "webgl", target_arch = "wasm32"))
+))]
+use bevy_mod_taa::disocclusion::DisocclusionTextures;
+
+use crate::{
+    bind_group_utils::{
+        dynamic_uniform_layout_entry, ftexture_layout_entry, utexture_layout_entry,
+        view_layout_entry, PerViewBindGroups,
+    },
+    copy_frame::FrameCopyLabel,
+    prepass_downsample::PrepassDownsampleTextures,
+    ssgi::{SSGIPass, SSGITextures},
+    ssgi_generate_sh::SSGISHTextures,
+    ssgi_resolve::SSGIResolveTextures,
+    view_history::SSGIHistoryReset,
+    wgsl_uniform,
+};
+
+const SHADER_HANDLE: Handle<Shader> = Handle::weak_from_u128(398475029384750293);
+
+/// Replaces the final output of the camera with one of the SSGI stages, for finding which stage
+/// is causing a problem. Cascade and mip indices are clamped to what's available.
+#[derive(Component, ExtractComponent, Clone, Copy, Debug, Default, PartialEq, Eq, Reflect)]
+#[reflect(Component)]
+pub enum SSGIDebugView {
+    #[default]
+    None,
+    /// Radiance of each probe of cascade N, averaged over all its directions
+    CascadeRadiance(u32),
+    /// SH irradiance of the cascade 0 probes, evaluated with the surface normal
+    SHIrradiance,
+    /// Output of the resolve, before it's multiplied by the diffuse color
+    ResolvedDiffuse,
+    /// Output of the resolve, before it's multiplied by the env brdf
+    ResolvedSpecular,
+    /// Downsampled depth at mip N
+    Depth(u32),
+    /// Downsampled normals at mip N
+    Normals(u32),
+    /// Downsampled motion vectors at mip N
+    Motion(u32),
+    /// How much the history is rejected by the resolve, from reprojection and disocclusion
+    HistoryRejection,
+    /// Visibility from the occlusion bitmask for each probe of cascade N, averaged over all its
+    /// directions. The cascades store visibility instead of radiance while this is shown, so the
+    /// SSGI history is reset when switching to or from it.
+    OcclusionBitmask(u32),
+}
+
+impl SSGIDebugView {
+    /// Mode and cascade/mip index used by ssgi_debug.wgsl
+    fn mode_index(&self) -> (u32, u32) {
+        match *self {
+            SSGIDebugView::None => (0, 0),
+            SSGIDebugView::CascadeRadiance(n) => (1, n),
+            SSGIDebugView::SHIrradiance => (2, 0),
+            SSGIDebugView::ResolvedDiffuse => (3, 0),
+            SSGIDebugView::ResolvedSpecular => (4, 0),
+            SSGIDebugView::Depth(mip) => (5, mip),
+            SSGIDebugView::Normals(mip) => (6, mip),
+            SSGIDebugView::Motion(mip) => (7, mip),
+            SSGIDebugView::HistoryRejection => (8, 0),
+            SSGIDebugView::OcclusionBitmask(n) => (9, n),
+        }
+    }
+}
+
+/// The cascades store visibility instead of radiance while [`SSGIDebugView::OcclusionBitmask`] is
+/// shown, which would stay in the SH and resolve history after switching away from it
+fn reset_history_on_occlusion_debug(
+    mut commands: Commands,
+    mut showed_occlusion: Local<HashMap<Entity, bool>>,
+    views: Query<(Entity, Option<&SSGIDebugView>), With<SSGIPass>>,
+) {
+    showed_occlusion.retain(|entity, _| views.contains(*entity));
+    for (entity, debug_view) in &views {
+        let shows_occlusion = matches!(debug_view, Some(SSGIDebugView::OcclusionBitmask(_)));
+        let showed = showed_occlusion.insert(entity, shows_occlusion);
+        if showed.unwrap_or(false) != shows_occlusion {
+            commands.entity(entity).insert(SSGIHistoryReset);
+        }
+    }
+}
+
+pub struct SSGIDebugViewPlugin;
+impl Plugin for SSGIDebugViewPlugin {
+    fn build(&self, app: &mut App) {
+        load_internal_asset!(
+            app,
+            SHADER_HANDLE,
+            "../assets/shaders/ssgi_debug.wgsl",
+            Shader::from_wgsl
+        );
+
+        app.register_type::<SSGIDebugView>()
+            .add_plugins(ExtractComponentPlugin::<SSGIDebugView>::default())
+            .add_systems(PostUpdate, reset_history_on_occlusion_debug);
+
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .init_resource::<SpecializedRenderPipelines<SSGIDebugLayout>>()
+            .init_resource::<PerViewBindGroups<SSGIDebugConfig>>()
+            .add_systems(Render, prepare_pipelines.in_set(RenderSet::Prepare))
+            .add_systems(
+                Render,
+                (prepare_uniforms, prepare_bind_groups)
+                    .chain()
+                    .in_set(RenderSet::PrepareBindGroups),
+            )
+            .add_render_graph_node::<ViewNodeRunner<SSGIDebugNode>>(Core3d, SSGIDebugLabel)
+            // After the frame copy so the debug output doesn't feed into the next frame's SSGI
+            .add_render_graph_edges(
+                Core3d,
+                (FrameCopyLabel, SSGIDebugLabel, Node3d::EndMainPass),
+            )
+            .add_render_graph_edge(Core3d, Node3d::MainTransparentPass, SSGIDebugLabel);
+    }
+
+    fn finish(&self, app: &mut App) {
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app.init_resource::<SSGIDebugLayout>();
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
+pub struct SSGIDebugLabel;
+
+wgsl_uniform! {
+    #[derive(Clone, Copy, ShaderType, Debug, Default)]
+    pub struct SSGIDebugConfig {
+        mode: u32,
+        index: u32,
+        directions: u32,
+        _webgl2_padding_1: f32,
+    }
+}
+
+#[derive(Default)]
+pub struct SSGIDebugNode;
+
+impl ViewNode for SSGIDebugNode {
+    type ViewQuery = (
+        &'static ExtractedCamera,
+        &'static ViewUniformOffset,
+        &'static ViewTarget,
+        &'static SSGIDebugView,
+        &'static SSGIDebugPipeline,
+    );
+
+    fn run(
+        &self,
+        graph_context: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        (camera, view_uniform_offset, view_target, debug_view, debug_pipeline): QueryItem<
+            Self::ViewQuery,
+        >,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        if *debug_view == SSGIDebugView::None {
+            return Ok(());
+        }
+
+        let pipeline_cache = world.resource::<PipelineCache>();
+
+        let Some(pipeline) = pipeline_cache.get_render_pipeline(debug_pipeline.pipeline_id) else {
+            return Ok(());
+        };
+
+        let Some(view_bind_groups) = world
+            .resource::<PerViewBindGroups<SSGIDebugConfig>>()
+            .0
+            .get(&graph_context.view_entity())
+        else {
+            return Ok(());
+        };
+        let Some(bind_group) = view_bind_groups.bind_group(0) else {
+            return Ok(());
+        };
+
+        let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+            label: Some("ssgi_debug_pass"),
+            color_attachments: &[Some(view_target.get_color_attachment())],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        if let Some(viewport) = camera.viewport.as_ref() {
+            render_pass.set_camera_viewport(viewport);
+        }
+        render_pass.set_render_pipeline(pipeline);
+        render_pass.set_bind_group(
+            0,
+            bind_group,
+            &[view_uniform_offset.offset, view_bind_groups.offsets[0]],
+        );
+        render_pass.draw(0..3, 0..1);
+
+        Ok(())
+    }
+}
+
+#[derive(Resource)]
+pub struct SSGIDebugLayout {
+    pub layout: BindGroupLayout,
+    pub shader: Handle<Shader>,
+}
+
+#[derive(Component)]
+pub struct SSGIDebugPipeline {
+    pipeline_id: CachedRenderPipelineId,
+}
+
+impl FromWorld for SSGIDebugLayout {
+    fn from_world(world: &mut World) -> Self {
+        #[allow(unused_mut)]
+        let mut entries = vec![
+            view_layout_entry(0),
+            utexture_layout_entry(101, TextureViewDimension::D2), // Cascade Data Texture 1
+            utexture_layout_entry(102, TextureViewDimension::D2), // Cascade Data Texture 2
+            utexture_layout_entry(103, TextureViewDimension::D2), // SH Texture
+            ftexture_layout_entry(104, TextureViewDimension::D2), // Resolve
+            ftexture_layout_entry(105, TextureViewDimension::D2), // Resolve Specular
+            ftexture_layout_entry(106, TextureViewDimension::D2), // Prepass Downsample Depth
+            ftexture_layout_entry(107, TextureViewDimension::D2), // Prepass Downsample Normals
+            ftexture_layout_entry(108, TextureViewDimension::D2), // Prepass Downsample Motion
+            dynamic_uniform_layout_entry(109, SSGIDebugConfig::min_size()),
+        ];
+
+        #[cfg(all(
+            feature = "disocclusion",
+            not(all(feature = "webgl", target_arch = "wasm32"))
+        ))]
+        entries.push(ftexture_layout_entry(110, TextureViewDimension::D2)); // Disocclusion
+
+        let layout = world
+            .resource::<RenderDevice>()
+            .create_bind_group_layout(Some("ssgi_debug_bind_group_layout"), &entries);
+
+        #[cfg(not(all(feature = "file_watcher")))]
+        let shader = SHADER_HANDLE;
+        #[cfg(all(feature = "file_watcher"))]
+        let shader = {
+            let asset_server = world.resource_mut::<AssetServer>();
+            asset_server.load("shaders/ssgi_debug.wgsl")
+        };
+
+        Self { layout, shader }
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Clone)]
+pub struct SSGIDebugPipelineKey {
+    hdr: bool,
+}
+
+impl SpecializedRenderPipeline for SSGIDebugLayout {
+    type Key = SSGIDebugPipelineKey;
+
+    // The pushes depend on features
+    #[allow(clippy::vec_init_then_push)]
+    fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
+        #[allow(unused_mut)]
+        let mut shader_defs = Vec::new();
+
+        #[cfg(all(feature = "webgl", target_arch = "wasm32"))]
+        shader_defs.push("WEBGL2".into());
+
+        #[cfg(all(
+            feature = "disocclusion",
+            not(all(feature = "webgl", target_arch = "wasm32"))
+        ))]
+        shader_defs.push("DISOCCLUSION".into());
+
+        let format = if key.hdr {
+            ViewTarget::TEXTURE_FORMAT_HDR
+        } else {
+            TextureFormat::bevy_default()
+        };
+
+        RenderPipelineDescriptor {
+            label: Some("ssgi_debug_pipeline".into()),
+            layout: vec![self.layout.clone()],
+            vertex: fullscreen_shader_vertex_state(),
+            fragment: Some(FragmentState {
+                shader: self.shader.clone(),
+                shader_defs,
+                entry_point: "fragment".into(),
+                targets: vec![Some(ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            push_constant_ranges: vec![],
+        }
+    }
+}
+
+fn prepare_pipelines(
+    mut commands: Commands,
+    pipeline_cache: Res<PipelineCache>,
+    mut pipelines: ResMut<SpecializedRenderPipelines<SSGIDebugLayout>>,
+    layout: Res<SSGIDebugLayout>,
+    views: Query<(Entity, &ExtractedView, &SSGIDebugView)>,
+) {
+    for (entity, view, debug_view) in &views {
+        if *debug_view == SSGIDebugView::None {
+            continue;
+        }
+        let key = SSGIDebugPipelineKey { hdr: view.hdr };
+        let pipeline_id = pipelines.specialize(&pipeline_cache, &layout, key);
+        commands
+            .entity(entity)
+            .insert(SSGIDebugPipeline { pipeline_id });
+    }
+}
+
+/// Cascade shown by the debug view, clamped to the ones that exist
+fn debug_cascade(debug_view: SSGIDebugView, ssgi_textures: &SSGITextures) -> u32 {
+    match debug_view {
+        SSGIDebugView::CascadeRadiance(index) | SSGIDebugView::OcclusionBitmask(index) => {
+            index.min(ssgi_textures.data_textures1.len() as u32 - 1)
+        }
+        _ => 0,
+    }
+}
+
+fn prepare_uniforms(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut bind_groups: ResMut<PerViewBindGroups<SSGIDebugConfig>>,
+    views: Query<(
+        Entity,
+        &SSGIDebugView,
+        &SSGIPass,
+        &SSGITextures,
+        &PrepassDownsampleTextures,
+    )>,
+) {
+    bind_groups.0.retain(|entity, _| {
+        views
+            .get(*entity)
+            .is_ok_and(|(_, debug_view, ..)| *debug_view != SSGIDebugView::None)
+    });
+
+    for (entity, debug_view, ssgi_pass, ssgi_textures, prepass_downsample_texture) in &views {
+        if *debug_view == SSGIDebugView::None {
+            continue;
+        }
+
+        let (mode, index) = debug_view.mode_index();
+        let cascade_n = debug_cascade(*debug_view, ssgi_textures);
+        let max_mip = prepass_downsample_texture.depth.texture.mip_level_count() - 1;
+        let config = SSGIDebugConfig {
+            mode,
+            index: match debug_view {
+                SSGIDebugView::Depth(_) | SSGIDebugView::Normals(_) | SSGIDebugView::Motion(_) => {
+                    index.min(max_mip)
+                }
+                _ => cascade_n,
+            },
+            directions: ssgi_pass.cascade_0_directions << cascade_n,
+            _webgl2_padding_1: 0.0,
+        };
+        bind_groups.0.entry(entity).or_default().write_uniforms(
+            &render_device,
+            &render_queue,
+            [config],
+        );
+    }
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn prepare_bind_groups(
+    render_device: Res<RenderDevice>,
+    layout: Res<SSGIDebugLayout>,
+    view_uniforms: Res<ViewUniforms>,
+    mut bind_groups: ResMut<PerViewBindGroups<SSGIDebugConfig>>,
+    views: Query<(
+        Entity,
+        &SSGIDebugView,
+        &SSGITextures,
+        &SSGISHTextures,
+        &SSGIResolveTextures,
+        &PrepassDownsampleTextures,
+    )>,
+    #[cfg(all(
+        feature = "disocclusion",
+        not(all(feature = "webgl", target_arch = "wasm32"))
+    ))]
+    disocclusion_textures: Query<&DisocclusionTextures>,
+) {
+    let Some(view_binding) = view_uniforms.uniforms.binding() else {
+        return;
+    };
+
+    for (
+        entity,
+        debug_view,
+        ssgi_textures,
+        sh_texture,
+        resolve_textures,
+        prepass_downsample_texture,
+    ) in &views
+    {
+        let Some(view_bind_groups) = bind_groups.0.get_mut(&entity) else {
+            continue;
+        };
+        let Some(config_binding) = view_bind_groups.uniforms.binding() else {
+            continue;
+        };
+
+        let cascade_n = debug_cascade(*debug_view, ssgi_textures) as usize;
+
+        #[allow(unused_mut)]
+        let mut entries = BindGroupEntries::with_indices((
+            (0, view_binding.clone()),
+            (101, &ssgi_textures.data_textures1[cascade_n].default_view),
+            (102, &ssgi_textures.data_textures2[cascade_n].default_view),
+            // Use write since it's the one ssgi_generate_sh would have just written to
+            (103, &sh_texture.write.default_view),
+            (104, &resolve_textures.write.default_view),
+            (105, &resolve_textures.specular_write.default_view),
+            (106, &prepass_downsample_texture.depth.default_view),
+            (107, &prepass_downsample_texture.normals.default_view),
+            (108, &prepass_downsample_texture.motion.default_view),
+            (109, config_binding),
+        ))
+        .to_vec();
+
+        #[cfg(all(
+            feature = "disocclusion",
+            not(all(feature = "webgl", target_arch = "wasm32"))
+        ))]
+        {
+            let Ok(disocclusion_textures) = disocclusion_textures.get(entity) else {
+                continue;
+            };
+            entries.push(BindGroupEntry {
+                binding: 110,
+                resource: BindingResource::TextureView(&disocclusion_textures.output.default_view),
+            });
+        }
+
+        view_bind_groups
+            .bind_groups
+            .resize_with(1, Default::default);
+        view_bind_groups.bind_groups[0].update(
+            &render_device,
+            "ssgi_debug_bind_group",
+            &layout.layout,
+            &entries,
+        );
+    }
+}
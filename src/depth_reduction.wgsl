@@ -0,0 +1,40 @@
+#define_import_path ssgi::depth_reduction
+
+// Same as DepthReduction in prepass_downsample.rs. Depth is reversed Z, so the closest is the
+// largest. With DEPTH_REDUCTION_MIN_MAX, depth.y is the farthest depth under the texel.
+struct DepthSample {
+    depth: vec2<f32>,
+    normal: vec2<f32>,
+    motion: vec2<f32>,
+}
+
+fn pick(a: DepthSample, b: DepthSample, closest: bool) -> DepthSample {
+    if (a.depth.x >= b.depth.x) == closest {
+        return a;
+    }
+    return b;
+}
+
+fn pick_4(a: DepthSample, b: DepthSample, c: DepthSample, d: DepthSample, closest: bool) -> DepthSample {
+    return pick(pick(a, b, closest), pick(c, d, closest), closest);
+}
+
+// Reduces a 2x2 block, a b on the top row and c d on the bottom, to the texel at coords of the
+// next mip. The normals and motion are taken from the same texel as the depth.
+fn reduce(a: DepthSample, b: DepthSample, c: DepthSample, d: DepthSample, coords: vec2<u32>) -> DepthSample {
+#ifdef DEPTH_REDUCTION_POINT
+    // Same texel a nearest sample at the center of the block lands on
+    return d;
+#else ifdef DEPTH_REDUCTION_FARTHEST
+    return pick_4(a, b, c, d, false);
+#else ifdef DEPTH_REDUCTION_CHECKERBOARD
+    // Alternating keeps both thin foreground and the background behind it in the higher mips
+    return pick_4(a, b, c, d, ((coords.x + coords.y) & 1u) == 0u);
+#else ifdef DEPTH_REDUCTION_MIN_MAX
+    var sample = pick_4(a, b, c, d, true);
+    sample.depth.y = min(min(a.depth.y, b.depth.y), min(c.depth.y, d.depth.y));
+    return sample;
+#else
+    return pick_4(a, b, c, d, true);
+#endif
+}
@@ -0,0 +1,124 @@
+//! CPU versions of the packed formats in `rgb9e5.wgsl` and `xyz8e5.wgsl`, for decoding readbacks
+//! of the cascade and SH textures. These follow the WGSL step by step so the results are bit exact.
+//! `min`/`max` are used instead of `clamp` since, like on the GPU, they return the non-NaN operand.
+
+use bevy::math::Vec3;
+
+pub const RGB9E5_EXPONENT_BITS: u32 = 5;
+pub const RGB9E5_MANTISSA_BITS: i32 = 9;
+pub const RGB9E5_MANTISSA_BITSU: u32 = 9;
+pub const RGB9E5_EXP_BIAS: i32 = 15;
+pub const RGB9E5_MAX_VALID_BIASED_EXP: u32 = 31;
+
+pub const MAX_RGB9E5_EXP: u32 = 16;
+pub const RGB9E5_MANTISSA_VALUES: i32 = 512;
+pub const MAX_RGB9E5_MANTISSA: i32 = 511;
+pub const MAX_RGB9E5_MANTISSAU: u32 = 511;
+pub const MAX_RGB9E5_: f32 = 65408.0;
+pub const EPSILON_RGB9E5_: f32 = 0.000000059604645;
+
+pub const XYZ8E5_EXPONENT_BITS: u32 = 5;
+pub const XYZ8E5_MANTISSA_BITS: i32 = 8;
+pub const XYZ8E5_MANTISSA_BITSU: u32 = 8;
+pub const XYZ8E5_EXP_BIAS: i32 = 15;
+pub const XYZ8E5_MAX_VALID_BIASED_EXP: u32 = 31;
+
+pub const MAX_XYZ8E5_EXP: u32 = 16;
+pub const XYZ8E5_MANTISSA_VALUES: i32 = 256;
+pub const MAX_XYZ8E5_MANTISSA: i32 = 255;
+pub const MAX_XYZ8E5_MANTISSAU: u32 = 255;
+pub const MAX_XYZ8E5_: f32 = 65280.0;
+pub const EPSILON_XYZ8E5_: f32 = 0.00000011920929;
+
+fn floor_log2(x: f32) -> i32 {
+    let biased_exponent = (x.to_bits() & 0x7F800000) >> 23;
+    biased_exponent as i32 - 127
+}
+
+// Always an integer power of 2 in range, so this is exact like exp2() in the shader
+fn exp2i(x: i32) -> f32 {
+    2.0f32.powi(x)
+}
+
+fn extract_bits(value: u32, offset: u32, bits: u32) -> u32 {
+    let mask = (1 << bits) - 1;
+    (value >> offset) & mask
+}
+
+/// Same as `vec3_to_rgb9e5_()` in `rgb9e5.wgsl`. Negative and NaN components become 0.0.
+pub fn vec3_to_rgb9e5(rgb: Vec3) -> u32 {
+    let rgb = rgb.max(Vec3::ZERO).min(Vec3::splat(MAX_RGB9E5_));
+
+    let maxrgb = rgb.x.max(rgb.y.max(rgb.z));
+    let mut exp_shared = (-RGB9E5_EXP_BIAS - 1).max(floor_log2(maxrgb)) + 1 + RGB9E5_EXP_BIAS;
+    let mut denom = exp2i(exp_shared - RGB9E5_EXP_BIAS - RGB9E5_MANTISSA_BITS);
+
+    let maxm = (maxrgb / denom + 0.5).floor() as i32;
+    if maxm == RGB9E5_MANTISSA_VALUES {
+        denom *= 2.0;
+        exp_shared += 1;
+    }
+
+    let n = (rgb / denom + 0.5).floor();
+    let (r, g, b) = (n.x as u32, n.y as u32, n.z as u32);
+
+    ((exp_shared as u32) << 27) | (b << 18) | (g << 9) | r
+}
+
+/// Same as `rgb9e5_to_vec3_()` in `rgb9e5.wgsl`
+pub fn rgb9e5_to_vec3(v: u32) -> Vec3 {
+    let exponent =
+        extract_bits(v, 27, RGB9E5_EXPONENT_BITS) as i32 - RGB9E5_EXP_BIAS - RGB9E5_MANTISSA_BITS;
+    let scale = exp2i(exponent);
+
+    Vec3::new(
+        extract_bits(v, 0, RGB9E5_MANTISSA_BITSU) as f32,
+        extract_bits(v, 9, RGB9E5_MANTISSA_BITSU) as f32,
+        extract_bits(v, 18, RGB9E5_MANTISSA_BITSU) as f32,
+    ) * scale
+}
+
+fn is_sign_negative(v: f32) -> u32 {
+    (v.to_bits() >> 31) & 1
+}
+
+/// Same as `vec3_to_xyz8e5_()` in `xyz8e5.wgsl`. The sign is kept, including for -0.0.
+/// NaN components become `MAX_XYZ8E5_` with the sign of the NaN.
+pub fn vec3_to_xyz8e5(xyz: Vec3) -> u32 {
+    let xsign = is_sign_negative(xyz.x) << 8;
+    let ysign = is_sign_negative(xyz.y) << 8;
+    let zsign = is_sign_negative(xyz.z) << 8;
+
+    let xyz = xyz.abs().min(Vec3::splat(MAX_XYZ8E5_));
+
+    let maxxyz = xyz.x.max(xyz.y.max(xyz.z));
+    let mut exp_shared = (-XYZ8E5_EXP_BIAS - 1).max(floor_log2(maxxyz)) + 1 + XYZ8E5_EXP_BIAS;
+    let mut denom = exp2i(exp_shared - XYZ8E5_EXP_BIAS - XYZ8E5_MANTISSA_BITS);
+
+    let maxm = (maxxyz / denom + 0.5).floor() as i32;
+    if maxm == XYZ8E5_MANTISSA_VALUES {
+        denom *= 2.0;
+        exp_shared += 1;
+    }
+
+    let s = (xyz / denom + 0.5).floor();
+    let (x, y, z) = (s.x as u32, s.y as u32, s.z as u32);
+
+    ((exp_shared as u32) << 27) | ((z | zsign) << 18) | ((y | ysign) << 9) | (x | xsign)
+}
+
+/// Same as `xyz8e5_to_vec3_()` in `xyz8e5.wgsl`
+pub fn xyz8e5_to_vec3(v: u32) -> Vec3 {
+    let exponent =
+        extract_bits(v, 27, XYZ8E5_EXPONENT_BITS) as i32 - XYZ8E5_EXP_BIAS - XYZ8E5_MANTISSA_BITS;
+    let scale = exp2i(exponent);
+
+    // Extract both the mantissa and sign at the same time.
+    let xb = extract_bits(v, 0, XYZ8E5_MANTISSA_BITSU + 1);
+    let yb = extract_bits(v, 9, XYZ8E5_MANTISSA_BITSU + 1);
+    let zb = extract_bits(v, 18, XYZ8E5_MANTISSA_BITSU + 1);
+
+    let signed = |b: u32| f32::from_bits(((b & 0xFF) as f32).to_bits() | (b & 0x100) << 23);
+
+    Vec3::new(signed(xb), signed(yb), signed(zb)) * scale
+}
@@ -0,0 +1,239 @@
+use bevy::{
+    asset::load_internal_asset,
+    core_pipeline::{
+        core_3d::graph::{Core3d, Node3d},
+        prepass::DeferredPrepass,
+    },
+    ecs::query::QueryItem,
+    pbr::{ExtendedMaterial, MaterialExtension, MaterialPlugin},
+    prelude::*,
+    render::{
+        camera::CameraUpdateSystem,
+        extract_component::{ExtractComponent, ExtractComponentPlugin},
+        render_asset::{RenderAssetUsages, RenderAssets},
+        render_graph::{
+            NodeRunError, RenderGraphApp, RenderGraphContext, RenderLabel, ViewNode, ViewNodeRunner,
+        },
+        render_resource::{AsBindGroup, Extent3d, ShaderRef, TextureDimension, TextureFormat},
+        renderer::RenderContext,
+        RenderApp,
+    },
+    utils::{error_once, HashMap},
+};
+
+use crate::{
+    ssgi::SSGIPass,
+    ssgi_resolve::{SSGIResolveLabel, SSGIResolveTextures},
+};
+
+pub const SHADER_HANDLE: Handle<Shader> = Handle::weak_from_u128(689023745098237450);
+
+/// Image that forward materials read the resolved SSGI from by default. Resized to match the forward
+/// SSGI camera that doesn't have a [`SSGIForwardIndirect`].
+pub const SSGI_FORWARD_INDIRECT_IMAGE_HANDLE: Handle<Image> =
+    Handle::weak_from_u128(912837450928374509);
+
+const FORWARD_INDIRECT_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+/// [`StandardMaterial`] that also applies the resolved SSGI. Use for forward rendered meshes
+/// viewed by a camera with [`crate::SSGIForwardBundle`]. Only the indirect diffuse is applied,
+/// the forward path has no indirect specular.
+pub type SSGIStandardMaterial = ExtendedMaterial<StandardMaterial, SSGIMaterialExtension>;
+
+#[derive(Asset, AsBindGroup, Reflect, Debug, Clone)]
+pub struct SSGIMaterialExtension {
+    /// The resolved SSGI of the camera viewing the mesh. Defaults to [`SSGI_FORWARD_INDIRECT_IMAGE_HANDLE`],
+    /// see [`SSGIForwardIndirect`] for other cameras.
+    #[texture(100)]
+    pub indirect: Handle<Image>,
+}
+
+impl Default for SSGIMaterialExtension {
+    fn default() -> Self {
+        SSGIMaterialExtension {
+            indirect: SSGI_FORWARD_INDIRECT_IMAGE_HANDLE,
+        }
+    }
+}
+
+impl MaterialExtension for SSGIMaterialExtension {
+    fn fragment_shader() -> ShaderRef {
+        #[cfg(not(all(feature = "file_watcher")))]
+        return SHADER_HANDLE.into();
+        #[cfg(all(feature = "file_watcher"))]
+        return "shaders/ssgi_forward.wgsl".into();
+    }
+}
+
+/// Marks a camera that uses the forward SSGI path.
+/// The resolved SSGI is copied to [`SSGI_FORWARD_INDIRECT_IMAGE_HANDLE`], unless the camera has a
+/// [`SSGIForwardIndirect`].
+#[derive(Component, ExtractComponent, Clone, Copy, Default)]
+pub struct SSGIForward;
+
+/// Gives a forward SSGI camera its own image to copy the resolved SSGI into, instead of
+/// [`SSGI_FORWARD_INDIRECT_IMAGE_HANDLE`] which only one camera can use at a time.
+/// Needed for each additional forward camera (split-screen, render to texture, etc...). Meshes seen by
+/// that camera need materials whose [`SSGIMaterialExtension::indirect`] is this image.
+#[derive(Component, ExtractComponent, Clone)]
+pub struct SSGIForwardIndirect {
+    pub image: Handle<Image>,
+}
+
+impl SSGIForwardIndirect {
+    pub fn new(images: &mut Assets<Image>) -> Self {
+        SSGIForwardIndirect {
+            image: images.add(new_forward_indirect_image()),
+        }
+    }
+}
+
+/// Builds the SSGI inputs from the normal prepass instead of the deferred gbuffer and
+/// applies the resolved indirect light through [`SSGIStandardMaterial`].
+pub struct SSGIForwardPlugin;
+impl Plugin for SSGIForwardPlugin {
+    fn build(&self, app: &mut App) {
+        load_internal_asset!(
+            app,
+            SHADER_HANDLE,
+            "../assets/shaders/ssgi_forward.wgsl",
+            Shader::from_wgsl
+        );
+
+        app.add_plugins((
+            MaterialPlugin::<SSGIStandardMaterial>::default(),
+            ExtractComponentPlugin::<SSGIForward>::default(),
+            ExtractComponentPlugin::<SSGIForwardIndirect>::default(),
+        ))
+        .add_systems(Startup, setup_forward_indirect_image)
+        // After the viewport sizes are updated, so the images match them the same frame
+        .add_systems(
+            PostUpdate,
+            resize_forward_indirect_images.after(CameraUpdateSystem),
+        );
+
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .add_render_graph_node::<ViewNodeRunner<SSGIForwardCopyNode>>(
+                Core3d,
+                SSGIForwardCopyLabel,
+            )
+            .add_render_graph_edges(
+                Core3d,
+                (
+                    SSGIResolveLabel,
+                    SSGIForwardCopyLabel,
+                    Node3d::StartMainPass,
+                ),
+            );
+    }
+}
+
+fn new_forward_indirect_image() -> Image {
+    Image::new_fill(
+        Extent3d {
+            width: 1,
+            height: 1,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0; 8],
+        FORWARD_INDIRECT_FORMAT,
+        RenderAssetUsages::default(),
+    )
+}
+
+fn setup_forward_indirect_image(mut images: ResMut<Assets<Image>>) {
+    images.insert(
+        SSGI_FORWARD_INDIRECT_IMAGE_HANDLE,
+        new_forward_indirect_image(),
+    );
+}
+
+#[allow(clippy::type_complexity)]
+fn resize_forward_indirect_images(
+    mut images: ResMut<Assets<Image>>,
+    cameras: Query<
+        (Entity, &Camera, Option<&SSGIForwardIndirect>),
+        (With<SSGIPass>, With<SSGIForward>, Without<DeferredPrepass>),
+    >,
+) {
+    let mut resized = HashMap::<AssetId<Image>, Entity>::new();
+    for (entity, camera, indirect) in &cameras {
+        if !camera.is_active {
+            continue;
+        }
+        let Some(size) = camera.physical_viewport_size() else {
+            continue;
+        };
+        let image_id = indirect.map_or(SSGI_FORWARD_INDIRECT_IMAGE_HANDLE.id(), |i| i.image.id());
+        if let Some(other) = resized.insert(image_id, entity) {
+            error_once!(
+                "Forward SSGI cameras {other:?} and {entity:?} copy to the same indirect image, \
+                only one of them will have correct SSGI. Add a SSGIForwardIndirect with its own \
+                image to each additional forward SSGI camera."
+            );
+            continue;
+        }
+        // Avoid get_mut when nothing changed, it would re-upload the image every frame
+        if images.get(image_id).is_some_and(|image| image.size() != size) {
+            let image = images.get_mut(image_id).unwrap();
+            image.resize(Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: 1,
+            });
+        }
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
+pub struct SSGIForwardCopyLabel;
+
+/// Copies the resolved SSGI into the view's [`SSGIForwardIndirect`] image, or
+/// [`SSGI_FORWARD_INDIRECT_IMAGE_HANDLE`], so forward materials can read it.
+#[derive(Default)]
+pub struct SSGIForwardCopyNode;
+
+impl ViewNode for SSGIForwardCopyNode {
+    type ViewQuery = (
+        &'static SSGIResolveTextures,
+        &'static SSGIForward,
+        Option<&'static SSGIForwardIndirect>,
+        Has<DeferredPrepass>,
+    );
+
+    fn run(
+        &self,
+        _graph_context: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        (resolve_textures, _forward, indirect, deferred): QueryItem<Self::ViewQuery>,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        if deferred {
+            return Ok(());
+        }
+        let images = world.resource::<RenderAssets<Image>>();
+        let image_id = indirect.map_or(SSGI_FORWARD_INDIRECT_IMAGE_HANDLE.id(), |i| i.image.id());
+        let Some(indirect_image) = images.get(image_id) else {
+            return Ok(());
+        };
+
+        let src = &resolve_textures.write.texture;
+        // Skip if another camera sized the image, or it hasn't been prepared yet
+        if indirect_image.texture.size() != src.size() {
+            return Ok(());
+        }
+
+        render_context.command_encoder().copy_texture_to_texture(
+            src.as_image_copy(),
+            indirect_image.texture.as_image_copy(),
+            src.size(),
+        );
+
+        Ok(())
+    }
+}
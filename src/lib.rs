@@ -1,14 +1,24 @@
 pub mod bind_group_utils;
+pub mod composite;
 pub mod copy_frame;
+pub mod debug_view;
+pub mod formats;
+pub mod forward;
 pub mod lighting_pass;
 pub mod prepass_downsample;
+pub mod quality;
+#[cfg(feature = "serde")]
+pub mod settings_asset;
 pub mod ssgi;
+pub mod ssgi_denoise;
 pub mod ssgi_generate_sh;
 pub mod ssgi_resolve;
+pub mod view_history;
 
 use bevy::{
     asset::load_internal_asset,
-    core_pipeline::prepass::{DeferredPrepass, DepthPrepass, MotionVectorPrepass},
+    core_pipeline::prepass::{DeferredPrepass, DepthPrepass, MotionVectorPrepass, NormalPrepass},
+    pbr::deferred::DeferredPbrLightingPlugin,
     prelude::*,
     render::{
         extract_resource::{ExtractResource, ExtractResourcePlugin},
@@ -16,13 +26,23 @@ use bevy::{
         texture::{CompressedImageFormats, ImageType},
     },
 };
-use bevy_mod_taa::disocclusion::DisocclusionSettings;
+#[cfg(all(
+    feature = "disocclusion",
+    not(all(feature = "webgl", target_arch = "wasm32"))
+))]
+use bevy_mod_taa::disocclusion::{DisocclusionPlugin, DisocclusionSettings};
+use composite::SSGICompositePlugin;
 use copy_frame::{CopyFrame, CopyFramePlugin};
+use debug_view::SSGIDebugViewPlugin;
+use forward::{SSGIForward, SSGIForwardPlugin};
 use lighting_pass::CustomDeferredPbrLightingPlugin;
 use prepass_downsample::{PrepassDownsample, PrepassDownsamplePlugin};
+use quality::SSGIQuality;
 use ssgi::{SSGIPass, SSGISamplePlugin};
+use ssgi_denoise::SSGIDenoisePlugin;
 use ssgi_generate_sh::{SSGIGenerateSH, SSGIGenerateSHPlugin};
 use ssgi_resolve::{SSGIResolve, SSGIResolvePlugin};
+use view_history::ViewHistoryPlugin;
 
 pub const RGB9E5_SHADER_HANDLE: Handle<Shader> = Handle::weak_from_u128(285370495827304598);
 pub const XYZ8E5_SHADER_HANDLE: Handle<Shader> = Handle::weak_from_u128(572304958723049851);
@@ -47,24 +67,52 @@ impl Plugin for SSGIPlugin {
             Shader::from_wgsl
         );
 
-        app.add_systems(Startup, load_blue_noise)
-            .add_systems(Update, add_disocclusion_settings)
-            .add_plugins((
-                ExtractResourcePlugin::<BlueNoise>::default(),
-                CustomDeferredPbrLightingPlugin,
-                CopyFramePlugin,
-                PrepassDownsamplePlugin,
-                SSGISamplePlugin,
-                SSGIGenerateSHPlugin,
-                SSGIResolvePlugin,
-            ));
-        // todo webgl
-        //if !app.is_plugin_added::<DisocclusionPlugin>() {
-        //    app.add_plugins(DisocclusionPlugin);
-        //}
+        // If bevy's default deferred lighting is used, add SSGI on top of it instead of replacing it.
+        // Requires SSGIPlugin to be added after PbrPlugin.
+        if app.is_plugin_added::<DeferredPbrLightingPlugin>() {
+            app.add_plugins(SSGICompositePlugin);
+        } else {
+            app.add_plugins(CustomDeferredPbrLightingPlugin);
+        }
+
+        app.register_type::<SSGIQuality>()
+            .add_systems(Startup, load_blue_noise);
+
+        // Needs to be added before the SSGI nodes so they can be ordered after it
+        #[cfg(all(
+            feature = "disocclusion",
+            not(all(feature = "webgl", target_arch = "wasm32"))
+        ))]
+        {
+            if !app.is_plugin_added::<DisocclusionPlugin>() {
+                app.add_plugins(DisocclusionPlugin);
+            }
+            app.add_systems(Update, add_disocclusion_settings);
+        }
+
+        app.add_plugins((
+            ExtractResourcePlugin::<BlueNoise>::default(),
+            ViewHistoryPlugin,
+            SSGIForwardPlugin,
+            CopyFramePlugin,
+            PrepassDownsamplePlugin,
+            SSGISamplePlugin,
+            SSGIGenerateSHPlugin,
+            SSGIResolvePlugin,
+            // After the SH & resolve plugins, for the nodes to be ordered between them
+            SSGIDenoisePlugin,
+            SSGIDebugViewPlugin,
+        ));
+
+        #[cfg(feature = "serde")]
+        app.add_plugins(settings_asset::SSGISettingsAssetPlugin);
     }
 }
 
+#[cfg(all(
+    feature = "disocclusion",
+    not(all(feature = "webgl", target_arch = "wasm32"))
+))]
 fn add_disocclusion_settings(
     mut commands: Commands,
     query: Query<Entity, (With<SSGIPass>, Without<DisocclusionSettings>)>,
@@ -89,6 +137,23 @@ pub struct SSGIBundle {
     pub motion_vector_prepass: MotionVectorPrepass,
 }
 
+/// Bundle to apply SSGI on a forward rendered camera.
+/// Meshes need to use [`forward::SSGIStandardMaterial`] to receive the indirect light.
+/// Only the diffuse is applied, without a gbuffer there's no roughness to resolve the indirect
+/// specular with, so [`SSGIResolve::specular`] has no effect.
+#[derive(Bundle, Default)]
+pub struct SSGIForwardBundle {
+    pub copy_frame: CopyFrame,
+    pub prepass_downsample: PrepassDownsample,
+    pub ssgi_pass: SSGIPass,
+    pub ssgi_generate_sh: SSGIGenerateSH,
+    pub ssgi_resolve: SSGIResolve,
+    pub forward: SSGIForward,
+    pub normal_prepass: NormalPrepass,
+    pub depth_prepass: DepthPrepass,
+    pub motion_vector_prepass: MotionVectorPrepass,
+}
+
 #[derive(Resource, ExtractResource, Clone)]
 pub struct BlueNoise(pub Handle<Image>);
 
@@ -19,6 +19,7 @@ use bevy::prelude::EnvironmentMapLight;
 use bevy::render::render_graph::RenderLabel;
 use bevy::render::texture::BevyDefault;
 use bevy::render::{
+    camera::ExtractedCamera,
     extract_component::{
         ComponentUniforms, ExtractComponent, ExtractComponentPlugin, UniformComponentPlugin,
     },
@@ -35,11 +36,10 @@ use bevy::render::{
     render_graph::RenderGraphApp, render_resource::*, view::ExtractedView, RenderApp,
 };
 
-use crate::bind_group_utils::{
-    fsampler_layout_entry, ftexture_layout_entry, linear_sampler, nearest_sampler,
-};
+use crate::bind_group_utils::{fsampler_layout_entry, ftexture_layout_entry, SSGISamplers};
 use crate::copy_frame::PrevFrameTexture;
-use crate::ssgi_resolve::SSGIResolveTextures;
+use crate::ssgi::SSGIPass;
+use crate::ssgi_resolve::{SSGIResolve, SSGIResolveTextures};
 use crate::{
     image, resource, shader_def_uint, BlueNoise, BLUE_NOISE_DIMS, BLUE_NOISE_ENTRY_N,
     BLUE_NOISE_GROUP_N,
@@ -149,7 +149,9 @@ impl Plugin for CustomDeferredPbrLightingPlugin {
             return;
         };
 
-        render_app.init_resource::<DeferredLightingLayout>();
+        render_app
+            .init_resource::<DeferredLightingLayout>()
+            .init_resource::<SSGISamplers>();
     }
 }
 
@@ -171,6 +173,7 @@ impl ViewNode for DeferredOpaquePass3dPbrLightingNode {
         &'static PrevFrameTexture,
         // todo webgl &'static DisocclusionTextures,
         &'static SSGIResolveTextures,
+        &'static ExtractedCamera,
     );
 
     fn run(
@@ -188,6 +191,7 @@ impl ViewNode for DeferredOpaquePass3dPbrLightingNode {
             deferred_lighting_pipeline,
             prev_frame_tex,
             ssgi_resolve,
+            camera,
         ): QueryItem<Self::ViewQuery>,
         world: &World,
     ) -> Result<(), NodeRunError> {
@@ -210,8 +214,7 @@ impl ViewNode for DeferredOpaquePass3dPbrLightingNode {
         };
 
         let blue_noise_tex = image!(images, &resource!(world, BlueNoise).0);
-        let nearest_sampler = nearest_sampler(render_context.render_device());
-        let linear_sampler = linear_sampler(render_context.render_device());
+        let samplers = world.resource::<SSGISamplers>();
 
         let bind_group_1 = render_context.render_device().create_bind_group(
             "deferred_lighting_layout_group_1",
@@ -219,12 +222,13 @@ impl ViewNode for DeferredOpaquePass3dPbrLightingNode {
             &BindGroupEntries::with_indices((
                 (0, deferred_lighting_pass_id_binding),
                 (1, &prev_frame_tex.texture.default_view),
-                (5, &nearest_sampler),
-                (6, &linear_sampler),
+                (5, &samplers.nearest),
+                (6, &samplers.linear),
                 // todo webgl (8, &disocclusion_textures.output.default_view),
                 (BLUE_NOISE_ENTRY_N, &blue_noise_tex.texture_view),
                 // Use write since it's the one  resolve would have just written to
                 (12, &ssgi_resolve.write.default_view),
+                (13, &ssgi_resolve.specular_write.default_view),
             )),
         );
 
@@ -254,6 +258,9 @@ impl ViewNode for DeferredOpaquePass3dPbrLightingNode {
             occlusion_query_set: None,
         });
 
+        if let Some(viewport) = camera.viewport.as_ref() {
+            render_pass.set_camera_viewport(viewport);
+        }
         render_pass.set_render_pipeline(pipeline);
         render_pass.set_bind_group(
             0,
@@ -293,6 +300,7 @@ impl FromWorld for DeferredLightingLayout {
                 // todo webgl ftexture_layout_entry(8, TextureViewDimension::D2), // Disocclusion
                 ftexture_layout_entry(BLUE_NOISE_ENTRY_N, TextureViewDimension::D2Array), // Blue Noise
                 ftexture_layout_entry(12, TextureViewDimension::D2), // SSGI Resolve
+                ftexture_layout_entry(13, TextureViewDimension::D2), // SSGI Resolve Specular
             ],
         );
         let mesh_pipeline = world.resource::<MeshPipeline>().clone();
@@ -325,10 +333,22 @@ pub struct DeferredLightingPipeline {
     pub pipeline_id: CachedRenderPipelineId,
 }
 
+#[derive(PartialEq, Eq, Hash, Clone)]
+pub struct DeferredLightingPipelineKey {
+    mesh_key: MeshPipelineKey,
+    ssgi_specular: bool,
+    ssgi_environment_diffuse: bool,
+}
+
 impl SpecializedRenderPipeline for DeferredLightingLayout {
-    type Key = MeshPipelineKey;
+    type Key = DeferredLightingPipelineKey;
 
     fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
+        let DeferredLightingPipelineKey {
+            mesh_key: key,
+            ssgi_specular,
+            ssgi_environment_diffuse,
+        } = key;
         let mut shader_defs = Vec::new();
 
         // Let the shader code know that it's running in a deferred pipeline.
@@ -349,7 +369,7 @@ impl SpecializedRenderPipeline for DeferredLightingLayout {
             } else if method == MeshPipelineKey::TONEMAP_METHOD_REINHARD_LUMINANCE {
                 shader_defs.push("TONEMAP_METHOD_REINHARD_LUMINANCE".into());
             } else if method == MeshPipelineKey::TONEMAP_METHOD_ACES_FITTED {
-                shader_defs.push("TONEMAP_METHOD_ACES_FITTED ".into());
+                shader_defs.push("TONEMAP_METHOD_ACES_FITTED".into());
             } else if method == MeshPipelineKey::TONEMAP_METHOD_AGX {
                 shader_defs.push("TONEMAP_METHOD_AGX".into());
             } else if method == MeshPipelineKey::TONEMAP_METHOD_SOMEWHAT_BORING_DISPLAY_TRANSFORM {
@@ -402,6 +422,14 @@ impl SpecializedRenderPipeline for DeferredLightingLayout {
         #[cfg(all(feature = "webgl", target_arch = "wasm32"))]
         shader_defs.push("SIXTEEN_BYTE_ALIGNMENT".into());
 
+        if ssgi_specular {
+            shader_defs.push("SSGI_SPECULAR".into());
+        }
+
+        if ssgi_environment_diffuse {
+            shader_defs.push("SSGI_ENVIRONMENT_DIFFUSE".into());
+        }
+
         shader_defs.extend_from_slice(&[
             shader_def_uint!(BLUE_NOISE_GROUP_N),
             shader_def_uint!(BLUE_NOISE_ENTRY_N),
@@ -488,6 +516,8 @@ pub fn prepare_deferred_lighting_pipelines(
             ),
             Has<RenderViewLightProbes<EnvironmentMapLight>>,
             Has<RenderViewLightProbes<IrradianceVolume>>,
+            Option<&SSGIResolve>,
+            Option<&SSGIPass>,
         ),
         With<DeferredPrepass>,
     >,
@@ -502,6 +532,8 @@ pub fn prepare_deferred_lighting_pipelines(
         (normal_prepass, depth_prepass, motion_vector_prepass),
         has_environment_maps,
         has_irradiance_volumes,
+        ssgi_resolve,
+        ssgi_pass,
     ) in &views
     {
         let mut view_key = MeshPipelineKey::from_hdr(view.hdr);
@@ -571,8 +603,16 @@ pub fn prepare_deferred_lighting_pipelines(
             }
         }
 
-        let pipeline_id =
-            pipelines.specialize(&pipeline_cache, &deferred_lighting_layout, view_key);
+        let key = DeferredLightingPipelineKey {
+            mesh_key: view_key,
+            ssgi_specular: ssgi_resolve.is_some_and(|ssgi_resolve| ssgi_resolve.specular > 0.0),
+            // An irradiance volume replaces the environment map diffuse where it has data, the
+            // shader can't tell where that is
+            ssgi_environment_diffuse: has_environment_maps
+                && !has_irradiance_volumes
+                && ssgi_pass.is_some_and(|ssgi_pass| ssgi_pass.environment_fallback > 0.0),
+        };
+        let pipeline_id = pipelines.specialize(&pipeline_cache, &deferred_lighting_layout, key);
 
         commands
             .entity(entity)
@@ -1,16 +1,21 @@
 #import bevy_pbr::pbr_deferred_functions::pbr_input_from_deferred_gbuffer
 #import bevy_pbr::pbr_deferred_types::unpack_unorm3x4_plus_unorm_20_
 #import bevy_pbr::utils::{octahedral_encode, octahedral_decode}
+#import bevy_pbr::mesh_view_bindings::view
 
+#ifdef FORWARD_PREPASS
+@group(0) @binding(102) var normal_prepass_texture: texture_2d<f32>;
+#else
 @group(0) @binding(101) var deferred_prepass_texture: texture_2d<u32>;
-//@group(0) @binding(102) var normal_prepass_texture: texture_2d<f32>;
+#endif
 @group(0) @binding(103) var depth_prepass_texture: texture_depth_2d;
 @group(0) @binding(104) var motion_prepass_texture: texture_2d<f32>;
 
 #import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
 
 struct FragmentOutput {
-    @location(0) depth: f32,
+    // Both the closest and farthest depth for DepthReduction::MinMax
+    @location(0) depth: vec2<f32>,
     @location(1) normals: vec2<f32>,
     @location(2) motion: vec2<f32>,
 }
@@ -19,24 +24,30 @@ struct FragmentOutput {
 fn fragment(in: FullscreenVertexOutput) -> FragmentOutput {
     var out: FragmentOutput;
 
-    var frag_coord = vec4(in.position.xy, 0.0, 0.0);
+    // The prepass textures are the size of the render target, the output the size of the viewport
+    var frag_coord = vec4(in.position.xy + view.viewport.xy, 0.0, 0.0);
+    let ifrag_coord = vec2<i32>(frag_coord.xy);
 
-    let deferred_data = textureLoad(deferred_prepass_texture, vec2<i32>(frag_coord.xy), 0);
+#ifdef FORWARD_PREPASS
+    frag_coord.z = textureLoad(depth_prepass_texture, ifrag_coord, 0);
+
+    out.depth = vec2(frag_coord.z);
+    // The normal prepass stores world space normals as n * 0.5 + 0.5
+    out.normals = octahedral_encode(normalize(textureLoad(normal_prepass_texture, ifrag_coord, 0).xyz * 2.0 - 1.0));
+#else
+    let deferred_data = textureLoad(deferred_prepass_texture, ifrag_coord, 0);
 
 #ifdef WEBGL2
     frag_coord.z = unpack_unorm3x4_plus_unorm_20_(deferred_data.b).w;
 #else
-    frag_coord.z = textureLoad(depth_prepass_texture, vec2<i32>(in.position.xy), 0);
+    frag_coord.z = textureLoad(depth_prepass_texture, ifrag_coord, 0);
 #endif
 
     var pbr_input = pbr_input_from_deferred_gbuffer(frag_coord, deferred_data);
 
-    out.depth = frag_coord.z;
-//#ifdef WEBGL2
+    out.depth = vec2(frag_coord.z);
     out.normals = octahedral_encode(pbr_input.N);
-//#else
-//    out.normals = octahedral_encode(textureLoad(normal_prepass_texture, vec2<i32>(in.position.xy), 0).xyz * 2.0 - 1.0);
-//#endif
-    out.motion = textureLoad(motion_prepass_texture, vec2<i32>(in.position.xy), 0).xy;
+#endif // FORWARD_PREPASS
+    out.motion = textureLoad(motion_prepass_texture, ifrag_coord, 0).xy;
     return out;
 }
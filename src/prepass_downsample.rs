@@ -1,50 +1,138 @@
+use std::ops::Range;
+
 use bevy::{
     asset::load_internal_asset,
-    core::FrameCount,
     core_pipeline::{
         core_3d::graph::{Core3d, Node3d},
         fullscreen_vertex_shader::fullscreen_shader_vertex_state,
-        prepass::ViewPrepassTextures,
+        prepass::{
+            DeferredPrepass, DepthPrepass, MotionVectorPrepass, NormalPrepass, ViewPrepassTextures,
+        },
+    },
+    ecs::entity::EntityHashSet,
+    pbr::{
+        extract_meshes, DrawMesh, Lightmap, MeshPipeline, MeshPipelineKey, RenderMeshInstances,
+        SetMeshBindGroup, SetMeshViewBindGroup,
     },
     prelude::*,
     render::{
+        batching::batch_and_prepare_render_phase,
         camera::ExtractedCamera,
         extract_component::{ExtractComponent, ExtractComponentPlugin},
+        mesh::MeshVertexBufferLayout,
+        render_asset::RenderAssets,
         render_graph::{Node, NodeRunError, RenderGraphApp, RenderGraphContext, RenderLabel},
+        render_phase::{
+            sort_phase_system, AddRenderCommand, CachedRenderPipelinePhaseItem, DrawFunctionId,
+            DrawFunctions, PhaseItem, RenderPhase, SetItemPipeline,
+        },
         render_resource::{
-            BindGroupEntries, BindGroupLayout, CachedRenderPipelineId, ColorTargetState,
-            ColorWrites, Extent3d, FragmentState, MultisampleState, Operations, PipelineCache,
-            PrimitiveState, RenderPassColorAttachment, RenderPassDescriptor,
-            RenderPipelineDescriptor, Sampler, TextureAspect, TextureDescriptor, TextureDimension,
-            TextureFormat, TextureUsages, TextureViewDescriptor, TextureViewDimension,
+            BindGroupEntries, BindGroupEntry, BindGroupLayout, BindGroupLayoutEntry,
+            BindingResource, CachedComputePipelineId, CachedRenderPipelineId, ColorTargetState,
+            ColorWrites, CompareFunction, ComputePassDescriptor, ComputePipeline,
+            ComputePipelineDescriptor, DepthBiasState, DepthStencilState, Extent3d, Face,
+            FragmentState, LoadOp, MultisampleState, Operations, PipelineCache, PrimitiveState,
+            RenderPassColorAttachment, RenderPassDepthStencilAttachment, RenderPassDescriptor,
+            RenderPipelineDescriptor, Sampler, ShaderDefVal, ShaderStages,
+            SpecializedComputePipeline, SpecializedComputePipelines, SpecializedMeshPipeline,
+            SpecializedMeshPipelineError, SpecializedMeshPipelines, SpecializedRenderPipeline,
+            SpecializedRenderPipelines, StencilState, StoreOp, TextureAspect, TextureDescriptor,
+            TextureDimension, TextureFormat, TextureUsages, TextureView, TextureViewDescriptor,
+            TextureViewDimension,
         },
-        renderer::{RenderContext, RenderDevice},
+        renderer::{RenderAdapter, RenderContext, RenderDevice},
+        settings::WgpuFeatures,
         texture::{CachedTexture, TextureCache},
-        view::{ExtractedView, ViewTarget, ViewUniformOffset},
-        Render, RenderApp, RenderSet,
+        view::{ExtractedView, ViewTarget, ViewUniformOffset, VisibleEntities},
+        Extract, ExtractSchedule, Render, RenderApp, RenderSet,
     },
+    utils::{nonmax::NonMaxU32, HashMap},
 };
 
 use crate::bind_group_utils::{
     dtexture_layout_entry, fsampler_layout_entry, ftexture_layout_entry, globals_binding,
-    globals_layout_entry, nearest_sampler, utexture_layout_entry, view_binding, view_layout_entry,
+    globals_layout_entry, nearest_sampler, storage_texture_layout_entry, utexture_layout_entry,
+    view_binding, view_layout_entry,
 };
+use crate::ssgi::SSGIPass;
+use crate::view_history::ViewHistories;
 
 #[cfg(all(feature = "webgl", target_arch = "wasm32"))]
 const DOWNSAMPLE_NORMALS_FORMAT: TextureFormat = TextureFormat::Rg16Float;
 #[cfg(not(all(feature = "webgl", target_arch = "wasm32")))]
 const DOWNSAMPLE_NORMALS_FORMAT: TextureFormat = TextureFormat::Rg16Unorm;
 const DOWNSAMPLE_DEPTH_FORMAT: TextureFormat = TextureFormat::R32Float;
+const DOWNSAMPLE_DEPTH_MIN_MAX_FORMAT: TextureFormat = TextureFormat::Rg32Float;
 const DOWNSAMPLE_MOTION_FORMAT: TextureFormat = TextureFormat::Rg16Float;
+/// Format of [`PrepassDownsampleTextures::back_face_depth`]
+pub const BACK_FACE_DEPTH_FORMAT: TextureFormat = TextureFormat::Depth32Float;
 
 #[derive(Component, ExtractComponent, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct PrepassDownsample {
+    /// Not configurable, the downsample node and the SSGI mip ranges assume it's within what the
+    /// views support, so it isn't read from settings either
+    #[cfg_attr(feature = "serde", serde(skip))]
     mip_levels: u8,
+    /// How the depth of each 2x2 block is reduced for the next mip
+    pub depth_reduction: DepthReduction,
 }
 
 impl Default for PrepassDownsample {
     fn default() -> Self {
-        PrepassDownsample { mip_levels: 5 }
+        PrepassDownsample {
+            mip_levels: 5,
+            depth_reduction: DepthReduction::default(),
+        }
+    }
+}
+
+/// How each 2x2 block of depth is reduced to one texel of the next mip. The normals and motion
+/// are taken from the same texel as the depth.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DepthReduction {
+    /// The same texel of each block, like point sampling
+    #[default]
+    Point,
+    /// The closest to the camera, thin geometry stays in the higher mips
+    Closest,
+    /// The farthest from the camera, thin geometry disappears in the higher mips
+    Farthest,
+    /// Alternates between the closest and farthest in a checkerboard, so the higher mips keep
+    /// some of both thin geometry and what's behind it
+    Checkerboard,
+    /// The closest in the first channel and the farthest in a second one. With a
+    /// [`crate::ssgi::SSGIThickness::Constant`] thickness, the SSGI march uses the pair as the
+    /// minimum thickness of each sample, so edges occlude what's between their depths.
+    /// Twice the memory of the other reductions.
+    MinMax,
+}
+
+impl DepthReduction {
+    /// Format of the depth mips, [`DepthReduction::MinMax`] needs two channels
+    pub fn depth_format(&self) -> TextureFormat {
+        match self {
+            DepthReduction::MinMax => DOWNSAMPLE_DEPTH_MIN_MAX_FORMAT,
+            _ => DOWNSAMPLE_DEPTH_FORMAT,
+        }
+    }
+
+    fn shader_def(&self) -> ShaderDefVal {
+        match self {
+            DepthReduction::Point => "DEPTH_REDUCTION_POINT".into(),
+            DepthReduction::Closest => "DEPTH_REDUCTION_CLOSEST".into(),
+            DepthReduction::Farthest => "DEPTH_REDUCTION_FARTHEST".into(),
+            DepthReduction::Checkerboard => "DEPTH_REDUCTION_CHECKERBOARD".into(),
+            DepthReduction::MinMax => "DEPTH_REDUCTION_MIN_MAX".into(),
+        }
+    }
+}
+
+impl PrepassDownsample {
+    pub fn mip_levels(&self) -> u8 {
+        self.mip_levels
     }
 }
 
@@ -53,6 +141,14 @@ pub struct PrepassDownsamplePlugin;
 
 const CONVERT_SHADER_HANDLE: Handle<Shader> = Handle::weak_from_u128(163429348570394285);
 const DOWNSAMPLE_SHADER_HANDLE: Handle<Shader> = Handle::weak_from_u128(329046523092834572);
+const SPD_SHADER_HANDLE: Handle<Shader> = Handle::weak_from_u128(582093475029384756);
+const DEPTH_REDUCTION_SHADER_HANDLE: Handle<Shader> = Handle::weak_from_u128(730495827340598237);
+const BACK_FACE_DEPTH_SHADER_HANDLE: Handle<Shader> = Handle::weak_from_u128(209384750293847561);
+
+/// Each workgroup of the single pass downsample reduces a 64x64 tile to 1x1, so 7 mips at most
+pub const SPD_MAX_MIP_LEVELS: u32 = 7;
+/// Size of the mip 0 tile each workgroup of the single pass downsample reduces
+const SPD_TILE_SIZE: u32 = 64;
 impl Plugin for PrepassDownsamplePlugin {
     fn build(&self, app: &mut App) {
         load_internal_asset!(
@@ -67,6 +163,24 @@ impl Plugin for PrepassDownsamplePlugin {
             "prepass_downsample.wgsl",
             Shader::from_wgsl
         );
+        load_internal_asset!(
+            app,
+            DEPTH_REDUCTION_SHADER_HANDLE,
+            "depth_reduction.wgsl",
+            Shader::from_wgsl
+        );
+        load_internal_asset!(
+            app,
+            SPD_SHADER_HANDLE,
+            "prepass_downsample_spd.wgsl",
+            Shader::from_wgsl
+        );
+        load_internal_asset!(
+            app,
+            BACK_FACE_DEPTH_SHADER_HANDLE,
+            "back_face_depth.wgsl",
+            Shader::from_wgsl
+        );
         app.add_plugins(ExtractComponentPlugin::<PrepassDownsample>::default());
         let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
             return;
@@ -74,11 +188,41 @@ impl Plugin for PrepassDownsamplePlugin {
 
         render_app
             .add_systems(Render, prepare_textures.in_set(RenderSet::PrepareResources))
+            .add_systems(Render, prepare_pipelines.in_set(RenderSet::Prepare))
+            .init_resource::<SpecializedRenderPipelines<PrepassDownsamplePipeline>>()
+            .init_resource::<SpecializedComputePipelines<SinglePassDownsampleLayouts>>()
             .add_render_graph_node::<DownsampleNode>(Core3d, DownsampleLabel)
             .add_render_graph_edges(
                 Core3d,
                 (Node3d::EndPrepasses, DownsampleLabel, Node3d::StartMainPass),
             );
+
+        render_app
+            .init_resource::<DrawFunctions<BackFaceDepth3d>>()
+            .init_resource::<SpecializedMeshPipelines<BackFaceDepthPipeline>>()
+            .init_resource::<BackFaceLightmaps>()
+            .add_render_command::<BackFaceDepth3d, DrawBackFaceDepth>()
+            .add_systems(
+                ExtractSchedule,
+                (
+                    extract_back_face_phases,
+                    extract_back_face_lightmaps.after(extract_meshes),
+                ),
+            )
+            .add_systems(
+                Render,
+                (
+                    queue_back_face_meshes.in_set(RenderSet::QueueMeshes),
+                    sort_phase_system::<BackFaceDepth3d>.in_set(RenderSet::PhaseSort),
+                    batch_and_prepare_render_phase::<BackFaceDepth3d, MeshPipeline>
+                        .in_set(RenderSet::PrepareResources),
+                ),
+            )
+            .add_render_graph_node::<BackFaceDepthNode>(Core3d, BackFaceDepthLabel)
+            .add_render_graph_edges(
+                Core3d,
+                (Node3d::EndPrepasses, BackFaceDepthLabel, DownsampleLabel),
+            );
     }
 
     fn finish(&self, app: &mut App) {
@@ -86,7 +230,10 @@ impl Plugin for PrepassDownsamplePlugin {
             Ok(render_app) => render_app,
             Err(_) => return,
         };
-        render_app.init_resource::<PrepassDownsamplePipeline>();
+        render_app
+            .init_resource::<PrepassDownsamplePipeline>()
+            .init_resource::<SinglePassDownsampleLayouts>()
+            .init_resource::<BackFaceDepthPipeline>();
     }
 }
 
@@ -101,6 +248,7 @@ pub struct DownsampleNode {
             &'static ViewPrepassTextures,
             &'static PrepassDownsampleTextures,
             &'static PrepassDownsample,
+            &'static PrepassDownsamplePipelines,
         ),
         With<ExtractedView>,
     >,
@@ -137,6 +285,7 @@ impl Node for DownsampleNode {
             prepass_textures,
             downsample_textures,
             prepass_downsample,
+            pipelines,
         )) = self.query.get_manual(world, view_entity)
         else {
             return Ok(());
@@ -150,18 +299,18 @@ impl Node for DownsampleNode {
         let copy_frame_pipeline = world.resource::<PrepassDownsamplePipeline>();
         let pipeline_cache = world.resource::<PipelineCache>();
 
-        let Some(convert_pipeline) =
-            pipeline_cache.get_render_pipeline(copy_frame_pipeline.convert_pipeline_id)
-        else {
+        // Without a deferred gbuffer the normals come from the normal prepass instead
+        let forward = prepass_textures.deferred.is_none();
+
+        let Some(convert_pipeline) = pipeline_cache.get_render_pipeline(pipelines.convert) else {
             return Ok(());
         };
-        let Some(downsample_pipeline) =
-            pipeline_cache.get_render_pipeline(copy_frame_pipeline.downsample_pipeline_id)
+        let Some(downsample_pipeline) = pipeline_cache.get_render_pipeline(pipelines.downsample)
         else {
             return Ok(());
         };
+        let depth_format = prepass_downsample.depth_reduction.depth_format();
 
-        let deferred_binding = prepass_textures.deferred.as_ref().unwrap();
         let depth_binding = prepass_textures.depth.as_ref().unwrap();
         let depth_view = depth_binding
             .texture
@@ -183,7 +332,7 @@ impl Node for DownsampleNode {
                         .texture
                         .create_view(&TextureViewDescriptor {
                             label: Some("MIP_DST_DEPTH"),
-                            format: Some(DOWNSAMPLE_DEPTH_FORMAT),
+                            format: Some(depth_format),
                             dimension: Some(TextureViewDimension::D2),
                             aspect: TextureAspect::All,
                             base_mip_level: 0,
@@ -219,19 +368,36 @@ impl Node for DownsampleNode {
                         }),
                 )
             };
-            let bind_group = render_context.render_device().create_bind_group(
-                "post_process_bind_group",
-                &copy_frame_pipeline.convert_layout,
-                // It's important for this to match the BindGroupLayout defined in the PostProcessPipeline
-                &BindGroupEntries::with_indices((
-                    (0, view_binding(world)),
-                    (9, globals_binding(world)),
-                    (101, &deferred_binding.texture.default_view),
-                    // todo webgl (102, &normal_binding.default_view),
-                    (103, &depth_view),
-                    (104, &motion_bindings.texture.default_view),
-                )),
-            );
+            let bind_group = if forward {
+                let Some(normal_binding) = prepass_textures.normal.as_ref() else {
+                    return Ok(());
+                };
+                render_context.render_device().create_bind_group(
+                    "post_process_bind_group",
+                    &copy_frame_pipeline.forward_convert_layout,
+                    &BindGroupEntries::with_indices((
+                        (0, view_binding(world)),
+                        (9, globals_binding(world)),
+                        (102, &normal_binding.texture.default_view),
+                        (103, &depth_view),
+                        (104, &motion_bindings.texture.default_view),
+                    )),
+                )
+            } else {
+                let deferred_binding = prepass_textures.deferred.as_ref().unwrap();
+                render_context.render_device().create_bind_group(
+                    "post_process_bind_group",
+                    &copy_frame_pipeline.convert_layout,
+                    // It's important for this to match the BindGroupLayout defined in the PostProcessPipeline
+                    &BindGroupEntries::with_indices((
+                        (0, view_binding(world)),
+                        (9, globals_binding(world)),
+                        (101, &deferred_binding.texture.default_view),
+                        (103, &depth_view),
+                        (104, &motion_bindings.texture.default_view),
+                    )),
+                )
+            };
             let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
                 label: Some("depth_normals_convert_pass"),
                 color_attachments: &[
@@ -260,6 +426,23 @@ impl Node for DownsampleNode {
             render_pass.draw(0..3, 0..1);
         }
 
+        // Falls back to the multi-pass downsample while the pipeline is compiling
+        if let Some(spd_pipeline) = pipelines
+            .single_pass
+            .and_then(|pipeline_id| pipeline_cache.get_compute_pipeline(pipeline_id))
+        {
+            let spd_layouts = world.resource::<SinglePassDownsampleLayouts>();
+            run_single_pass_downsample(
+                render_context,
+                spd_layouts.layout(mip_levels, depth_format),
+                spd_pipeline,
+                downsample_textures,
+                mip_levels,
+                depth_format,
+            );
+            return Ok(());
+        }
+
         #[cfg(all(feature = "webgl", target_arch = "wasm32"))]
         {
             let depth_dst_view = downsample_textures.temp_depth.default_view.clone();
@@ -324,7 +507,7 @@ impl Node for DownsampleNode {
                     .texture
                     .create_view(&TextureViewDescriptor {
                         label: Some("MIP_SRC_DEPTH"),
-                        format: Some(DOWNSAMPLE_DEPTH_FORMAT),
+                        format: Some(depth_format),
                         dimension: Some(TextureViewDimension::D2),
                         aspect: TextureAspect::All,
                         base_mip_level: i,
@@ -369,7 +552,7 @@ impl Node for DownsampleNode {
                     .texture
                     .create_view(&TextureViewDescriptor {
                         label: Some("MIP_DST_DEPTH"),
-                        format: Some(DOWNSAMPLE_DEPTH_FORMAT),
+                        format: Some(depth_format),
                         dimension: Some(TextureViewDimension::D2),
                         aspect: TextureAspect::All,
                         base_mip_level: i + 1,
@@ -452,6 +635,305 @@ impl Node for DownsampleNode {
     }
 }
 
+#[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
+pub struct BackFaceDepthLabel;
+
+/// Renders the back faces of the meshes into [`PrepassDownsampleTextures::back_face_depth`],
+/// for cameras with [`SSGIPass::back_face_prepass`]
+pub struct BackFaceDepthNode {
+    query: QueryState<
+        (
+            &'static RenderPhase<BackFaceDepth3d>,
+            &'static PrepassDownsampleTextures,
+        ),
+        With<ExtractedView>,
+    >,
+}
+
+impl FromWorld for BackFaceDepthNode {
+    fn from_world(world: &mut World) -> Self {
+        Self {
+            query: QueryState::new(world),
+        }
+    }
+}
+
+impl Node for BackFaceDepthNode {
+    fn update(&mut self, world: &mut World) {
+        self.query.update_archetypes(world);
+    }
+
+    fn run<'w>(
+        &self,
+        graph_context: &mut RenderGraphContext,
+        render_context: &mut RenderContext<'w>,
+        world: &'w World,
+    ) -> Result<(), NodeRunError> {
+        let view_entity = graph_context.view_entity();
+
+        let Ok((back_face_phase, downsample_textures)) = self.query.get_manual(world, view_entity)
+        else {
+            return Ok(());
+        };
+        let Some(back_face_depth) = &downsample_textures.back_face_depth else {
+            return Ok(());
+        };
+
+        // The texture is the size of the viewport, so the camera viewport isn't set
+        let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+            label: Some("back_face_depth_pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                view: &back_face_depth.default_view,
+                // Reversed Z, 0.0 is nothing behind the front faces
+                depth_ops: Some(Operations {
+                    load: LoadOp::Clear(0.0),
+                    store: StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        back_face_phase.render(&mut render_pass, world, view_entity);
+
+        Ok(())
+    }
+}
+
+/// Back faces of the meshes visible to a camera with [`SSGIPass::back_face_prepass`]
+pub struct BackFaceDepth3d {
+    pub entity: Entity,
+    pub asset_id: AssetId<Mesh>,
+    pub pipeline_id: CachedRenderPipelineId,
+    pub draw_function: DrawFunctionId,
+    pub batch_range: Range<u32>,
+    pub dynamic_offset: Option<NonMaxU32>,
+}
+
+impl PhaseItem for BackFaceDepth3d {
+    type SortKey = (usize, AssetId<Mesh>);
+
+    #[inline]
+    fn entity(&self) -> Entity {
+        self.entity
+    }
+
+    #[inline]
+    fn sort_key(&self) -> Self::SortKey {
+        // Same as the opaque prepass, by pipeline then mesh so more of them are batched
+        (self.pipeline_id.id(), self.asset_id)
+    }
+
+    #[inline]
+    fn draw_function(&self) -> DrawFunctionId {
+        self.draw_function
+    }
+
+    #[inline]
+    fn sort(items: &mut [Self]) {
+        items.sort_unstable_by_key(Self::sort_key);
+    }
+
+    #[inline]
+    fn batch_range(&self) -> &Range<u32> {
+        &self.batch_range
+    }
+
+    #[inline]
+    fn batch_range_mut(&mut self) -> &mut Range<u32> {
+        &mut self.batch_range
+    }
+
+    #[inline]
+    fn dynamic_offset(&self) -> Option<NonMaxU32> {
+        self.dynamic_offset
+    }
+
+    #[inline]
+    fn dynamic_offset_mut(&mut self) -> &mut Option<NonMaxU32> {
+        &mut self.dynamic_offset
+    }
+}
+
+impl CachedRenderPipelinePhaseItem for BackFaceDepth3d {
+    #[inline]
+    fn cached_pipeline(&self) -> CachedRenderPipelineId {
+        self.pipeline_id
+    }
+}
+
+type DrawBackFaceDepth = (
+    SetItemPipeline,
+    SetMeshViewBindGroup<0>,
+    SetMeshBindGroup<1>,
+    DrawMesh,
+);
+
+/// The mesh pipeline with a depth only vertex shader that culls the front faces instead, so it
+/// uses the same bind groups as the main pass. Morph targets aren't applied.
+#[derive(Resource)]
+struct BackFaceDepthPipeline {
+    mesh_pipeline: MeshPipeline,
+}
+
+impl FromWorld for BackFaceDepthPipeline {
+    fn from_world(world: &mut World) -> Self {
+        Self {
+            mesh_pipeline: world.resource::<MeshPipeline>().clone(),
+        }
+    }
+}
+
+impl SpecializedMeshPipeline for BackFaceDepthPipeline {
+    type Key = MeshPipelineKey;
+
+    fn specialize(
+        &self,
+        key: Self::Key,
+        layout: &MeshVertexBufferLayout,
+    ) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
+        let mut descriptor = self.mesh_pipeline.specialize(key, layout)?;
+        descriptor.label = Some("back_face_depth_pipeline".into());
+        descriptor.vertex.shader = BACK_FACE_DEPTH_SHADER_HANDLE;
+        descriptor.fragment = None;
+        descriptor.primitive.cull_mode = Some(Face::Front);
+        // Keeps the closest back face
+        descriptor.depth_stencil = Some(DepthStencilState {
+            format: BACK_FACE_DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: CompareFunction::GreaterEqual,
+            stencil: StencilState::default(),
+            bias: DepthBiasState::default(),
+        });
+        // The key has the view's MSAA so the view layout matches, but this pass isn't multisampled
+        descriptor.multisample = MultisampleState::default();
+        Ok(descriptor)
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn extract_back_face_phases(
+    mut commands: Commands,
+    cameras: Extract<Query<(Entity, &Camera, &SSGIPass), With<Camera3d>>>,
+) {
+    for (entity, camera, ssgi_pass) in &cameras {
+        if camera.is_active && ssgi_pass.back_face_prepass {
+            commands
+                .get_or_spawn(entity)
+                .insert(RenderPhase::<BackFaceDepth3d>::default());
+        }
+    }
+}
+
+/// Meshes that [`SetMeshBindGroup`] binds a lightmap for, so they need
+/// [`MeshPipelineKey::LIGHTMAPPED`]. Bevy's own list of them isn't public, so it's made with the
+/// same checks.
+#[derive(Resource, Default)]
+struct BackFaceLightmaps(EntityHashSet);
+
+fn extract_back_face_lightmaps(
+    mut back_face_lightmaps: ResMut<BackFaceLightmaps>,
+    lightmaps: Extract<Query<(Entity, &ViewVisibility, &Lightmap)>>,
+    render_mesh_instances: Res<RenderMeshInstances>,
+    images: Res<RenderAssets<Image>>,
+    meshes: Res<RenderAssets<Mesh>>,
+) {
+    back_face_lightmaps.0.clear();
+    for (entity, view_visibility, lightmap) in &lightmaps {
+        let loaded = images.get(&lightmap.image).is_some()
+            && render_mesh_instances
+                .get(&entity)
+                .and_then(|mesh_instance| meshes.get(mesh_instance.mesh_asset_id))
+                .is_some_and(|mesh| mesh.layout.contains(Mesh::ATTRIBUTE_UV_1.id));
+        if view_visibility.get() && loaded {
+            back_face_lightmaps.0.insert(entity);
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn queue_back_face_meshes(
+    draw_functions: Res<DrawFunctions<BackFaceDepth3d>>,
+    pipeline: Res<BackFaceDepthPipeline>,
+    mut pipelines: ResMut<SpecializedMeshPipelines<BackFaceDepthPipeline>>,
+    pipeline_cache: Res<PipelineCache>,
+    msaa: Res<Msaa>,
+    render_meshes: Res<RenderAssets<Mesh>>,
+    render_mesh_instances: Res<RenderMeshInstances>,
+    lightmaps: Res<BackFaceLightmaps>,
+    mut views: Query<(
+        &VisibleEntities,
+        &mut RenderPhase<BackFaceDepth3d>,
+        Has<DepthPrepass>,
+        Has<NormalPrepass>,
+        Has<MotionVectorPrepass>,
+        Has<DeferredPrepass>,
+    )>,
+) {
+    let draw_function = draw_functions.read().id::<DrawBackFaceDepth>();
+
+    for (
+        visible_entities,
+        mut back_face_phase,
+        depth_prepass,
+        normal_prepass,
+        motion_vector_prepass,
+        deferred_prepass,
+    ) in &mut views
+    {
+        // Has to match the layout of the view's mesh view bind group
+        let mut view_key = MeshPipelineKey::from_msaa_samples(msaa.samples());
+        view_key.set(MeshPipelineKey::DEPTH_PREPASS, depth_prepass);
+        view_key.set(MeshPipelineKey::NORMAL_PREPASS, normal_prepass);
+        view_key.set(
+            MeshPipelineKey::MOTION_VECTOR_PREPASS,
+            motion_vector_prepass,
+        );
+        view_key.set(MeshPipelineKey::DEFERRED_PREPASS, deferred_prepass);
+
+        for visible_entity in &visible_entities.entities {
+            let Some(mesh_instance) = render_mesh_instances.get(visible_entity) else {
+                continue;
+            };
+            // NotShadowCaster leaves out meshes that shouldn't occlude, like glass
+            if !mesh_instance.shadow_caster {
+                continue;
+            }
+            let Some(mesh) = render_meshes.get(mesh_instance.mesh_asset_id) else {
+                continue;
+            };
+
+            let mut mesh_key =
+                MeshPipelineKey::from_primitive_topology(mesh.primitive_topology) | view_key;
+            if mesh.morph_targets.is_some() {
+                mesh_key |= MeshPipelineKey::MORPH_TARGETS;
+            }
+            if lightmaps.0.contains(visible_entity) {
+                mesh_key |= MeshPipelineKey::LIGHTMAPPED;
+            }
+
+            let pipeline_id =
+                match pipelines.specialize(&pipeline_cache, &pipeline, mesh_key, &mesh.layout) {
+                    Ok(pipeline_id) => pipeline_id,
+                    Err(err) => {
+                        error!("{}", err);
+                        continue;
+                    }
+                };
+
+            back_face_phase.add(BackFaceDepth3d {
+                entity: *visible_entity,
+                asset_id: mesh_instance.mesh_asset_id,
+                pipeline_id,
+                draw_function,
+                batch_range: 0..1,
+                dynamic_offset: None,
+            });
+        }
+    }
+}
+
 #[derive(Component, Clone)]
 pub struct PrepassDownsampleTextures {
     pub normals: CachedTexture,
@@ -465,17 +947,19 @@ pub struct PrepassDownsampleTextures {
     #[cfg(all(feature = "webgl", target_arch = "wasm32"))]
     pub temp_motion: CachedTexture,
     pub histry_depth: CachedTexture,
+    /// Depth of the closest back faces, the far side of each occluder. Only there with
+    /// [`SSGIPass::back_face_prepass`]
+    pub back_face_depth: Option<CachedTexture>,
 }
 
 #[derive(Resource)]
 struct PrepassDownsamplePipeline {
     convert_layout: BindGroupLayout,
+    forward_convert_layout: BindGroupLayout,
     downsample_layout: BindGroupLayout,
     sampler1: Sampler,
     sampler2: Sampler,
     sampler3: Sampler,
-    convert_pipeline_id: CachedRenderPipelineId,
-    downsample_pipeline_id: CachedRenderPipelineId,
 }
 
 impl FromWorld for PrepassDownsamplePipeline {
@@ -490,55 +974,23 @@ impl FromWorld for PrepassDownsamplePipeline {
             view_layout_entry(0),
             globals_layout_entry(9),
             utexture_layout_entry(101, TextureViewDimension::D2),
-            // todo webgl ftexture_layout_entry(102, TextureViewDimension::D2),
             dtexture_layout_entry(103, TextureViewDimension::D2),
             ftexture_layout_entry(104, TextureViewDimension::D2),
         ];
 
-        let convert_layout = world
-            .resource::<RenderDevice>()
-            .create_bind_group_layout(Some("copy_frame_bind_group_layout"), &entries);
-
-        #[allow(unused_mut)]
-        let mut shader_defs = Vec::new();
+        let convert_layout =
+            render_device.create_bind_group_layout(Some("copy_frame_bind_group_layout"), &entries);
 
-        #[cfg(all(feature = "webgl", target_arch = "wasm32"))]
-        shader_defs.push("WEBGL2".into());
+        let entries = vec![
+            view_layout_entry(0),
+            globals_layout_entry(9),
+            ftexture_layout_entry(102, TextureViewDimension::D2), // Normal prepass
+            dtexture_layout_entry(103, TextureViewDimension::D2),
+            ftexture_layout_entry(104, TextureViewDimension::D2),
+        ];
 
-        let convert_pipeline_id =
-            world
-                .resource_mut::<PipelineCache>()
-                .queue_render_pipeline(RenderPipelineDescriptor {
-                    label: Some("prepass_downsample_convert_pipeline".into()),
-                    layout: vec![convert_layout.clone()],
-                    vertex: fullscreen_shader_vertex_state(),
-                    fragment: Some(FragmentState {
-                        shader: CONVERT_SHADER_HANDLE,
-                        shader_defs,
-                        entry_point: "fragment".into(),
-                        targets: vec![
-                            Some(ColorTargetState {
-                                format: DOWNSAMPLE_DEPTH_FORMAT,
-                                blend: None,
-                                write_mask: ColorWrites::ALL,
-                            }),
-                            Some(ColorTargetState {
-                                format: DOWNSAMPLE_NORMALS_FORMAT,
-                                blend: None,
-                                write_mask: ColorWrites::ALL,
-                            }),
-                            Some(ColorTargetState {
-                                format: DOWNSAMPLE_MOTION_FORMAT,
-                                blend: None,
-                                write_mask: ColorWrites::ALL,
-                            }),
-                        ],
-                    }),
-                    primitive: PrimitiveState::default(),
-                    depth_stencil: None,
-                    multisample: MultisampleState::default(),
-                    push_constant_ranges: vec![],
-                });
+        let forward_convert_layout = render_device
+            .create_bind_group_layout(Some("forward_convert_bind_group_layout"), &entries);
 
         let entries = vec![
             ftexture_layout_entry(0, TextureViewDimension::D2),
@@ -549,65 +1001,396 @@ impl FromWorld for PrepassDownsamplePipeline {
             fsampler_layout_entry(5),
         ];
 
-        let downsample_layout = world
-            .resource::<RenderDevice>()
-            .create_bind_group_layout(Some("copy_frame_bind_group_layout"), &entries);
-
-        let downsample_pipeline_id =
-            world
-                .resource_mut::<PipelineCache>()
-                .queue_render_pipeline(RenderPipelineDescriptor {
-                    label: Some("prepass_downsample_pipeline".into()),
-                    layout: vec![downsample_layout.clone()],
-                    vertex: fullscreen_shader_vertex_state(),
-                    fragment: Some(FragmentState {
-                        shader: DOWNSAMPLE_SHADER_HANDLE,
-                        shader_defs: vec![],
-                        entry_point: "fragment".into(),
-                        targets: vec![
-                            Some(ColorTargetState {
-                                format: DOWNSAMPLE_DEPTH_FORMAT,
-                                blend: None,
-                                write_mask: ColorWrites::ALL,
-                            }),
-                            Some(ColorTargetState {
-                                format: DOWNSAMPLE_NORMALS_FORMAT,
-                                blend: None,
-                                write_mask: ColorWrites::ALL,
-                            }),
-                            Some(ColorTargetState {
-                                format: DOWNSAMPLE_MOTION_FORMAT,
-                                blend: None,
-                                write_mask: ColorWrites::ALL,
-                            }),
-                        ],
-                    }),
-                    primitive: PrimitiveState::default(),
-                    depth_stencil: None,
-                    multisample: MultisampleState::default(),
-                    push_constant_ranges: vec![],
-                });
+        let downsample_layout =
+            render_device.create_bind_group_layout(Some("copy_frame_bind_group_layout"), &entries);
 
         Self {
             convert_layout,
+            forward_convert_layout,
             downsample_layout,
             sampler1,
             sampler2,
             sampler3,
-            convert_pipeline_id,
-            downsample_pipeline_id,
         }
     }
 }
 
+#[derive(PartialEq, Eq, Hash, Clone, Copy)]
+enum PrepassDownsamplePass {
+    /// Copies the deferred gbuffer into mip 0
+    Convert,
+    /// Copies the normal prepass into mip 0
+    ForwardConvert,
+    /// Makes one mip from the previous one
+    Downsample,
+}
+
+#[derive(PartialEq, Eq, Hash, Clone)]
+struct PrepassDownsamplePipelineKey {
+    pass: PrepassDownsamplePass,
+    depth_reduction: DepthReduction,
+}
+
+impl SpecializedRenderPipeline for PrepassDownsamplePipeline {
+    type Key = PrepassDownsamplePipelineKey;
+
+    fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
+        let depth_format = key.depth_reduction.depth_format();
+
+        let mut shader_defs: Vec<ShaderDefVal> = Vec::new();
+
+        #[cfg(all(feature = "webgl", target_arch = "wasm32"))]
+        shader_defs.push("WEBGL2".into());
+
+        match key.pass {
+            PrepassDownsamplePass::Convert => convert_pipeline_descriptor(
+                "prepass_downsample_convert_pipeline",
+                &self.convert_layout,
+                shader_defs,
+                depth_format,
+            ),
+            PrepassDownsamplePass::ForwardConvert => {
+                shader_defs.push("FORWARD_PREPASS".into());
+                convert_pipeline_descriptor(
+                    "prepass_downsample_forward_convert_pipeline",
+                    &self.forward_convert_layout,
+                    shader_defs,
+                    depth_format,
+                )
+            }
+            PrepassDownsamplePass::Downsample => RenderPipelineDescriptor {
+                label: Some("prepass_downsample_pipeline".into()),
+                layout: vec![self.downsample_layout.clone()],
+                vertex: fullscreen_shader_vertex_state(),
+                fragment: Some(FragmentState {
+                    shader: DOWNSAMPLE_SHADER_HANDLE,
+                    shader_defs: vec![key.depth_reduction.shader_def()],
+                    entry_point: "fragment".into(),
+                    targets: downsample_targets(depth_format),
+                }),
+                primitive: PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: MultisampleState::default(),
+                push_constant_ranges: vec![],
+            },
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct PrepassDownsamplePipelines {
+    convert: CachedRenderPipelineId,
+    downsample: CachedRenderPipelineId,
+    /// None if the single pass downsample isn't supported, the multi-pass one is used instead
+    single_pass: Option<CachedComputePipelineId>,
+}
+
+fn prepare_pipelines(
+    mut commands: Commands,
+    pipeline_cache: Res<PipelineCache>,
+    pipeline: Res<PrepassDownsamplePipeline>,
+    mut pipelines: ResMut<SpecializedRenderPipelines<PrepassDownsamplePipeline>>,
+    spd_layouts: Res<SinglePassDownsampleLayouts>,
+    mut spd_pipelines: ResMut<SpecializedComputePipelines<SinglePassDownsampleLayouts>>,
+    views: Query<(Entity, &PrepassDownsample, Has<DeferredPrepass>)>,
+) {
+    for (entity, prepass_downsample, deferred) in &views {
+        let depth_reduction = prepass_downsample.depth_reduction;
+        let convert_pass = if deferred {
+            PrepassDownsamplePass::Convert
+        } else {
+            PrepassDownsamplePass::ForwardConvert
+        };
+        let convert = pipelines.specialize(
+            &pipeline_cache,
+            &pipeline,
+            PrepassDownsamplePipelineKey {
+                pass: convert_pass,
+                depth_reduction,
+            },
+        );
+        let downsample = pipelines.specialize(
+            &pipeline_cache,
+            &pipeline,
+            PrepassDownsamplePipelineKey {
+                pass: PrepassDownsamplePass::Downsample,
+                depth_reduction,
+            },
+        );
+
+        let mip_levels = prepass_downsample.mip_levels as u32;
+        let single_pass = spd_layouts
+            .supports(mip_levels, depth_reduction.depth_format())
+            .then(|| {
+                spd_pipelines.specialize(
+                    &pipeline_cache,
+                    &spd_layouts,
+                    SinglePassDownsampleKey {
+                        mip_levels,
+                        depth_reduction,
+                    },
+                )
+            });
+
+        commands.entity(entity).insert(PrepassDownsamplePipelines {
+            convert,
+            downsample,
+            single_pass,
+        });
+    }
+}
+
+/// Bind group layouts of the single pass downsample for each mip count, from 2 to
+/// [`SPD_MAX_MIP_LEVELS`], and depth format. Empty if the adapter can't write to the downsample
+/// formats from compute shaders, like on WebGL2.
+#[derive(Resource)]
+pub struct SinglePassDownsampleLayouts {
+    layouts: HashMap<(u32, TextureFormat), BindGroupLayout>,
+    max_storage_textures: u32,
+}
+
+impl SinglePassDownsampleLayouts {
+    pub fn supports(&self, mip_levels: u32, depth_format: TextureFormat) -> bool {
+        self.layouts.contains_key(&(mip_levels, depth_format))
+            && (mip_levels - 1) * 3 <= self.max_storage_textures
+    }
+
+    fn layout(&self, mip_levels: u32, depth_format: TextureFormat) -> &BindGroupLayout {
+        &self.layouts[&(mip_levels, depth_format)]
+    }
+}
+
+impl FromWorld for SinglePassDownsampleLayouts {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let render_adapter = world.resource::<RenderAdapter>();
+
+        let supports_storage = |format: TextureFormat| {
+            render_device
+                .features()
+                .contains(WgpuFeatures::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES)
+                && render_adapter
+                    .get_texture_format_features(format)
+                    .allowed_usages
+                    .contains(TextureUsages::STORAGE_BINDING)
+        };
+        let depth_formats: Vec<_> = [DOWNSAMPLE_DEPTH_FORMAT, DOWNSAMPLE_DEPTH_MIN_MAX_FORMAT]
+            .into_iter()
+            .filter(|format| supports_storage(*format))
+            .collect();
+        if !supports_storage(DOWNSAMPLE_NORMALS_FORMAT)
+            || !supports_storage(DOWNSAMPLE_MOTION_FORMAT)
+        {
+            return Self {
+                layouts: HashMap::new(),
+                max_storage_textures: 0,
+            };
+        }
+
+        let layouts = (2..=SPD_MAX_MIP_LEVELS)
+            .flat_map(|mip_levels| depth_formats.iter().map(move |f| (mip_levels, *f)))
+            .map(|(mip_levels, depth_format)| {
+                let mut entries: Vec<_> = [
+                    ftexture_layout_entry(0, TextureViewDimension::D2), // Depth mip 0
+                    ftexture_layout_entry(1, TextureViewDimension::D2), // Normals mip 0
+                    ftexture_layout_entry(2, TextureViewDimension::D2), // Motion mip 0
+                ]
+                .into_iter()
+                .map(|entry| BindGroupLayoutEntry {
+                    visibility: ShaderStages::COMPUTE,
+                    ..entry
+                })
+                .collect();
+                for mip in 1..mip_levels {
+                    entries.extend([
+                        storage_texture_layout_entry(10 + mip, depth_format),
+                        storage_texture_layout_entry(20 + mip, DOWNSAMPLE_NORMALS_FORMAT),
+                        storage_texture_layout_entry(30 + mip, DOWNSAMPLE_MOTION_FORMAT),
+                    ]);
+                }
+                let layout = render_device.create_bind_group_layout(
+                    Some("prepass_downsample_spd_bind_group_layout"),
+                    &entries,
+                );
+                ((mip_levels, depth_format), layout)
+            })
+            .collect();
+
+        Self {
+            layouts,
+            max_storage_textures: render_device.limits().max_storage_textures_per_shader_stage,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Clone)]
+pub struct SinglePassDownsampleKey {
+    mip_levels: u32,
+    depth_reduction: DepthReduction,
+}
+
+impl SpecializedComputePipeline for SinglePassDownsampleLayouts {
+    type Key = SinglePassDownsampleKey;
+
+    fn specialize(&self, key: Self::Key) -> ComputePipelineDescriptor {
+        ComputePipelineDescriptor {
+            label: Some("prepass_downsample_spd_pipeline".into()),
+            layout: vec![self
+                .layout(key.mip_levels, key.depth_reduction.depth_format())
+                .clone()],
+            push_constant_ranges: vec![],
+            shader: SPD_SHADER_HANDLE,
+            shader_defs: vec![
+                ShaderDefVal::UInt("MIP_LEVELS".into(), key.mip_levels),
+                key.depth_reduction.shader_def(),
+            ],
+            entry_point: "downsample".into(),
+        }
+    }
+}
+
+fn mip_view(texture: &CachedTexture, format: TextureFormat, mip: u32) -> TextureView {
+    texture.texture.create_view(&TextureViewDescriptor {
+        label: Some("prepass_downsample_mip"),
+        format: Some(format),
+        dimension: Some(TextureViewDimension::D2),
+        aspect: TextureAspect::All,
+        base_mip_level: mip,
+        mip_level_count: Some(1),
+        base_array_layer: 0,
+        array_layer_count: Some(1),
+    })
+}
+
+/// Writes all the mips after mip 0 in one dispatch
+fn run_single_pass_downsample(
+    render_context: &mut RenderContext,
+    layout: &BindGroupLayout,
+    pipeline: &ComputePipeline,
+    downsample_textures: &PrepassDownsampleTextures,
+    mip_levels: u32,
+    depth_format: TextureFormat,
+) {
+    let views: Vec<_> = (0..mip_levels)
+        .map(|mip| {
+            [
+                mip_view(&downsample_textures.depth, depth_format, mip),
+                mip_view(&downsample_textures.normals, DOWNSAMPLE_NORMALS_FORMAT, mip),
+                mip_view(&downsample_textures.motion, DOWNSAMPLE_MOTION_FORMAT, mip),
+            ]
+        })
+        .collect();
+
+    let entries: Vec<_> = views
+        .iter()
+        .enumerate()
+        .flat_map(|(mip, [depth, normals, motion])| {
+            // Mip 0 is read from, the rest are written to
+            let base = if mip == 0 { 0 } else { 10 + mip as u32 };
+            let stride = if mip == 0 { 1 } else { 10 };
+            [
+                BindGroupEntry {
+                    binding: base,
+                    resource: BindingResource::TextureView(depth),
+                },
+                BindGroupEntry {
+                    binding: base + stride,
+                    resource: BindingResource::TextureView(normals),
+                },
+                BindGroupEntry {
+                    binding: base + stride * 2,
+                    resource: BindingResource::TextureView(motion),
+                },
+            ]
+        })
+        .collect();
+
+    let bind_group = render_context.render_device().create_bind_group(
+        "prepass_downsample_spd_bind_group",
+        layout,
+        &entries,
+    );
+
+    let size = downsample_textures.depth.texture.size();
+    let mut compute_pass =
+        render_context
+            .command_encoder()
+            .begin_compute_pass(&ComputePassDescriptor {
+                label: Some("prepass_downsample_spd_pass"),
+                timestamp_writes: None,
+            });
+    compute_pass.set_pipeline(pipeline);
+    compute_pass.set_bind_group(0, &bind_group, &[]);
+    compute_pass.dispatch_workgroups(
+        size.width.div_ceil(SPD_TILE_SIZE),
+        size.height.div_ceil(SPD_TILE_SIZE),
+        1,
+    );
+}
+
+fn convert_pipeline_descriptor(
+    label: &'static str,
+    layout: &BindGroupLayout,
+    shader_defs: Vec<ShaderDefVal>,
+    depth_format: TextureFormat,
+) -> RenderPipelineDescriptor {
+    RenderPipelineDescriptor {
+        label: Some(label.into()),
+        layout: vec![layout.clone()],
+        vertex: fullscreen_shader_vertex_state(),
+        fragment: Some(FragmentState {
+            shader: CONVERT_SHADER_HANDLE,
+            shader_defs,
+            entry_point: "fragment".into(),
+            targets: downsample_targets(depth_format),
+        }),
+        primitive: PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: MultisampleState::default(),
+        push_constant_ranges: vec![],
+    }
+}
+
+fn downsample_targets(depth_format: TextureFormat) -> Vec<Option<ColorTargetState>> {
+    [
+        depth_format,
+        DOWNSAMPLE_NORMALS_FORMAT,
+        DOWNSAMPLE_MOTION_FORMAT,
+    ]
+    .into_iter()
+    .map(|format| {
+        Some(ColorTargetState {
+            format,
+            blend: None,
+            write_mask: ColorWrites::ALL,
+        })
+    })
+    .collect()
+}
+
 fn prepare_textures(
     mut commands: Commands,
     mut texture_cache: ResMut<TextureCache>,
     render_device: Res<RenderDevice>,
-    views: Query<(Entity, &ExtractedCamera, &PrepassDownsample)>,
-    frame_count: Res<FrameCount>,
+    spd_layouts: Res<SinglePassDownsampleLayouts>,
+    views: Query<(
+        Entity,
+        &ExtractedCamera,
+        &PrepassDownsample,
+        Option<&SSGIPass>,
+    )>,
+    mut view_histories: ResMut<ViewHistories>,
 ) {
-    for (entity, camera, prepass_downsample) in &views {
+    for (entity, camera, prepass_downsample, ssgi_pass) in &views {
+        let depth_format = prepass_downsample.depth_reduction.depth_format();
+        let mut usage = TextureUsages::RENDER_ATTACHMENT
+            | TextureUsages::TEXTURE_BINDING
+            | TextureUsages::COPY_SRC
+            | TextureUsages::COPY_DST;
+        if spd_layouts.supports(prepass_downsample.mip_levels as u32, depth_format) {
+            usage |= TextureUsages::STORAGE_BINDING;
+        }
+
         if let Some(physical_viewport_size) = camera.physical_viewport_size {
             let mut depth_texture_descriptor = TextureDescriptor {
                 label: None,
@@ -619,11 +1402,8 @@ fn prepare_textures(
                 mip_level_count: prepass_downsample.mip_levels as u32,
                 sample_count: 1,
                 dimension: TextureDimension::D2,
-                format: DOWNSAMPLE_DEPTH_FORMAT,
-                usage: TextureUsages::RENDER_ATTACHMENT
-                    | TextureUsages::TEXTURE_BINDING
-                    | TextureUsages::COPY_SRC
-                    | TextureUsages::COPY_DST,
+                format: depth_format,
+                usage,
                 view_formats: &[],
             };
             let mut normals_texture_descriptor = TextureDescriptor {
@@ -637,10 +1417,7 @@ fn prepare_textures(
                 sample_count: 1,
                 dimension: TextureDimension::D2,
                 format: DOWNSAMPLE_NORMALS_FORMAT,
-                usage: TextureUsages::RENDER_ATTACHMENT
-                    | TextureUsages::TEXTURE_BINDING
-                    | TextureUsages::COPY_SRC
-                    | TextureUsages::COPY_DST,
+                usage,
                 view_formats: &[],
             };
             let mut motion_texture_descriptor = TextureDescriptor {
@@ -654,23 +1431,17 @@ fn prepare_textures(
                 sample_count: 1,
                 dimension: TextureDimension::D2,
                 format: DOWNSAMPLE_MOTION_FORMAT,
-                usage: TextureUsages::RENDER_ATTACHMENT
-                    | TextureUsages::TEXTURE_BINDING
-                    | TextureUsages::COPY_SRC
-                    | TextureUsages::COPY_DST,
+                usage,
                 view_formats: &[],
             };
 
             normals_texture_descriptor.label = Some("PrepassDownsampleNormalsTexture");
-            depth_texture_descriptor.label = Some("PrepassDownsampleDepthTextureA");
+            depth_texture_descriptor.label = Some("PrepassDownsampleDepthTexture");
             motion_texture_descriptor.label = Some("PrepassDownsampleMotionTexture");
             let normals_texture =
                 texture_cache.get(&render_device, normals_texture_descriptor.clone());
-            let depth_texture_a =
-                texture_cache.get(&render_device, depth_texture_descriptor.clone());
-            depth_texture_descriptor.label = Some("PrepassDownsampleDepthTextureB");
-            let depth_texture_b =
-                texture_cache.get(&render_device, depth_texture_descriptor.clone());
+            let depth_textures =
+                view_histories.ping_pong(&render_device, entity, &depth_texture_descriptor);
             let motion_texture =
                 texture_cache.get(&render_device, motion_texture_descriptor.clone());
             #[cfg(all(feature = "webgl", target_arch = "wasm32"))]
@@ -692,32 +1463,41 @@ fn prepare_textures(
             let temp_motion_texture =
                 texture_cache.get(&render_device, motion_texture_descriptor.clone());
 
-            let textures = if frame_count.0 % 2 == 0 {
-                PrepassDownsampleTextures {
-                    normals: normals_texture,
-                    depth: depth_texture_a,
-                    histry_depth: depth_texture_b,
-                    motion: motion_texture,
-                    #[cfg(all(feature = "webgl", target_arch = "wasm32"))]
-                    temp_normals: temp_normals_texture,
-                    #[cfg(all(feature = "webgl", target_arch = "wasm32"))]
-                    temp_depth: temp_depth_texture,
-                    #[cfg(all(feature = "webgl", target_arch = "wasm32"))]
-                    temp_motion: temp_motion_texture,
-                }
-            } else {
-                PrepassDownsampleTextures {
-                    normals: normals_texture,
-                    depth: depth_texture_b,
-                    histry_depth: depth_texture_a,
-                    motion: motion_texture,
-                    #[cfg(all(feature = "webgl", target_arch = "wasm32"))]
-                    temp_normals: temp_normals_texture,
-                    #[cfg(all(feature = "webgl", target_arch = "wasm32"))]
-                    temp_depth: temp_depth_texture,
-                    #[cfg(all(feature = "webgl", target_arch = "wasm32"))]
-                    temp_motion: temp_motion_texture,
-                }
+            let back_face_depth = ssgi_pass
+                .is_some_and(|ssgi_pass| ssgi_pass.back_face_prepass)
+                .then(|| {
+                    texture_cache.get(
+                        &render_device,
+                        TextureDescriptor {
+                            label: Some("PrepassDownsampleBackFaceDepthTexture"),
+                            size: Extent3d {
+                                depth_or_array_layers: 1,
+                                width: physical_viewport_size.x,
+                                height: physical_viewport_size.y,
+                            },
+                            mip_level_count: 1,
+                            sample_count: 1,
+                            dimension: TextureDimension::D2,
+                            format: BACK_FACE_DEPTH_FORMAT,
+                            usage: TextureUsages::RENDER_ATTACHMENT
+                                | TextureUsages::TEXTURE_BINDING,
+                            view_formats: &[],
+                        },
+                    )
+                });
+
+            let textures = PrepassDownsampleTextures {
+                normals: normals_texture,
+                depth: depth_textures.write,
+                histry_depth: depth_textures.read,
+                motion: motion_texture,
+                back_face_depth,
+                #[cfg(all(feature = "webgl", target_arch = "wasm32"))]
+                temp_normals: temp_normals_texture,
+                #[cfg(all(feature = "webgl", target_arch = "wasm32"))]
+                temp_depth: temp_depth_texture,
+                #[cfg(all(feature = "webgl", target_arch = "wasm32"))]
+                temp_motion: temp_motion_texture,
             };
 
             commands.entity(entity).insert(textures);
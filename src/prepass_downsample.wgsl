@@ -6,18 +6,45 @@
 @group(0) @binding(5) var motion_sampler: sampler;
 
 #import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
+#import ssgi::depth_reduction::{DepthSample, reduce}
 
 struct FragmentOutput {
-    @location(0) depth: f32,
-    @location(1) normals: vec4<f32>,
+    @location(0) depth: vec2<f32>,
+    @location(1) normals: vec2<f32>,
     @location(2) motion: vec2<f32>,
 }
 
+fn load(coords: vec2<i32>) -> DepthSample {
+    let texel = clamp(coords, vec2(0), vec2<i32>(textureDimensions(depth_prepass_texture)) - 1);
+    var sample: DepthSample;
+    sample.depth = textureLoad(depth_prepass_texture, texel, 0).xy;
+    sample.normal = textureLoad(normal_prepass_texture, texel, 0).xy;
+    sample.motion = textureLoad(motion_prepass_texture, texel, 0).xy;
+    return sample;
+}
+
 @fragment
 fn fragment(in: FullscreenVertexOutput) -> FragmentOutput {
     var out: FragmentOutput;
-    out.depth = textureSample(depth_prepass_texture, depth_sampler, in.uv).x;
-    out.normals = textureSample(normal_prepass_texture, normal_sampler, in.uv);
+#ifdef DEPTH_REDUCTION_POINT
+    out.depth = textureSample(depth_prepass_texture, depth_sampler, in.uv).xy;
+    out.normals = textureSample(normal_prepass_texture, normal_sampler, in.uv).xy;
     out.motion = textureSample(motion_prepass_texture, normal_sampler, in.uv).xy;
+#else
+    // The 2x2 block of the previous mip under this texel. On WebGL2 the source is always mip 0,
+    // so only the 2x2 block at the center of the texel's footprint is reduced.
+    let dims = vec2<f32>(textureDimensions(depth_prepass_texture));
+    let coords = vec2<i32>(floor(in.uv * dims - 0.5));
+    let sample = reduce(
+        load(coords),
+        load(coords + vec2(1, 0)),
+        load(coords + vec2(0, 1)),
+        load(coords + vec2(1, 1)),
+        vec2<u32>(in.position.xy),
+    );
+    out.depth = sample.depth;
+    out.normals = sample.normal;
+    out.motion = sample.motion;
+#endif
     return out;
 }
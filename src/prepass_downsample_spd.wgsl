@@ -0,0 +1,199 @@
+// Single pass downsampler, writes mips 1 to MIP_LEVELS - 1 of the depth, normals and motion
+// from mip 0 in one dispatch. Each workgroup reduces a 64x64 tile of mip 0 down to 1x1 in mip 6,
+// so up to 7 mip levels are supported.
+// The normals and motion come from the same texel as the depth, so they always agree with it.
+
+#import ssgi::depth_reduction::{DepthSample, reduce}
+
+@group(0) @binding(0) var depth_mip_0: texture_2d<f32>;
+@group(0) @binding(1) var normals_mip_0: texture_2d<f32>;
+@group(0) @binding(2) var motion_mip_0: texture_2d<f32>;
+
+#ifdef DEPTH_REDUCTION_MIN_MAX
+@group(0) @binding(11) var depth_mip_1: texture_storage_2d<rg32float, write>;
+#else
+@group(0) @binding(11) var depth_mip_1: texture_storage_2d<r32float, write>;
+#endif
+@group(0) @binding(21) var normals_mip_1: texture_storage_2d<rg16unorm, write>;
+@group(0) @binding(31) var motion_mip_1: texture_storage_2d<rg16float, write>;
+#if MIP_LEVELS > 2
+#ifdef DEPTH_REDUCTION_MIN_MAX
+@group(0) @binding(12) var depth_mip_2: texture_storage_2d<rg32float, write>;
+#else
+@group(0) @binding(12) var depth_mip_2: texture_storage_2d<r32float, write>;
+#endif
+@group(0) @binding(22) var normals_mip_2: texture_storage_2d<rg16unorm, write>;
+@group(0) @binding(32) var motion_mip_2: texture_storage_2d<rg16float, write>;
+#endif
+#if MIP_LEVELS > 3
+#ifdef DEPTH_REDUCTION_MIN_MAX
+@group(0) @binding(13) var depth_mip_3: texture_storage_2d<rg32float, write>;
+#else
+@group(0) @binding(13) var depth_mip_3: texture_storage_2d<r32float, write>;
+#endif
+@group(0) @binding(23) var normals_mip_3: texture_storage_2d<rg16unorm, write>;
+@group(0) @binding(33) var motion_mip_3: texture_storage_2d<rg16float, write>;
+#endif
+#if MIP_LEVELS > 4
+#ifdef DEPTH_REDUCTION_MIN_MAX
+@group(0) @binding(14) var depth_mip_4: texture_storage_2d<rg32float, write>;
+#else
+@group(0) @binding(14) var depth_mip_4: texture_storage_2d<r32float, write>;
+#endif
+@group(0) @binding(24) var normals_mip_4: texture_storage_2d<rg16unorm, write>;
+@group(0) @binding(34) var motion_mip_4: texture_storage_2d<rg16float, write>;
+#endif
+#if MIP_LEVELS > 5
+#ifdef DEPTH_REDUCTION_MIN_MAX
+@group(0) @binding(15) var depth_mip_5: texture_storage_2d<rg32float, write>;
+#else
+@group(0) @binding(15) var depth_mip_5: texture_storage_2d<r32float, write>;
+#endif
+@group(0) @binding(25) var normals_mip_5: texture_storage_2d<rg16unorm, write>;
+@group(0) @binding(35) var motion_mip_5: texture_storage_2d<rg16float, write>;
+#endif
+#if MIP_LEVELS > 6
+#ifdef DEPTH_REDUCTION_MIN_MAX
+@group(0) @binding(16) var depth_mip_6: texture_storage_2d<rg32float, write>;
+#else
+@group(0) @binding(16) var depth_mip_6: texture_storage_2d<r32float, write>;
+#endif
+@group(0) @binding(26) var normals_mip_6: texture_storage_2d<rg16unorm, write>;
+@group(0) @binding(36) var motion_mip_6: texture_storage_2d<rg16float, write>;
+#endif
+
+// The mip 2 sample of each thread, then reused for each following mip
+var<workgroup> tile_depth: array<vec2<f32>, 256>;
+var<workgroup> tile_normal: array<vec2<f32>, 256>;
+var<workgroup> tile_motion: array<vec2<f32>, 256>;
+
+fn load_mip_0(coords: vec2<u32>) -> DepthSample {
+    let texel = vec2<i32>(min(coords, textureDimensions(depth_mip_0) - 1u));
+    var sample: DepthSample;
+    sample.depth = textureLoad(depth_mip_0, texel, 0).xy;
+    sample.normal = textureLoad(normals_mip_0, texel, 0).xy;
+    sample.motion = textureLoad(motion_mip_0, texel, 0).xy;
+    return sample;
+}
+
+// coords is the texel of mip 1
+fn reduce_mip_0(coords: vec2<u32>) -> DepthSample {
+    let mip_0_coords = coords * 2u;
+    return reduce(
+        load_mip_0(mip_0_coords),
+        load_mip_0(mip_0_coords + vec2(1u, 0u)),
+        load_mip_0(mip_0_coords + vec2(0u, 1u)),
+        load_mip_0(mip_0_coords + vec2(1u, 1u)),
+        coords,
+    );
+}
+
+fn store(mip: u32, coords: vec2<u32>, sample: DepthSample) {
+    let depth = vec4(sample.depth, 0.0, 0.0);
+    let normal = vec4(sample.normal, 0.0, 0.0);
+    let motion = vec4(sample.motion, 0.0, 0.0);
+    switch mip {
+        case 1u: {
+            textureStore(depth_mip_1, coords, depth);
+            textureStore(normals_mip_1, coords, normal);
+            textureStore(motion_mip_1, coords, motion);
+        }
+#if MIP_LEVELS > 2
+        case 2u: {
+            textureStore(depth_mip_2, coords, depth);
+            textureStore(normals_mip_2, coords, normal);
+            textureStore(motion_mip_2, coords, motion);
+        }
+#endif
+#if MIP_LEVELS > 3
+        case 3u: {
+            textureStore(depth_mip_3, coords, depth);
+            textureStore(normals_mip_3, coords, normal);
+            textureStore(motion_mip_3, coords, motion);
+        }
+#endif
+#if MIP_LEVELS > 4
+        case 4u: {
+            textureStore(depth_mip_4, coords, depth);
+            textureStore(normals_mip_4, coords, normal);
+            textureStore(motion_mip_4, coords, motion);
+        }
+#endif
+#if MIP_LEVELS > 5
+        case 5u: {
+            textureStore(depth_mip_5, coords, depth);
+            textureStore(normals_mip_5, coords, normal);
+            textureStore(motion_mip_5, coords, motion);
+        }
+#endif
+#if MIP_LEVELS > 6
+        case 6u: {
+            textureStore(depth_mip_6, coords, depth);
+            textureStore(normals_mip_6, coords, normal);
+            textureStore(motion_mip_6, coords, motion);
+        }
+#endif
+        default: {}
+    }
+}
+
+fn tile_sample(index: u32) -> DepthSample {
+    return DepthSample(tile_depth[index], tile_normal[index], tile_motion[index]);
+}
+
+@compute @workgroup_size(256, 1, 1)
+fn downsample(
+    @builtin(workgroup_id) workgroup_id: vec3<u32>,
+    @builtin(local_invocation_index) local_index: u32,
+) {
+    // Each thread has a 2x2 block of mip 1, which is one texel of mip 2
+    let local = vec2(local_index % 16u, local_index / 16u);
+
+    let mip_1_coords = workgroup_id.xy * 32u + local * 2u;
+    let mip_1_a = reduce_mip_0(mip_1_coords);
+    let mip_1_b = reduce_mip_0(mip_1_coords + vec2(1u, 0u));
+    let mip_1_c = reduce_mip_0(mip_1_coords + vec2(0u, 1u));
+    let mip_1_d = reduce_mip_0(mip_1_coords + vec2(1u, 1u));
+    store(1u, mip_1_coords, mip_1_a);
+    store(1u, mip_1_coords + vec2(1u, 0u), mip_1_b);
+    store(1u, mip_1_coords + vec2(0u, 1u), mip_1_c);
+    store(1u, mip_1_coords + vec2(1u, 1u), mip_1_d);
+
+    let mip_2_coords = workgroup_id.xy * 16u + local;
+    let mip_2 = reduce(mip_1_a, mip_1_b, mip_1_c, mip_1_d, mip_2_coords);
+    store(2u, mip_2_coords, mip_2);
+    tile_depth[local_index] = mip_2.depth;
+    tile_normal[local_index] = mip_2.normal;
+    tile_motion[local_index] = mip_2.motion;
+
+    // Each following mip halves the size of the tile. The tile stays in the same 16 wide layout,
+    // and the texels of each mip are written back to the top left corner of the one it came from.
+    for (var mip = 3u; mip < min(u32(#{MIP_LEVELS}), 7u); mip += 1u) {
+        workgroupBarrier();
+
+        let size = 16u >> (mip - 2u);
+        let in_mip = local.x < size && local.y < size;
+        var sample: DepthSample;
+        if in_mip {
+            let index = local.y * 2u * 16u + local.x * 2u;
+            let coords = workgroup_id.xy * size + local;
+            sample = reduce(
+                tile_sample(index),
+                tile_sample(index + 1u),
+                tile_sample(index + 16u),
+                tile_sample(index + 17u),
+                coords,
+            );
+            store(mip, coords, sample);
+        }
+
+        workgroupBarrier();
+
+        if in_mip {
+            let index = local.y * 16u + local.x;
+            tile_depth[index] = sample.depth;
+            tile_normal[index] = sample.normal;
+            tile_motion[index] = sample.motion;
+        }
+    }
+}
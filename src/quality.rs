@@ -0,0 +1,89 @@
+use bevy::prelude::*;
+
+use crate::{
+    ssgi::SSGIPass, ssgi_generate_sh::SSGIGenerateSH, ssgi_resolve::SSGIResolve, SSGIBundle,
+    SSGIForwardBundle,
+};
+
+/// Coordinated settings for [`SSGIPass`], [`SSGIGenerateSH`] and [`SSGIResolve`].
+///
+/// Most of the cost is in the [`SSGIPass`] ray march, which scales with the number of probes
+/// (`viewport / render_scale²`) times `cascade_0_directions`. Each step down roughly halves the cost.
+/// Only the performance related fields are set, artistic ones like `brightness` are left alone.
+#[derive(Reflect, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum SSGIQuality {
+    /// Probes every 8 pixels, 8 directions, 4 cascades. Short range GI with more temporal smoothing.
+    Low,
+    /// Probes every 6 pixels, 8 directions, 5 cascades.
+    Medium,
+    /// Probes every 4 pixels, 16 directions, 6 cascades. Same as the defaults.
+    #[default]
+    High,
+    /// Probes every 2 pixels, 16 directions, 6 cascades. Much slower than High.
+    Ultra,
+    /// Fields have been tuned by hand, [`SSGIQuality::apply`] leaves them as is.
+    Custom,
+}
+
+impl SSGIQuality {
+    /// Sets the performance related fields for this quality level. Does nothing for [`SSGIQuality::Custom`].
+    pub fn apply(
+        &self,
+        ssgi_pass: &mut SSGIPass,
+        ssgi_generate_sh: &mut SSGIGenerateSH,
+        ssgi_resolve: &mut SSGIResolve,
+    ) {
+        let (render_scale, cascade_0_directions, cascade_count, mip_min, mip_max) = match self {
+            SSGIQuality::Low => (8, 8, 4, 3.0, 4.0),
+            SSGIQuality::Medium => (6, 8, 5, 2.0, 4.0),
+            SSGIQuality::High => (4, 16, 6, 2.0, 4.0),
+            SSGIQuality::Ultra => (2, 16, 6, 1.0, 4.0),
+            SSGIQuality::Custom => return,
+        };
+        // Less probes & directions are noisier, so lean more on the history
+        let (sh_max_history_length, resolve_max_history_length, specular_samples) = match self {
+            SSGIQuality::Low => (10.0, 12.0, 1),
+            SSGIQuality::Medium => (7.0, 10.0, 1),
+            SSGIQuality::High => (5.0, 10.0, 2),
+            SSGIQuality::Ultra => (4.0, 7.0, 4),
+            SSGIQuality::Custom => return,
+        };
+
+        ssgi_pass.render_scale = render_scale;
+        ssgi_pass.cascade_0_directions = cascade_0_directions;
+        ssgi_pass.cascade_count = cascade_count;
+        ssgi_pass.mip_min = mip_min;
+        ssgi_pass.mip_max = mip_max;
+        ssgi_generate_sh.max_history_length = sh_max_history_length;
+        ssgi_resolve.max_history_length = resolve_max_history_length;
+        ssgi_resolve.specular_samples = specular_samples;
+    }
+
+    /// [`SSGIBundle`] using this quality level. [`SSGIQuality::Custom`] gives the defaults.
+    pub fn bundle(&self) -> SSGIBundle {
+        let mut bundle = SSGIBundle::default();
+        self.apply(
+            &mut bundle.ssgi_pass,
+            &mut bundle.ssgi_generate_sh,
+            &mut bundle.ssgi_resolve,
+        );
+        bundle
+    }
+
+    /// [`SSGIForwardBundle`] using this quality level. [`SSGIQuality::Custom`] gives the defaults.
+    pub fn forward_bundle(&self) -> SSGIForwardBundle {
+        let mut bundle = SSGIForwardBundle::default();
+        self.apply(
+            &mut bundle.ssgi_pass,
+            &mut bundle.ssgi_generate_sh,
+            &mut bundle.ssgi_resolve,
+        );
+        bundle
+    }
+}
+
+impl From<SSGIQuality> for SSGIBundle {
+    fn from(quality: SSGIQuality) -> Self {
+        quality.bundle()
+    }
+}
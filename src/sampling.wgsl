@@ -33,7 +33,53 @@ fn YCoCg_to_RGB(ycocg: vec3<f32>) -> vec3<f32> {
     let r = ycocg.x + ycocg.y - ycocg.z;
     let g = ycocg.x + ycocg.z;
     let b = ycocg.x - ycocg.y - ycocg.z;
-    return saturate(vec3(r, g, b));
+    // Not saturated, radiance is HDR
+    return max(vec3(r, g, b), vec3(0.0));
+}
+
+// Bounds and moments of a neighborhood in YCoCg, that history is clamped to
+struct Neighborhood {
+    minimum: vec3<f32>,
+    maximum: vec3<f32>,
+    sum: vec3<f32>,
+    sum_squared: vec3<f32>,
+    count: f32,
+}
+
+fn neighborhood_new() -> Neighborhood {
+    return Neighborhood(vec3(F32_MAX), vec3(-F32_MAX), vec3(0.0), vec3(0.0), 0.0);
+}
+
+fn neighborhood_add(neighborhood: ptr<function, Neighborhood>, rgb: vec3<f32>) {
+    let ycocg = RGB_to_YCoCg(rgb);
+    (*neighborhood).minimum = min((*neighborhood).minimum, ycocg);
+    (*neighborhood).maximum = max((*neighborhood).maximum, ycocg);
+    (*neighborhood).sum += ycocg;
+    (*neighborhood).sum_squared += ycocg * ycocg;
+    (*neighborhood).count += 1.0;
+}
+
+// Clamps the history to the min/max of the neighborhood
+fn neighborhood_clamp(neighborhood: Neighborhood, history: vec3<f32>) -> vec3<f32> {
+    return YCoCg_to_RGB(clamp(RGB_to_YCoCg(history), neighborhood.minimum, neighborhood.maximum));
+}
+
+// Clips the history towards the mean of the neighborhood, to the box of gamma standard deviations
+// around it. https://developer.download.nvidia.com/gameworks/events/GDC2016/msalvi_temporal_supersampling.pdf
+fn neighborhood_clip_variance(neighborhood: Neighborhood, history: vec3<f32>, gamma: f32) -> vec3<f32> {
+    let count = max(neighborhood.count, 1.0);
+    let mean = neighborhood.sum / count;
+    let sigma = sqrt(max(neighborhood.sum_squared / count - mean * mean, vec3(0.0)));
+    let extents = gamma * sigma + F32_EPSILON;
+
+    let ycocg = RGB_to_YCoCg(history);
+    let offset = ycocg - mean;
+    let units = abs(offset / extents);
+    let max_unit = max(units.x, max(units.y, units.z));
+    if max_unit > 1.0 {
+        return YCoCg_to_RGB(mean + offset / max_unit);
+    }
+    return history;
 }
 
 fn uniform_sample_sphere(urand: vec2<f32>) -> vec3<f32> {
@@ -0,0 +1,183 @@
+use std::fmt;
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    prelude::*,
+    utils::{BoxedFuture, HashSet},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    copy_frame::CopyFrame, prepass_downsample::PrepassDownsample, ssgi::SSGIPass,
+    ssgi_denoise::SSGIDenoise, ssgi_generate_sh::SSGIGenerateSH, ssgi_resolve::SSGIResolve,
+};
+
+/// SSGI settings loaded from a `.ssgi.ron` file. Add the `Handle<SSGISettingsAsset>` to a camera
+/// with SSGI to apply them. Components left as `None` aren't changed, and missing fields use their defaults.
+/// [`SSGIDenoise`] is added to the camera if it doesn't have it yet, since it's opt-in.
+/// With the `file_watcher` feature the camera is updated when the file is saved.
+///
+/// ```ron
+/// (
+///     ssgi_pass: Some((brightness: 3.0, render_scale: 6)),
+///     ssgi_resolve: Some((max_history_length: 7.0)),
+/// )
+/// ```
+#[derive(Asset, TypePath, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct SSGISettingsAsset {
+    pub ssgi_pass: Option<SSGIPass>,
+    pub ssgi_generate_sh: Option<SSGIGenerateSH>,
+    pub ssgi_resolve: Option<SSGIResolve>,
+    pub ssgi_denoise: Option<SSGIDenoise>,
+    pub prepass_downsample: Option<PrepassDownsample>,
+    pub copy_frame: Option<CopyFrame>,
+}
+
+impl SSGISettingsAsset {
+    /// Settings currently on a camera, for saving tuned values back out.
+    pub fn from_components(
+        ssgi_pass: Option<&SSGIPass>,
+        ssgi_generate_sh: Option<&SSGIGenerateSH>,
+        ssgi_resolve: Option<&SSGIResolve>,
+        ssgi_denoise: Option<&SSGIDenoise>,
+        prepass_downsample: Option<&PrepassDownsample>,
+        copy_frame: Option<&CopyFrame>,
+    ) -> Self {
+        SSGISettingsAsset {
+            ssgi_pass: ssgi_pass.cloned(),
+            ssgi_generate_sh: ssgi_generate_sh.cloned(),
+            ssgi_resolve: ssgi_resolve.cloned(),
+            ssgi_denoise: ssgi_denoise.cloned(),
+            prepass_downsample: prepass_downsample.cloned(),
+            copy_frame: copy_frame.cloned(),
+        }
+    }
+
+    pub fn to_ron(&self) -> Result<String, ron::Error> {
+        ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+    }
+}
+
+#[derive(Default)]
+pub struct SSGISettingsLoader;
+
+#[derive(Debug)]
+pub enum SSGISettingsLoaderError {
+    Io(std::io::Error),
+    Ron(ron::error::SpannedError),
+}
+
+impl fmt::Display for SSGISettingsLoaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SSGISettingsLoaderError::Io(e) => write!(f, "Could not read SSGI settings: {e}"),
+            SSGISettingsLoaderError::Ron(e) => write!(f, "Could not parse SSGI settings: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for SSGISettingsLoaderError {}
+
+impl From<std::io::Error> for SSGISettingsLoaderError {
+    fn from(e: std::io::Error) -> Self {
+        SSGISettingsLoaderError::Io(e)
+    }
+}
+
+impl From<ron::error::SpannedError> for SSGISettingsLoaderError {
+    fn from(e: ron::error::SpannedError) -> Self {
+        SSGISettingsLoaderError::Ron(e)
+    }
+}
+
+impl AssetLoader for SSGISettingsLoader {
+    type Asset = SSGISettingsAsset;
+    type Settings = ();
+    type Error = SSGISettingsLoaderError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<Self::Asset, Self::Error>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            Ok(ron::de::from_bytes::<SSGISettingsAsset>(&bytes)?)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["ssgi.ron"]
+    }
+}
+
+pub struct SSGISettingsAssetPlugin;
+impl Plugin for SSGISettingsAssetPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<SSGISettingsAsset>()
+            .init_asset_loader::<SSGISettingsLoader>()
+            .add_systems(PostUpdate, apply_ssgi_settings);
+    }
+}
+
+/// Copies the settings to the camera when the handle is added/changed, or the asset is loaded/modified.
+#[allow(clippy::type_complexity)]
+fn apply_ssgi_settings(
+    mut commands: Commands,
+    mut events: EventReader<AssetEvent<SSGISettingsAsset>>,
+    settings: Res<Assets<SSGISettingsAsset>>,
+    mut views: Query<(
+        Entity,
+        Ref<Handle<SSGISettingsAsset>>,
+        Option<&mut SSGIPass>,
+        Option<&mut SSGIGenerateSH>,
+        Option<&mut SSGIResolve>,
+        Option<&mut PrepassDownsample>,
+        Option<&mut CopyFrame>,
+    )>,
+) {
+    let mut updated = HashSet::new();
+    for event in events.read() {
+        match event {
+            AssetEvent::Added { id } | AssetEvent::Modified { id } => {
+                updated.insert(*id);
+            }
+            _ => (),
+        }
+    }
+
+    for (
+        entity,
+        handle,
+        ssgi_pass,
+        ssgi_generate_sh,
+        ssgi_resolve,
+        prepass_downsample,
+        copy_frame,
+    ) in &mut views
+    {
+        if !handle.is_changed() && !updated.contains(&handle.id()) {
+            continue;
+        }
+        let Some(settings) = settings.get(&*handle) else {
+            continue;
+        };
+        apply(ssgi_pass, &settings.ssgi_pass);
+        apply(ssgi_generate_sh, &settings.ssgi_generate_sh);
+        apply(ssgi_resolve, &settings.ssgi_resolve);
+        apply(prepass_downsample, &settings.prepass_downsample);
+        apply(copy_frame, &settings.copy_frame);
+        if let Some(ssgi_denoise) = &settings.ssgi_denoise {
+            commands.entity(entity).insert(ssgi_denoise.clone());
+        }
+    }
+}
+
+fn apply<T: Clone>(target: Option<Mut<T>>, value: &Option<T>) {
+    if let (Some(mut target), Some(value)) = (target, value) {
+        *target = value.clone();
+    }
+}
@@ -1,3 +1,6 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+
 use bevy::app::prelude::*;
 use bevy::asset::{load_internal_asset, Handle};
 use bevy::core_pipeline::core_3d::graph::{Core3d, Node3d};
@@ -11,39 +14,55 @@ use bevy::render::camera::ExtractedCamera;
 use bevy::render::extract_component::{ExtractComponent, ExtractComponentPlugin};
 use bevy::render::render_asset::RenderAssets;
 use bevy::render::render_graph::RenderLabel;
-use bevy::render::texture::{CachedTexture, TextureCache};
+use bevy::render::texture::{CachedTexture, FallbackImage, TextureCache};
 use bevy::render::{
+    globals::GlobalsBuffer,
     render_graph::{NodeRunError, RenderGraphContext, ViewNode, ViewNodeRunner},
     render_resource::{Operations, PipelineCache, RenderPassDescriptor},
-    renderer::{RenderContext, RenderDevice},
-    view::ViewUniformOffset,
-    Render, RenderSet,
+    renderer::{RenderContext, RenderDevice, RenderQueue},
+    view::{ViewUniformOffset, ViewUniforms},
+    Extract, ExtractSchedule, Render, RenderSet,
 };
 
+#[cfg(feature = "inspector")]
 use bevy_inspector_egui::{inspector_options::ReflectInspectorOptions, InspectorOptions};
 
 use bevy::render::{render_graph::RenderGraphApp, render_resource::*, RenderApp};
+use bevy::utils::HashSet;
 
 use crate::bind_group_utils::{
-    fsampler_layout_entry, ftexture_layout_entry, globals_binding, globals_layout_entry,
-    linear_sampler, nearest_sampler, uniform_buffer, uniform_layout_entry, utexture_layout_entry,
-    view_binding, view_layout_entry,
+    dynamic_uniform_layout_entry, fsampler_layout_entry, ftexture_layout_entry,
+    globals_layout_entry, storage_texture_layout_entry, texture_layout_entry,
+    utexture_layout_entry, view_layout_entry, PerViewBindGroups, SSGISamplers,
 };
+#[cfg(all(
+    feature = "disocclusion",
+    not(all(feature = "webgl", target_arch = "wasm32"))
+))]
+use bevy_mod_taa::disocclusion::{DisocclusionLabel, DisocclusionTextures};
+
 use crate::copy_frame::PrevFrameTexture;
-use crate::prepass_downsample::{DownsampleLabel, PrepassDownsampleTextures};
-use crate::{image, resource, shader_def_uint, BlueNoise, BLUE_NOISE_DIMS, BLUE_NOISE_ENTRY_N};
+use crate::debug_view::SSGIDebugView;
+use crate::prepass_downsample::{
+    DepthReduction, DownsampleLabel, PrepassDownsample, PrepassDownsampleTextures,
+};
+use crate::{shader_def_uint, wgsl_uniform, BlueNoise, BLUE_NOISE_DIMS, BLUE_NOISE_ENTRY_N};
 
-#[derive(Component, ExtractComponent, Clone, Reflect, InspectorOptions)]
-#[reflect(Component, InspectorOptions)]
+#[derive(Component, ExtractComponent, Clone, Reflect)]
+#[reflect(Component)]
+#[cfg_attr(feature = "inspector", derive(InspectorOptions))]
+#[cfg_attr(feature = "inspector", reflect(InspectorOptions))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct SSGIPass {
     /// Proportion of the render target resolution. Should be a multiple of 2, [2..=16], 2 is quite slow
-    #[inspector(min = 2, max = 32)]
+    #[cfg_attr(feature = "inspector", inspector(min = 2, max = 16))]
     pub render_scale: u32,
-    /// Must be a square multiple of 4
-    #[inspector(min = 4, max = 32)]
+    /// Must be a non-zero multiple of 4
+    #[cfg_attr(feature = "inspector", inspector(min = 4, max = 32))]
     pub cascade_0_directions: u32,
     /// [2..=6]
-    #[inspector(min = 2, max = 8)]
+    #[cfg_attr(feature = "inspector", inspector(min = 2, max = 6))]
     pub cascade_count: u32,
     pub jitter_probe_position: bool,
     pub jitter_probe_direction: bool,
@@ -52,52 +71,78 @@ pub struct SSGIPass {
     /// If too low noise from aliasing will be visible when things are moving.
     pub noise_frame_period: u32,
     /// How much differences in depth affect interpolation between probes when combining cascades
-    #[inspector(min = 0.0)]
+    #[cfg_attr(feature = "inspector", inspector(min = 0.0))]
     pub distance_rejection: f32,
     /// How much differences in normals affect interpolation between probes when combining cascades
-    #[inspector(min = 0.0)]
+    #[cfg_attr(feature = "inspector", inspector(min = 0.0))]
     pub normal_rejection: f32,
     /// Controls the amount of light distance falloff. 0.0 to disable falloff
     pub falloff: f32,
     pub square_falloff: bool,
     /// SSGI Brightness
-    #[inspector(min = 0.0, max = 100.0)]
+    #[cfg_attr(feature = "inspector", inspector(min = 0.0, max = 100.0))]
     pub brightness: f32,
-    /// Non-PBR rough specular with simple fresnel
-    #[inspector(min = 0.0, max = 100.0)]
-    pub rough_specular: f32,
-    /// Non-PBR rough specular fresnel sharpness
-    #[inspector(min = 0.1, max = 100.0)]
-    pub rough_specular_sharpness: f32,
     /// How much light we accept from things pointing away from our position
     /// Allows us to still sample the color even if we hit something from the backside
     /// This is needed for top down where only the tops of things are visible
     /// 2.0 is needed for steep top down
-    #[inspector(min = -2.0, max = 2.0)]
+    #[cfg_attr(feature = "inspector", inspector(min = -2.0, max = 2.0))]
     pub backside_illumination: f32,
     /// Minimum mip to use for depth samples
-    #[inspector(min = 0.0, max = 5.0)]
+    #[cfg_attr(feature = "inspector", inspector(min = 0.0, max = 5.0))]
     pub depth_mip_min: f32,
     /// Minimum mip to use for normal, motion vector, color, samples
-    #[inspector(min = 0.0, max = 5.0)]
+    #[cfg_attr(feature = "inspector", inspector(min = 0.0, max = 5.0))]
     pub mip_min: f32,
     /// Maximum mip to use for normal, motion vector, color, samples
     /// Max is used for ray march steps further away from the ray origin
-    #[inspector(min = 0.0, max = 5.0)]
+    #[cfg_attr(feature = "inspector", inspector(min = 0.0, max = 5.0))]
     pub mip_max: f32,
-    #[inspector(min = 0.0, max = 1.0)]
+    #[cfg_attr(feature = "inspector", inspector(min = 0.0, max = 1.0))]
     /// How much cascade intervals overlap.
     pub interval_overlap: f32,
     /// Min distance each interval travels, the distance is screen space,
     /// but the unit only applies for cascade 0, higher cascades scale up non-linearly
-    #[inspector(min = 1.0, max = 1000.0)]
+    #[cfg_attr(feature = "inspector", inspector(min = 1.0, max = 1000.0))]
     pub cascade_0_dist: f32,
     // If this is false defined there will **less** ray march steps and it will be faster
     // but there can be more light leaking / inconsistencies
     pub divide_steps_by_square_of_cascade_exp: bool,
     // How much the raw horizon occlusion is used. Leave at 0.0 for bitmask occlusion;
-    #[inspector(min = 0.0, max = 100.0)]
+    // Samples with a finite thickness have no horizon, so it has no effect on them
+    #[cfg_attr(feature = "inspector", inspector(min = 0.0, max = 100.0))]
     pub horizon_occlusion: f32,
+    /// How much rays that leave the screen, or reach the end of the last cascade, sample the
+    /// camera's [`EnvironmentMapLight`] diffuse map. Weighted by how unoccluded the ray direction is.
+    /// 0.0 to disable. Has no effect if the camera has no [`EnvironmentMapLight`].
+    /// When enabled, this crate's deferred lighting pass leaves out Bevy's unoccluded
+    /// [`EnvironmentMapLight`] diffuse so it's only counted once, unless the camera also has
+    /// irradiance volumes. The composite ([`crate::composite::SSGICompositePlugin`]) and forward
+    /// ([`crate::SSGIForwardBundle`]) paths add the SSGI on top of Bevy's lighting, which still
+    /// includes the environment map diffuse, so there it's counted twice where rays escape.
+    #[cfg_attr(feature = "inspector", inspector(min = 0.0, max = 10.0))]
+    pub environment_fallback: f32,
+    /// How far behind its front face each depth sample occludes
+    pub thickness: SSGIThickness,
+    /// Render the back faces of the meshes to a second depth texture, and use it as the far side
+    /// of each occluder in the march, in addition to [`SSGIPass::thickness`]. Costs another depth
+    /// pass of the scene. Meshes with `NotShadowCaster` are left out.
+    pub back_face_prepass: bool,
+}
+
+/// How thick the surface behind each depth sample of the ray march is assumed to be. Only the
+/// directions between the front and back of a sample are occluded by it. With
+/// [`DepthReduction::MinMax`] and a [`SSGIThickness::Constant`], samples are at least as thick as
+/// the depth range under them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Reflect)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SSGIThickness {
+    /// Everything below the horizon of a sample is occluded, so thin objects like poles and
+    /// railings occlude as much as walls
+    #[default]
+    Infinite,
+    /// Each sample is this thick in world units, along the view ray. Must be positive.
+    Constant(f32),
 }
 
 impl Default for SSGIPass {
@@ -114,8 +159,6 @@ impl Default for SSGIPass {
             falloff: 1.0,
             square_falloff: false,
             brightness: 1.0,
-            rough_specular: 1.0,
-            rough_specular_sharpness: 3.0,
             backside_illumination: 0.0,
             depth_mip_min: 0.0,
             mip_min: 2.0,
@@ -124,16 +167,220 @@ impl Default for SSGIPass {
             cascade_0_dist: 21.0,
             divide_steps_by_square_of_cascade_exp: true,
             horizon_occlusion: 0.0,
+            environment_fallback: 0.0,
+            thickness: SSGIThickness::Infinite,
+            back_face_prepass: false,
         }
     }
 }
 
 impl SSGIPass {
+    /// Checks the constraints documented on each field, including the mip ranges against
+    /// [`PrepassDownsample::mip_levels`] and that every cascade has at least one probe for the viewport.
+    pub fn validate(
+        &self,
+        prepass_downsample: &PrepassDownsample,
+        viewport_size: UVec2,
+    ) -> Result<(), SSGIConfigError> {
+        if !RENDER_SCALE_RANGE.contains(&self.render_scale) || self.render_scale % 2 != 0 {
+            return Err(SSGIConfigError::RenderScale(self.render_scale));
+        }
+        if self.cascade_0_directions < 4 || self.cascade_0_directions % 4 != 0 {
+            return Err(SSGIConfigError::Cascade0Directions(
+                self.cascade_0_directions,
+            ));
+        }
+        if !CASCADE_COUNT_RANGE.contains(&self.cascade_count) {
+            return Err(SSGIConfigError::CascadeCount(self.cascade_count));
+        }
+        let max_mip = prepass_downsample.mip_levels().saturating_sub(1) as f32;
+        let mips_valid = self.depth_mip_min >= 0.0
+            && self.mip_min >= 0.0
+            && self.mip_min <= self.mip_max
+            && self.depth_mip_min <= max_mip
+            && self.mip_max <= max_mip;
+        if !mips_valid {
+            return Err(SSGIConfigError::MipRange {
+                depth_mip_min: self.depth_mip_min,
+                mip_min: self.mip_min,
+                mip_max: self.mip_max,
+                mip_levels: prepass_downsample.mip_levels(),
+            });
+        }
+        if let SSGIThickness::Constant(thickness) = self.thickness {
+            if !(thickness > 0.0 && thickness.is_finite()) {
+                return Err(SSGIConfigError::Thickness(thickness));
+            }
+        }
+        let required = self.min_viewport_size();
+        if viewport_size.x < required.x || viewport_size.y < required.y {
+            return Err(SSGIConfigError::ViewportTooSmall {
+                viewport_size,
+                required,
+            });
+        }
+        Ok(())
+    }
+
+    /// Smallest viewport where the highest cascade still has one probe
+    pub fn min_viewport_size(&self) -> UVec2 {
+        UVec2::splat(self.render_scale << self.cascade_count.saturating_sub(1))
+    }
+
+    /// Clamps the settings to the nearest values that pass [`SSGIPass::validate`].
+    /// If the viewport is too small even at the minimum cascade count it's left at the minimum.
+    /// A thickness that isn't positive falls back to [`SSGIThickness::Infinite`], and mips that
+    /// aren't finite fall back to their defaults.
+    pub fn clamp(&mut self, prepass_downsample: &PrepassDownsample, viewport_size: UVec2) {
+        self.render_scale = self
+            .render_scale
+            .clamp(*RENDER_SCALE_RANGE.start(), *RENDER_SCALE_RANGE.end())
+            / 2
+            * 2;
+        self.cascade_0_directions = (self.cascade_0_directions / 4 * 4).max(4);
+        self.cascade_count = self
+            .cascade_count
+            .clamp(*CASCADE_COUNT_RANGE.start(), *CASCADE_COUNT_RANGE.end());
+
+        // f32::clamp panics on a NaN bound, and NaN would pass through it as a value anyway
+        let default = SSGIPass::default();
+        for (mip, default_mip) in [
+            (&mut self.depth_mip_min, default.depth_mip_min),
+            (&mut self.mip_min, default.mip_min),
+            (&mut self.mip_max, default.mip_max),
+        ] {
+            if !mip.is_finite() {
+                *mip = default_mip;
+            }
+        }
+
+        let max_mip = prepass_downsample.mip_levels().saturating_sub(1) as f32;
+        self.depth_mip_min = self.depth_mip_min.clamp(0.0, max_mip);
+        self.mip_max = self.mip_max.clamp(0.0, max_mip);
+        self.mip_min = self.mip_min.clamp(0.0, self.mip_max);
+
+        if let SSGIThickness::Constant(thickness) = self.thickness {
+            if !(thickness > 0.0 && thickness.is_finite()) {
+                self.thickness = SSGIThickness::Infinite;
+            }
+        }
+
+        while self.cascade_count > *CASCADE_COUNT_RANGE.start() {
+            let required = self.min_viewport_size();
+            if viewport_size.x >= required.x && viewport_size.y >= required.y {
+                break;
+            }
+            self.cascade_count -= 1;
+        }
+    }
+
     pub fn key(&self) -> SSGIPipelineKey {
         SSGIPipelineKey {
             jitter_probe_position: self.jitter_probe_position,
             jitter_probe_direction: self.jitter_probe_direction,
             noise_frame_period: self.noise_frame_period,
+            debug_occlusion: false,
+            depth_min_max: false,
+            back_face_depth: self.back_face_prepass,
+        }
+    }
+}
+
+pub const RENDER_SCALE_RANGE: RangeInclusive<u32> = 2..=16;
+pub const CASCADE_COUNT_RANGE: RangeInclusive<u32> = 2..=6;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SSGIConfigError {
+    /// Must be a multiple of 2 within [`RENDER_SCALE_RANGE`]
+    RenderScale(u32),
+    /// Must be a non zero multiple of 4
+    Cascade0Directions(u32),
+    /// Must be within [`CASCADE_COUNT_RANGE`]
+    CascadeCount(u32),
+    /// Mips must be positive, `mip_min <= mip_max`, and below the number of mips the [`PrepassDownsample`] makes
+    MipRange {
+        depth_mip_min: f32,
+        mip_min: f32,
+        mip_max: f32,
+        mip_levels: u8,
+    },
+    /// [`SSGIThickness::Constant`] must be positive and finite
+    Thickness(f32),
+    /// The highest cascade would have no probes
+    ViewportTooSmall {
+        viewport_size: UVec2,
+        required: UVec2,
+    },
+}
+
+impl fmt::Display for SSGIConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SSGIConfigError::RenderScale(render_scale) => write!(
+                f,
+                "render_scale {render_scale} must be a multiple of 2 in {RENDER_SCALE_RANGE:?}"
+            ),
+            SSGIConfigError::Cascade0Directions(directions) => write!(
+                f,
+                "cascade_0_directions {directions} must be a non zero multiple of 4"
+            ),
+            SSGIConfigError::CascadeCount(cascade_count) => write!(
+                f,
+                "cascade_count {cascade_count} must be in {CASCADE_COUNT_RANGE:?}"
+            ),
+            SSGIConfigError::MipRange {
+                depth_mip_min,
+                mip_min,
+                mip_max,
+                mip_levels,
+            } => write!(
+                f,
+                "depth_mip_min {depth_mip_min}, mip_min {mip_min}, mip_max {mip_max} must be \
+                positive, with mip_min <= mip_max, and below PrepassDownsample mip_levels {mip_levels}"
+            ),
+            SSGIConfigError::Thickness(thickness) => {
+                write!(f, "thickness {thickness} must be positive and finite")
+            }
+            SSGIConfigError::ViewportTooSmall {
+                viewport_size,
+                required,
+            } => write!(
+                f,
+                "viewport {viewport_size} is smaller than the {required} needed for the highest cascade"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SSGIConfigError {}
+
+/// Logs and clamps any [`SSGIPass`] that would fail [`SSGIPass::validate`], before it's extracted.
+/// Clamping can't always make it valid, so each view only logs until it's valid again.
+pub fn validate_ssgi_pass(
+    mut warned: Local<HashSet<Entity>>,
+    mut views: Query<(Entity, &Camera, &mut SSGIPass, Option<&PrepassDownsample>)>,
+) {
+    warned.retain(|entity| views.contains(*entity));
+    for (entity, camera, mut ssgi_pass, prepass_downsample) in &mut views {
+        // Skip while minimized etc, so the settings aren't clamped for a transient size
+        let Some(viewport_size) = camera
+            .physical_viewport_size()
+            .filter(|size| size.x > 0 && size.y > 0)
+        else {
+            continue;
+        };
+        let default_prepass_downsample = PrepassDownsample::default();
+        let prepass_downsample = prepass_downsample.unwrap_or(&default_prepass_downsample);
+        match ssgi_pass.validate(prepass_downsample, viewport_size) {
+            Ok(()) => {
+                warned.remove(&entity);
+            }
+            Err(e) => {
+                if warned.insert(entity) {
+                    warn!("Invalid SSGIPass on {entity:?}, clamping: {e}");
+                }
+                ssgi_pass.clamp(prepass_downsample, viewport_size);
+            }
         }
     }
 }
@@ -143,6 +390,12 @@ pub struct SSGIPipelineKey {
     pub jitter_probe_position: bool,
     pub jitter_probe_direction: bool,
     pub noise_frame_period: u32,
+    /// Write the occlusion bitmask visibility to the cascades instead of radiance, for [`SSGIDebugView::OcclusionBitmask`]
+    pub debug_occlusion: bool,
+    /// The depth mips have the farthest depth too, for [`DepthReduction::MinMax`]
+    pub depth_min_max: bool,
+    /// Use the back face depth for the far side of occluders, for [`SSGIPass::back_face_prepass`]
+    pub back_face_depth: bool,
 }
 impl SSGIPipelineKey {
     pub fn shader_defs(&self, shader_defs: &mut Vec<ShaderDefVal>) {
@@ -156,39 +409,64 @@ impl SSGIPipelineKey {
         if self.jitter_probe_direction {
             shader_defs.push("JITTER_PROBE_DIRECTION".into());
         }
+        if self.debug_occlusion {
+            shader_defs.push("DEBUG_OCCLUSION".into());
+        }
+        if self.depth_min_max {
+            shader_defs.push("DEPTH_MIN_MAX".into());
+        }
+        if self.back_face_depth {
+            shader_defs.push("BACK_FACE_DEPTH".into());
+        }
+        #[cfg(all(
+            feature = "disocclusion",
+            not(all(feature = "webgl", target_arch = "wasm32"))
+        ))]
+        shader_defs.push("DISOCCLUSION".into());
     }
 }
 
 pub const SHADER_HANDLE: Handle<Shader> = Handle::weak_from_u128(845720938457230948);
 
 pub const CASCADE_FORMAT: TextureFormat = TextureFormat::Rgba32Uint;
+/// Same as `@workgroup_size` of the compute entry point in `ssgi.wgsl`
+pub const SSGI_WORKGROUP_SIZE: u32 = 8;
+
+wgsl_uniform! {
+    #[derive(Component, Clone, Copy, ShaderType, Debug, Default)]
+    pub struct SSGIConfig {
+        cas_w: u32,
+        cas_h: u32,
+        cascade_n: u32,
+        directions: u32,
+        cas_0_directions: u32,
+        cas_0_render_scale: u32,
+        cascade_count: u32,
+        render_scale: u32,
+        distance_rejection: f32,
+        normal_rejection: f32,
+        falloff: f32,
+        square_falloff: u32,
+        brightness: f32,
+        backside_illumination: f32,
+        depth_mip_min: f32,
+        mip_min: f32,
+        mip_max: f32,
+        interval_overlap: f32,
+        cascade_0_dist: f32,
+        divide_steps_by_square_of_cascade_exp: u32,
+        horizon_occlusion: f32,
+        environment_fallback: f32,
+        thickness: f32,
+        _webgl2_padding_1: f32,
+    }
+}
 
-#[derive(Component, Clone, Copy, ShaderType, Debug, Default)]
-pub struct SSGIConfig {
-    cas_w: u32,
-    cas_h: u32,
-    cascade_n: u32,
-    directions: u32,
-    cas_0_directions: u32,
-    cas_0_render_scale: u32,
-    cascade_count: u32,
-    render_scale: u32,
-    distance_rejection: f32,
-    normal_rejection: f32,
-    falloff: f32,
-    square_falloff: u32,
-    brightness: f32,
-    backside_illumination: f32,
-    depth_mip_min: f32,
-    mip_min: f32,
-    mip_max: f32,
-    interval_overlap: f32,
-    cascade_0_dist: f32,
-    divide_steps_by_square_of_cascade_exp: u32,
-    horizon_occlusion: f32,
-    _webgl2_padding_1: f32,
-    _webgl2_padding_2: f32,
-    _webgl2_padding_3: f32,
+/// The diffuse map and intensity of the camera's [`EnvironmentMapLight`], used for rays that leave the screen.
+#[derive(Component, Clone)]
+pub struct SSGIEnvironmentMap {
+    pub diffuse_map: Handle<Image>,
+    pub intensity: f32,
 }
 
 pub struct SSGISamplePlugin;
@@ -202,20 +480,36 @@ impl Plugin for SSGISamplePlugin {
         );
 
         app.register_type::<SSGIPass>()
-            .add_plugins(ExtractComponentPlugin::<SSGIPass>::default());
+            .add_plugins(ExtractComponentPlugin::<SSGIPass>::default())
+            .add_systems(PostUpdate, validate_ssgi_pass);
 
         let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
             return;
         };
 
         render_app
+            .add_systems(ExtractSchedule, extract_environment_maps)
             .add_systems(Render, prepare_textures.in_set(RenderSet::PrepareResources))
+            .add_systems(
+                Render,
+                (prepare_uniforms, prepare_bind_groups)
+                    .chain()
+                    .in_set(RenderSet::PrepareBindGroups),
+            )
             .init_resource::<SpecializedRenderPipelines<SSGILayout>>()
+            .init_resource::<SpecializedComputePipelines<SSGILayout>>()
+            .init_resource::<PerViewBindGroups<SSGIConfig>>()
             .add_systems(Render, (prepare_pipelines.in_set(RenderSet::Prepare),))
             .add_render_graph_node::<ViewNodeRunner<SSGIOpaquePass3dPbrLightingNode>>(
                 Core3d, SSGILabel,
             )
             .add_render_graph_edges(Core3d, (DownsampleLabel, SSGILabel, Node3d::StartMainPass));
+
+        #[cfg(all(
+            feature = "disocclusion",
+            not(all(feature = "webgl", target_arch = "wasm32"))
+        ))]
+        render_app.add_render_graph_edge(Core3d, DisocclusionLabel, SSGILabel);
     }
 
     fn finish(&self, app: &mut App) {
@@ -223,7 +517,21 @@ impl Plugin for SSGISamplePlugin {
             return;
         };
 
-        render_app.init_resource::<SSGILayout>();
+        render_app
+            .init_resource::<SSGILayout>()
+            .init_resource::<SSGISamplers>();
+    }
+}
+
+fn extract_environment_maps(
+    mut commands: Commands,
+    views: Extract<Query<(Entity, &EnvironmentMapLight), With<SSGIPass>>>,
+) {
+    for (entity, environment_map) in &views {
+        commands.get_or_spawn(entity).insert(SSGIEnvironmentMap {
+            diffuse_map: environment_map.diffuse_map.clone(),
+            intensity: environment_map.intensity,
+        });
     }
 }
 
@@ -237,49 +545,159 @@ impl ViewNode for SSGIOpaquePass3dPbrLightingNode {
     type ViewQuery = (
         &'static ViewUniformOffset,
         &'static SSGIPipeline,
-        &'static PrevFrameTexture,
-        &'static PrepassDownsampleTextures,
         &'static SSGITextures,
         &'static SSGIPass,
-        // todo webgl &'static DisocclusionTextures,
     );
 
     fn run(
         &self,
-        _graph_context: &mut RenderGraphContext,
+        graph_context: &mut RenderGraphContext,
         render_context: &mut RenderContext,
-        (
-            view_uniform_offset,
-            ssgi_pipelines,
-            prev_frame_tex,
-            prepass_downsample_texture,
-            ssgi_textures,
-            ssgi_pass,
-            // todo webgl disocclusion_textures,
-        ): QueryItem<Self::ViewQuery>,
+        (view_uniform_offset, ssgi_pipeline, ssgi_textures, ssgi_pass): QueryItem<Self::ViewQuery>,
         world: &World,
     ) -> Result<(), NodeRunError> {
         let pipeline_cache = world.resource::<PipelineCache>();
-        let ssgi_lighting_layout = world.resource::<SSGILayout>();
-        let images = world.resource::<RenderAssets<Image>>();
 
-        let Some(ssgi_pipeline) =
-            pipeline_cache.get_render_pipeline(ssgi_pipelines.ssgi_pipeline_id)
+        let Some(view_bind_groups) = world
+            .resource::<PerViewBindGroups<SSGIConfig>>()
+            .0
+            .get(&graph_context.view_entity())
         else {
             return Ok(());
         };
 
-        let blue_noise_tex = image!(images, &resource!(world, BlueNoise).0);
-        let nearest_sampler = nearest_sampler(render_context.render_device());
-        let linear_sampler = linear_sampler(render_context.render_device());
-
         for cascade_n in (0..ssgi_pass.cascade_count as usize).rev() {
+            let Some(bind_group) = view_bind_groups.bind_group(cascade_n) else {
+                return Ok(());
+            };
+            let dynamic_offsets = [
+                view_uniform_offset.offset,
+                view_bind_groups.offsets[cascade_n],
+            ];
+
+            match ssgi_pipeline {
+                SSGIPipeline::Fragment(pipeline_id) => {
+                    let Some(pipeline) = pipeline_cache.get_render_pipeline(*pipeline_id) else {
+                        return Ok(());
+                    };
+
+                    let attachments = [
+                        Some(RenderPassColorAttachment {
+                            view: &ssgi_textures.data_textures1[cascade_n].default_view,
+                            resolve_target: None,
+                            ops: Operations::default(),
+                        }),
+                        Some(RenderPassColorAttachment {
+                            view: &ssgi_textures.data_textures2[cascade_n].default_view,
+                            resolve_target: None,
+                            ops: Operations::default(),
+                        }),
+                    ];
+
+                    run_pass(
+                        render_context,
+                        "ssgi_lighting_pass",
+                        &attachments,
+                        pipeline,
+                        bind_group,
+                        &dynamic_offsets,
+                    );
+                }
+                SSGIPipeline::Compute(pipeline_id) => {
+                    let Some(pipeline) = pipeline_cache.get_compute_pipeline(*pipeline_id) else {
+                        return Ok(());
+                    };
+
+                    let texture = &ssgi_textures.data_textures1[cascade_n].texture;
+                    run_compute_pass(
+                        render_context,
+                        "ssgi_lighting_compute_pass",
+                        pipeline,
+                        bind_group,
+                        &dynamic_offsets,
+                        UVec2::new(texture.width(), texture.height()),
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn run_pass(
+    render_context: &mut RenderContext,
+    pass_name: &str,
+    attachments: &[Option<RenderPassColorAttachment<'_>>],
+    pipeline: &RenderPipeline,
+    bind_group: &BindGroup,
+    dynamic_offsets: &[u32],
+) {
+    let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+        label: Some(pass_name),
+        color_attachments: attachments,
+        depth_stencil_attachment: None,
+        timestamp_writes: None,
+        occlusion_query_set: None,
+    });
+
+    render_pass.set_render_pipeline(pipeline);
+    render_pass.set_bind_group(0, bind_group, dynamic_offsets);
+    render_pass.draw(0..3, 0..1);
+}
+
+fn run_compute_pass(
+    render_context: &mut RenderContext,
+    pass_name: &str,
+    pipeline: &ComputePipeline,
+    bind_group: &BindGroup,
+    dynamic_offsets: &[u32],
+    size: UVec2,
+) {
+    let mut compute_pass =
+        render_context
+            .command_encoder()
+            .begin_compute_pass(&ComputePassDescriptor {
+                label: Some(pass_name),
+                timestamp_writes: None,
+            });
+
+    compute_pass.set_pipeline(pipeline);
+    compute_pass.set_bind_group(0, bind_group, dynamic_offsets);
+    compute_pass.dispatch_workgroups(
+        size.x.div_ceil(SSGI_WORKGROUP_SIZE),
+        size.y.div_ceil(SSGI_WORKGROUP_SIZE),
+        1,
+    );
+}
+
+fn prepare_uniforms(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    images: Res<RenderAssets<Image>>,
+    mut bind_groups: ResMut<PerViewBindGroups<SSGIConfig>>,
+    views: Query<(
+        Entity,
+        &SSGIPass,
+        &SSGITextures,
+        Option<&SSGIEnvironmentMap>,
+    )>,
+) {
+    bind_groups.0.retain(|entity, _| views.contains(*entity));
+
+    for (entity, ssgi_pass, ssgi_textures, environment_map) in &views {
+        // No environment light if there's no environment map or it's not loaded yet
+        let environment_intensity = environment_map
+            .filter(|env| images.get(&env.diffuse_map).is_some())
+            .map_or(0.0, |env| env.intensity);
+
+        let configs = (0..ssgi_pass.cascade_count as usize).map(|cascade_n| {
             let scale = 1 << cascade_n;
 
             let directions = ssgi_pass.cascade_0_directions * (1 << (cascade_n * 1));
 
             let cascade_n_u32 = cascade_n as u32;
-            let config = SSGIConfig {
+            SSGIConfig {
                 cas_w: ssgi_textures.data_textures1[cascade_n].texture.width()
                     / ssgi_pass.cascade_0_directions,
                 cas_h: ssgi_textures.data_textures1[cascade_n].texture.height(),
@@ -304,115 +722,211 @@ impl ViewNode for SSGIOpaquePass3dPbrLightingNode {
                     .divide_steps_by_square_of_cascade_exp
                     as u32,
                 horizon_occlusion: ssgi_pass.horizon_occlusion,
+                environment_fallback: ssgi_pass.environment_fallback * environment_intensity,
+                thickness: match ssgi_pass.thickness {
+                    SSGIThickness::Infinite => 0.0,
+                    SSGIThickness::Constant(thickness) => thickness,
+                },
                 _webgl2_padding_1: 0.0,
-                _webgl2_padding_2: 0.0,
-                _webgl2_padding_3: 0.0,
-            };
-
-            let uniform = uniform_buffer(config, render_context, "SSGI Config Uniform");
-            {
-                let mut cas_read_tex_index = cascade_n + 1;
-
-                if cas_read_tex_index >= ssgi_pass.cascade_count as usize {
-                    cas_read_tex_index = 0; // Wont be used in this, just as placeholder binding
-                }
-
-                let bind_group_1 = render_context.render_device().create_bind_group(
-                    "ssgi_lighting_layout_group_1",
-                    &ssgi_lighting_layout.bind_group_layout,
-                    &BindGroupEntries::with_indices((
-                        (0, view_binding(world)),
-                        (9, globals_binding(world)),
-                        (101, &prev_frame_tex.texture.default_view),
-                        (102, &prepass_downsample_texture.normals.default_view),
-                        (103, &prepass_downsample_texture.depth.default_view),
-                        (104, &prepass_downsample_texture.motion.default_view),
-                        (105, &nearest_sampler),
-                        (106, &linear_sampler),
-                        // todo webgl (8, &disocclusion_textures.output.default_view),
-                        (109, uniform.as_entire_binding()),
-                        (BLUE_NOISE_ENTRY_N, &blue_noise_tex.texture_view),
-                        (
-                            110,
-                            &ssgi_textures.data_textures1[cas_read_tex_index].default_view,
-                        ),
-                        (
-                            111,
-                            &ssgi_textures.data_textures2[cas_read_tex_index].default_view,
-                        ),
-                    )),
-                );
-
-                let attachments = [
-                    Some(RenderPassColorAttachment {
-                        view: &ssgi_textures.data_textures1[cascade_n].default_view,
-                        resolve_target: None,
-                        ops: Operations::default(),
-                    }),
-                    Some(RenderPassColorAttachment {
-                        view: &ssgi_textures.data_textures2[cascade_n].default_view,
-                        resolve_target: None,
-                        ops: Operations::default(),
-                    }),
-                ];
-
-                run_pass(
-                    render_context,
-                    "ssgi_lighting_pass",
-                    &attachments,
-                    ssgi_pipeline,
-                    view_uniform_offset,
-                    bind_group_1,
-                );
             }
-        }
+        });
 
-        Ok(())
+        bind_groups.0.entry(entity).or_default().write_uniforms(
+            &render_device,
+            &render_queue,
+            configs,
+        );
     }
 }
 
-fn run_pass(
-    render_context: &mut RenderContext,
-    pass_name: &str,
-    attachments: &[Option<RenderPassColorAttachment<'_>>],
-    pipeline: &RenderPipeline,
-    view_uniform_offset: &ViewUniformOffset,
-    bind_group: BindGroup,
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn prepare_bind_groups(
+    render_device: Res<RenderDevice>,
+    ssgi_lighting_layout: Res<SSGILayout>,
+    samplers: Res<SSGISamplers>,
+    view_uniforms: Res<ViewUniforms>,
+    globals_buffer: Res<GlobalsBuffer>,
+    images: Res<RenderAssets<Image>>,
+    fallback_image: Res<FallbackImage>,
+    blue_noise: Option<Res<BlueNoise>>,
+    mut bind_groups: ResMut<PerViewBindGroups<SSGIConfig>>,
+    views: Query<(
+        Entity,
+        &SSGIPass,
+        &PrevFrameTexture,
+        &PrepassDownsampleTextures,
+        &SSGITextures,
+        Option<&SSGIEnvironmentMap>,
+    )>,
+    #[cfg(all(
+        feature = "disocclusion",
+        not(all(feature = "webgl", target_arch = "wasm32"))
+    ))]
+    disocclusion_textures: Query<&DisocclusionTextures>,
 ) {
-    let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
-        label: Some(pass_name),
-        color_attachments: &attachments,
-        depth_stencil_attachment: None,
-        timestamp_writes: None,
-        occlusion_query_set: None,
-    });
+    let (Some(view_binding), Some(globals_binding)) = (
+        view_uniforms.uniforms.binding(),
+        globals_buffer.buffer.binding(),
+    ) else {
+        return;
+    };
+    let Some(blue_noise_tex) = blue_noise.and_then(|blue_noise| images.get(&blue_noise.0)) else {
+        return;
+    };
 
-    render_pass.set_render_pipeline(pipeline);
-    render_pass.set_bind_group(0, &bind_group, &[view_uniform_offset.offset]);
-    render_pass.draw(0..3, 0..1);
+    for (
+        entity,
+        ssgi_pass,
+        prev_frame_tex,
+        prepass_downsample_texture,
+        ssgi_textures,
+        environment_map,
+    ) in &views
+    {
+        let Some(view_bind_groups) = bind_groups.0.get_mut(&entity) else {
+            continue;
+        };
+        let Some(config_binding) = view_bind_groups.uniforms.binding() else {
+            continue;
+        };
+
+        // Fall back to a black cube map if there's no environment map or it's not loaded yet
+        let environment_map_view = environment_map
+            .and_then(|env| images.get(&env.diffuse_map))
+            .map_or(&fallback_image.cube.texture_view, |image| {
+                &image.texture_view
+            });
+
+        #[cfg(all(
+            feature = "disocclusion",
+            not(all(feature = "webgl", target_arch = "wasm32"))
+        ))]
+        let Ok(disocclusion_textures) = disocclusion_textures.get(entity) else {
+            continue;
+        };
+
+        // Only read with SSGIPass::back_face_prepass, just a placeholder binding otherwise
+        let back_face_depth_view = prepass_downsample_texture
+            .back_face_depth
+            .as_ref()
+            .map_or(&prepass_downsample_texture.depth.default_view, |texture| {
+                &texture.default_view
+            });
+
+        view_bind_groups
+            .bind_groups
+            .resize_with(ssgi_pass.cascade_count as usize, Default::default);
+
+        for cascade_n in 0..ssgi_pass.cascade_count as usize {
+            let mut cas_read_tex_index = cascade_n + 1;
+
+            if cas_read_tex_index >= ssgi_pass.cascade_count as usize {
+                cas_read_tex_index = 0; // Wont be used in this, just as placeholder binding
+            }
+
+            let mut entries = BindGroupEntries::with_indices((
+                (0, view_binding.clone()),
+                (9, globals_binding.clone()),
+                (101, &prev_frame_tex.texture.default_view),
+                (102, &prepass_downsample_texture.normals.default_view),
+                (103, &prepass_downsample_texture.depth.default_view),
+                (104, &prepass_downsample_texture.motion.default_view),
+                (105, &samplers.nearest),
+                (106, &samplers.linear),
+                (107, environment_map_view),
+                (109, config_binding.clone()),
+                (BLUE_NOISE_ENTRY_N, &blue_noise_tex.texture_view),
+                (
+                    110,
+                    &ssgi_textures.data_textures1[cas_read_tex_index].default_view,
+                ),
+                (
+                    111,
+                    &ssgi_textures.data_textures2[cas_read_tex_index].default_view,
+                ),
+                (114, back_face_depth_view),
+            ))
+            .to_vec();
+
+            #[cfg(all(
+                feature = "disocclusion",
+                not(all(feature = "webgl", target_arch = "wasm32"))
+            ))]
+            entries.push(BindGroupEntry {
+                binding: 108,
+                resource: BindingResource::TextureView(&disocclusion_textures.output.default_view),
+            });
+
+            let layout = match &ssgi_lighting_layout.compute_bind_group_layout {
+                Some(compute_layout) => {
+                    entries.extend_from_slice(&BindGroupEntries::with_indices((
+                        (112, &ssgi_textures.data_textures1[cascade_n].default_view),
+                        (113, &ssgi_textures.data_textures2[cascade_n].default_view),
+                    )));
+                    compute_layout
+                }
+                None => &ssgi_lighting_layout.bind_group_layout,
+            };
+
+            view_bind_groups.bind_groups[cascade_n].update(
+                &render_device,
+                "ssgi_lighting_layout_group_1",
+                layout,
+                &entries,
+            );
+        }
+    }
 }
 
 impl FromWorld for SSGILayout {
     fn from_world(world: &mut World) -> Self {
         let render_device = world.resource::<RenderDevice>();
-        let layout = render_device.create_bind_group_layout(
-            Some("ssgi_lighting_layout"),
-            &[
-                view_layout_entry(0),
-                globals_layout_entry(9),
-                ftexture_layout_entry(101, TextureViewDimension::D2), // Prev frame
-                ftexture_layout_entry(102, TextureViewDimension::D2), // Prepass Downsample Normals
-                ftexture_layout_entry(103, TextureViewDimension::D2), // Prepass Downsample Depth
-                ftexture_layout_entry(104, TextureViewDimension::D2), // Prepass Downsample Motion
-                fsampler_layout_entry(105),                           // Nearest Sampler
-                fsampler_layout_entry(106),                           // Linear Sampler
-                // todo webgl ftexture_layout_entry(108, TextureViewDimension::D2), // Disocclusion
-                uniform_layout_entry(109, SSGIConfig::min_size()),
-                ftexture_layout_entry(BLUE_NOISE_ENTRY_N, TextureViewDimension::D2Array), // Blue Noise
-                utexture_layout_entry(110, TextureViewDimension::D2), // Higher Cascade Data Texture 1
-                utexture_layout_entry(111, TextureViewDimension::D2), // Higher Cascade Data Texture 2
-            ],
-        );
+        #[allow(unused_mut)]
+        let mut entries = vec![
+            view_layout_entry(0),
+            globals_layout_entry(9),
+            ftexture_layout_entry(101, TextureViewDimension::D2), // Prev frame
+            ftexture_layout_entry(102, TextureViewDimension::D2), // Prepass Downsample Normals
+            ftexture_layout_entry(103, TextureViewDimension::D2), // Prepass Downsample Depth
+            ftexture_layout_entry(104, TextureViewDimension::D2), // Prepass Downsample Motion
+            fsampler_layout_entry(105),                           // Nearest Sampler
+            fsampler_layout_entry(106),                           // Linear Sampler
+            ftexture_layout_entry(107, TextureViewDimension::Cube), // Environment Map
+            dynamic_uniform_layout_entry(109, SSGIConfig::min_size()),
+            ftexture_layout_entry(BLUE_NOISE_ENTRY_N, TextureViewDimension::D2Array), // Blue Noise
+            utexture_layout_entry(110, TextureViewDimension::D2), // Higher Cascade Data Texture 1
+            utexture_layout_entry(111, TextureViewDimension::D2), // Higher Cascade Data Texture 2
+            // Back Face Depth, unfilterable so Depth32Float can be bound
+            texture_layout_entry(
+                114,
+                TextureViewDimension::D2,
+                TextureSampleType::Float { filterable: false },
+            ),
+        ];
+
+        #[cfg(all(
+            feature = "disocclusion",
+            not(all(feature = "webgl", target_arch = "wasm32"))
+        ))]
+        entries.push(ftexture_layout_entry(108, TextureViewDimension::D2)); // Disocclusion
+
+        let layout = render_device.create_bind_group_layout(Some("ssgi_lighting_layout"), &entries);
+
+        let compute_layout = compute_supported(render_device).then(|| {
+            let mut compute_entries: Vec<_> = entries
+                .iter()
+                .map(|entry| BindGroupLayoutEntry {
+                    visibility: ShaderStages::COMPUTE,
+                    ..*entry
+                })
+                .collect();
+            compute_entries.extend([
+                storage_texture_layout_entry(112, CASCADE_FORMAT), // Cascade Data Texture 1
+                storage_texture_layout_entry(113, CASCADE_FORMAT), // Cascade Data Texture 2
+            ]);
+            render_device
+                .create_bind_group_layout(Some("ssgi_lighting_compute_layout"), &compute_entries)
+        });
 
         #[cfg(not(all(feature = "file_watcher")))]
         let shader = SHADER_HANDLE;
@@ -424,6 +938,7 @@ impl FromWorld for SSGILayout {
 
         Self {
             bind_group_layout: layout,
+            compute_bind_group_layout: compute_layout,
             ssgi_shader: shader,
         }
     }
@@ -432,18 +947,18 @@ impl FromWorld for SSGILayout {
 #[derive(Resource)]
 pub struct SSGILayout {
     bind_group_layout: BindGroupLayout,
+    /// Same bindings plus the cascade outputs as storage textures. None if compute isn't
+    /// supported, like on WebGL2, in which case the cascades are rendered with fragment shaders.
+    compute_bind_group_layout: Option<BindGroupLayout>,
     pub ssgi_shader: Handle<Shader>,
 }
 
-#[derive(Component)]
-pub struct SSGIPipeline {
-    pub ssgi_pipeline_id: CachedRenderPipelineId,
-}
-
-impl SpecializedRenderPipeline for SSGILayout {
-    type Key = SSGIPipelineKey;
+impl SSGILayout {
+    pub fn uses_compute(&self) -> bool {
+        self.compute_bind_group_layout.is_some()
+    }
 
-    fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
+    fn shader_defs(&self, key: SSGIPipelineKey) -> Vec<ShaderDefVal> {
         let mut shader_defs = Vec::new();
 
         #[cfg(all(feature = "webgl", target_arch = "wasm32"))]
@@ -463,6 +978,31 @@ impl SpecializedRenderPipeline for SSGILayout {
         // Always true, since we're in the deferred lighting pipeline
         shader_defs.push("DEFERRED_PREPASS".into());
 
+        shader_defs
+    }
+}
+
+/// WebGL2 has no compute shaders or storage textures
+fn compute_supported(render_device: &RenderDevice) -> bool {
+    let limits = render_device.limits();
+    limits.max_compute_invocations_per_workgroup >= SSGI_WORKGROUP_SIZE * SSGI_WORKGROUP_SIZE
+        // The depth tile in ssgi.wgsl, two channels with DepthReduction::MinMax
+        && limits.max_compute_workgroup_storage_size >= 32 * 32 * 8
+        && limits.max_storage_textures_per_shader_stage >= 2
+}
+
+#[derive(Component)]
+pub enum SSGIPipeline {
+    Fragment(CachedRenderPipelineId),
+    Compute(CachedComputePipelineId),
+}
+
+impl SpecializedRenderPipeline for SSGILayout {
+    type Key = SSGIPipelineKey;
+
+    fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
+        let shader_defs = self.shader_defs(key);
+
         RenderPipelineDescriptor {
             label: Some("ssgi_lighting_pipeline".into()),
             layout: vec![self.bind_group_layout.clone()],
@@ -492,19 +1032,56 @@ impl SpecializedRenderPipeline for SSGILayout {
     }
 }
 
+impl SpecializedComputePipeline for SSGILayout {
+    type Key = SSGIPipelineKey;
+
+    fn specialize(&self, key: Self::Key) -> ComputePipelineDescriptor {
+        let mut shader_defs = self.shader_defs(key);
+        shader_defs.push("COMPUTE".into());
+
+        ComputePipelineDescriptor {
+            label: Some("ssgi_lighting_compute_pipeline".into()),
+            layout: self.compute_bind_group_layout.clone().into_iter().collect(),
+            push_constant_ranges: vec![],
+            shader: self.ssgi_shader.clone(),
+            shader_defs,
+            entry_point: "compute".into(),
+        }
+    }
+}
+
 pub fn prepare_pipelines(
     mut commands: Commands,
     pipeline_cache: Res<PipelineCache>,
     mut pipelines: ResMut<SpecializedRenderPipelines<SSGILayout>>,
+    mut compute_pipelines: ResMut<SpecializedComputePipelines<SSGILayout>>,
     ssgi_lighting_layout: Res<SSGILayout>,
-    views: Query<(Entity, &SSGIPass)>,
+    views: Query<(
+        Entity,
+        &SSGIPass,
+        Option<&SSGIDebugView>,
+        Option<&PrepassDownsample>,
+    )>,
 ) {
-    for (entity, ssgi_pass) in &views {
-        let ssgi_pipeline_id: CachedRenderPipelineId =
-            pipelines.specialize(&pipeline_cache, &ssgi_lighting_layout, ssgi_pass.key());
-        commands
-            .entity(entity)
-            .insert(SSGIPipeline { ssgi_pipeline_id });
+    for (entity, ssgi_pass, debug_view, prepass_downsample) in &views {
+        let mut key = ssgi_pass.key();
+        key.debug_occlusion = matches!(debug_view, Some(SSGIDebugView::OcclusionBitmask(_)));
+        key.depth_min_max = prepass_downsample
+            .is_some_and(|downsample| downsample.depth_reduction == DepthReduction::MinMax);
+        let pipeline = if ssgi_lighting_layout.uses_compute() {
+            SSGIPipeline::Compute(compute_pipelines.specialize(
+                &pipeline_cache,
+                &ssgi_lighting_layout,
+                key,
+            ))
+        } else {
+            SSGIPipeline::Fragment(pipelines.specialize(
+                &pipeline_cache,
+                &ssgi_lighting_layout,
+                key,
+            ))
+        };
+        commands.entity(entity).insert(pipeline);
     }
 }
 
@@ -518,8 +1095,16 @@ fn prepare_textures(
     mut commands: Commands,
     mut texture_cache: ResMut<TextureCache>,
     render_device: Res<RenderDevice>,
+    ssgi_lighting_layout: Res<SSGILayout>,
     views: Query<(Entity, &ExtractedCamera, &SSGIPass)>,
 ) {
+    let usage = TextureUsages::TEXTURE_BINDING
+        | if ssgi_lighting_layout.uses_compute() {
+            TextureUsages::STORAGE_BINDING
+        } else {
+            TextureUsages::RENDER_ATTACHMENT
+        };
+
     for (entity, camera, ssgi_pass) in &views {
         if let Some(physical_viewport_size) = camera.physical_viewport_size {
             let mut data_textures1 = Vec::new();
@@ -545,7 +1130,7 @@ fn prepare_textures(
                     sample_count: 1,
                     dimension: TextureDimension::D2,
                     format: CASCADE_FORMAT,
-                    usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+                    usage,
                     view_formats: &[],
                 };
 
@@ -64,6 +64,47 @@ fn frag_coord_for_cas(cascade_n: u32, cas_xy: vec2<i32>, cas_0_render_scale: u32
     return frag_coord;
 }
 
+struct ProbeGeometry {
+    world_position: vec3<f32>,
+    normal: vec3<f32>,
+}
+
+// Position and normal of the surface a probe was placed on
+fn load_probe_geometry(
+    icas_coord: vec2<i32>, 
+    cascade_n: u32, 
+    cas_0_render_scale: u32,
+    prepass_downsample_normals: texture_2d<f32>,
+    prepass_downsample_depth: texture_2d<f32>,
+) -> ProbeGeometry {
+    let gather_frag_coord = vec2<i32>(frag_coord_for_cas(cascade_n, icas_coord, cas_0_render_scale));
+    let gather_uv = vec2<f32>(gather_frag_coord) / view.viewport.zw;
+    let gather_depth = textureLoad(prepass_downsample_depth, gather_frag_coord, 0).x;
+
+    var probe: ProbeGeometry;
+    probe.world_position = vt::position_ndc_to_world(vec3(vt::uv_to_ndc(gather_uv), gather_depth));
+    probe.normal = octahedral_decode(textureLoad(prepass_downsample_normals, gather_frag_coord, 0).xy);
+    return probe;
+}
+
+// How much a probe applies to a surface, from the differences in normals and position. Probes on
+// the same plane are rejected less by distance. Can be 0.0 when the probe is too far away.
+fn probe_weight(
+    probe: ProbeGeometry,
+    normal: vec3<f32>, 
+    world_position: vec3<f32>, 
+    normal_rejection: f32, 
+    distance_rejection: f32, 
+    pixel_radius: f32,
+) -> f32 {
+    let m_pixel_radius = pixel_radius * 1000.0;
+    let coplanar = sampling::coplanar(world_position, probe.normal, probe.world_position, normal, 0.95, pixel_radius * 5.0);
+    let dist_reject = select(distance_rejection, distance_rejection * 0.25, coplanar);
+    var weight = max(pow(dot(normal, probe.normal), normal_rejection), 0.001);
+    weight *= max(1.0 - saturate(distance(probe.world_position, world_position) / m_pixel_radius * dist_reject), 0.0);
+    return weight;
+}
+
 fn weight_bilinear(
     aa: ptr<function, f32>, 
     ba: ptr<function, f32>, 
@@ -81,55 +122,19 @@ fn weight_bilinear(
     prepass_downsample_depth: texture_2d<f32>,
     pixel_radius: f32,
 ) {
-    var gather_frag_coord: vec2<i32>;
-    var gather_depth: f32;
-    var gather_normal: vec3<f32>;
-    var gather_uv: vec2<f32>;
-    var gather_ws_pos: vec3<f32>;
-    var coplanar: bool;
-    var dist_reject: f32;
-    let m_pixel_radius = pixel_radius * 1000.0;
+    var probe: ProbeGeometry;
+
+    probe = load_probe_geometry(icas_coord + vec2(0, 0), cascade_n, cas_0_render_scale, prepass_downsample_normals, prepass_downsample_depth);
+    *aa *= probe_weight(probe, normal, world_position, normal_rejection, distance_rejection, pixel_radius);
 
-    gather_frag_coord = vec2<i32>(frag_coord_for_cas(cascade_n, (icas_coord + vec2(0, 0)), cas_0_render_scale));
-    gather_uv = vec2<f32>(gather_frag_coord) / view.viewport.zw;
-    gather_normal = octahedral_decode(textureLoad(prepass_downsample_normals, gather_frag_coord, 0).xy);
-    gather_depth = textureLoad(prepass_downsample_depth, gather_frag_coord, 0).x;
-    gather_ws_pos = vt::position_ndc_to_world(vec3(vt::uv_to_ndc(gather_uv), gather_depth));
-    coplanar = sampling::coplanar(world_position, gather_normal, gather_ws_pos, normal, 0.95, pixel_radius * 5.0);
-    dist_reject = select(distance_rejection, distance_rejection * 0.25, coplanar);
-    *aa *= max(pow(dot(normal, gather_normal), normal_rejection), 0.001);
-    *aa *= max(1.0 - saturate(distance(gather_ws_pos, world_position) / m_pixel_radius * dist_reject), 0.0);
-
-
-    gather_frag_coord = vec2<i32>(frag_coord_for_cas(cascade_n, (icas_coord + vec2(1, 0)), cas_0_render_scale));
-    gather_uv = vec2<f32>(gather_frag_coord) / view.viewport.zw;
-    gather_normal = octahedral_decode(textureLoad(prepass_downsample_normals, gather_frag_coord, 0).xy);
-    gather_depth = textureLoad(prepass_downsample_depth, gather_frag_coord, 0).x;
-    gather_ws_pos = vt::position_ndc_to_world(vec3(vt::uv_to_ndc(gather_uv), gather_depth));
-    coplanar = sampling::coplanar(world_position, gather_normal, gather_ws_pos, normal, 0.95, pixel_radius * 5.0);
-    dist_reject = select(distance_rejection, distance_rejection * 0.25, coplanar);
-    *ba *= max(pow(dot(normal, gather_normal), normal_rejection), 0.001);
-    *ba *= max(1.0 - saturate(distance(gather_ws_pos, world_position) / m_pixel_radius * dist_reject), 0.0);
-
-    gather_frag_coord = vec2<i32>(frag_coord_for_cas(cascade_n, (icas_coord + vec2(0, 1)), cas_0_render_scale));
-    gather_uv = vec2<f32>(gather_frag_coord) / view.viewport.zw;
-    gather_normal = octahedral_decode(textureLoad(prepass_downsample_normals, gather_frag_coord, 0).xy);
-    gather_depth = textureLoad(prepass_downsample_depth, gather_frag_coord, 0).x;
-    gather_ws_pos = vt::position_ndc_to_world(vec3(vt::uv_to_ndc(gather_uv), gather_depth));
-    coplanar = sampling::coplanar(world_position, gather_normal, gather_ws_pos, normal, 0.95, pixel_radius * 5.0);
-    dist_reject = select(distance_rejection, distance_rejection * 0.25, coplanar);
-    *ab *= max(pow(dot(normal, gather_normal), normal_rejection), 0.001);
-    *ab *= max(1.0 - saturate(distance(gather_ws_pos, world_position) / m_pixel_radius * dist_reject), 0.0);
-
-    gather_frag_coord = vec2<i32>(frag_coord_for_cas(cascade_n, (icas_coord + vec2(1, 1)), cas_0_render_scale));
-    gather_uv = vec2<f32>(gather_frag_coord) / view.viewport.zw;
-    gather_normal = octahedral_decode(textureLoad(prepass_downsample_normals, gather_frag_coord, 0).xy);
-    gather_depth = textureLoad(prepass_downsample_depth, gather_frag_coord, 0).x;
-    gather_ws_pos = vt::position_ndc_to_world(vec3(vt::uv_to_ndc(gather_uv), gather_depth));
-    coplanar = sampling::coplanar(world_position, gather_normal, gather_ws_pos, normal, 0.95, pixel_radius * 5.0);
-    dist_reject = select(distance_rejection, distance_rejection * 0.25, coplanar);
-    *bb *= max(pow(dot(normal, gather_normal), normal_rejection), 0.001);
-    *bb *= max(1.0 - saturate(distance(gather_ws_pos, world_position) / m_pixel_radius * dist_reject), 0.0);
+    probe = load_probe_geometry(icas_coord + vec2(1, 0), cascade_n, cas_0_render_scale, prepass_downsample_normals, prepass_downsample_depth);
+    *ba *= probe_weight(probe, normal, world_position, normal_rejection, distance_rejection, pixel_radius);
+
+    probe = load_probe_geometry(icas_coord + vec2(0, 1), cascade_n, cas_0_render_scale, prepass_downsample_normals, prepass_downsample_depth);
+    *ab *= probe_weight(probe, normal, world_position, normal_rejection, distance_rejection, pixel_radius);
+
+    probe = load_probe_geometry(icas_coord + vec2(1, 1), cascade_n, cas_0_render_scale, prepass_downsample_normals, prepass_downsample_depth);
+    *bb *= probe_weight(probe, normal, world_position, normal_rejection, distance_rejection, pixel_radius);
 
     // Renormalize
     let sum = 1.0 / (*aa + *ba + *ab + *bb);
@@ -137,4 +142,17 @@ fn weight_bilinear(
     *ba *= sum;
     *ab *= sum;
     *bb *= sum;
-}
\ No newline at end of file
+}
+// How disoccluded a pixel is, from bevy_mod_taa's disocclusion mask.
+// Uses the middle of the three channels so one noisy channel alone doesn't reject the history.
+fn disocclusion_amount(d: vec4<f32>) -> f32 {
+    let two_of_three = min(min(max(d.x, d.y), max(d.y, d.z)), max(d.x, d.z));
+    return saturate(two_of_three * 3.0);
+}
+
+// Frames accumulated in a texel after this one, from the reprojected length of its history.
+// Rejected history doesn't count, so disoccluded texels start over and converge quickly.
+// The current frame is blended in with a weight of 1 / length.
+fn next_history_length(prev_length: f32, rejection: f32, min_length: f32, max_length: f32) -> f32 {
+    return clamp(max(prev_length, 0.0) * (1.0 - rejection) + 1.0, min_length, max_length);
+}
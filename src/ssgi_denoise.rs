@@ -0,0 +1,491 @@
+use bevy::{
+    asset::load_internal_asset,
+    core_pipeline::{
+        core_3d::graph::Core3d, fullscreen_vertex_shader::fullscreen_shader_vertex_state,
+    },
+    prelude::*,
+    render::{
+        camera::ExtractedCamera,
+        extract_component::{ExtractComponent, ExtractComponentPlugin},
+        globals::GlobalsBuffer,
+        render_asset::RenderAssets,
+        render_graph::{Node, NodeRunError, RenderGraphApp, RenderGraphContext, RenderLabel},
+        render_resource::{
+            BindGroupEntries, BindGroupLayout, CachedRenderPipelineId, ColorTargetState,
+            ColorWrites, Extent3d, FragmentState, MultisampleState, Operations, PipelineCache,
+            PrimitiveState, RenderPassColorAttachment, RenderPassDescriptor,
+            RenderPipelineDescriptor, ShaderDefVal, ShaderType, SpecializedRenderPipeline,
+            SpecializedRenderPipelines, TextureDescriptor, TextureDimension, TextureFormat,
+            TextureUsages, TextureViewDimension,
+        },
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+        texture::{CachedTexture, TextureCache},
+        view::{ExtractedView, ViewUniformOffset, ViewUniforms},
+        Render, RenderApp, RenderSet,
+    },
+};
+
+#[cfg(feature = "inspector")]
+use bevy_inspector_egui::{inspector_options::ReflectInspectorOptions, InspectorOptions};
+
+use crate::{
+    bind_group_utils::{
+        dynamic_uniform_layout_entry, ftexture_layout_entry, globals_layout_entry,
+        utexture_layout_entry, view_layout_entry, PerViewBindGroups,
+    },
+    prepass_downsample::PrepassDownsampleTextures,
+    shader_def_uint,
+    ssgi::{SSGIPass, SSGIPipelineKey},
+    ssgi_generate_sh::{SSGIGenerateSH, SSGIGenerateSHLabel, SSGISHTextures},
+    ssgi_resolve::SSGIResolveLabel,
+    wgsl_uniform, BlueNoise, BLUE_NOISE_DIMS, BLUE_NOISE_ENTRY_N,
+};
+
+const SH_DATA_FORMAT: TextureFormat = TextureFormat::Rgba32Uint;
+pub const SHADER_HANDLE: Handle<Shader> = Handle::weak_from_u128(73094857230948571);
+
+/// Add to a camera with SSGI to filter the SH probes with edge-aware à-trous wavelet iterations
+/// before they're resolved, using the downsampled depth & normals as edge-stopping functions.
+/// Each iteration doubles the spacing between the 5x5 taps, so a few of them cover a wide area.
+/// Smooths out the noise of low direction counts, at the cost of some detail in the lighting.
+/// Only the resolve sees the filtered probes, the SH history isn't filtered.
+#[derive(Component, ExtractComponent, Clone, Reflect)]
+#[reflect(Component)]
+#[cfg_attr(feature = "inspector", derive(InspectorOptions))]
+#[cfg_attr(feature = "inspector", reflect(InspectorOptions))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct SSGIDenoise {
+    /// Number of à-trous iterations. The filter covers `4 * (2^iterations - 1) + 1` probes across.
+    #[cfg_attr(feature = "inspector", inspector(min = 1, max = 5))]
+    pub iterations: u32,
+    /// How much differences in normals stop the filter
+    #[cfg_attr(feature = "inspector", inspector(min = 0.0))]
+    pub normal_rejection: f32,
+    /// How much the distance of a probe from the plane of the center probe stops the filter,
+    /// relative to the distance between them on screen
+    #[cfg_attr(feature = "inspector", inspector(min = 0.0))]
+    pub distance_rejection: f32,
+    /// How much relative differences in the luminance of the probes stop the filter, keeps the
+    /// edges of shadows and bright spots. 0.0 to only filter by geometry.
+    #[cfg_attr(feature = "inspector", inspector(min = 0.0))]
+    pub luminance_rejection: f32,
+}
+
+impl Default for SSGIDenoise {
+    fn default() -> Self {
+        SSGIDenoise {
+            iterations: 3,
+            normal_rejection: 64.0,
+            distance_rejection: 4.0,
+            luminance_rejection: 2.0,
+        }
+    }
+}
+
+wgsl_uniform! {
+    #[derive(Component, Clone, Copy, ShaderType, Debug, Default)]
+    pub struct SSGIDenoiseConfig {
+        render_scale: u32,
+        step_size: u32,
+        normal_rejection: f32,
+        distance_rejection: f32,
+        luminance_rejection: f32,
+        _webgl2_padding_1: f32,
+        _webgl2_padding_2: f32,
+        _webgl2_padding_3: f32,
+    }
+}
+
+pub struct SSGIDenoisePlugin;
+impl Plugin for SSGIDenoisePlugin {
+    fn build(&self, app: &mut App) {
+        load_internal_asset!(
+            app,
+            SHADER_HANDLE,
+            "../assets/shaders/ssgi_denoise.wgsl",
+            Shader::from_wgsl
+        );
+
+        app.register_type::<SSGIDenoise>()
+            .add_plugins(ExtractComponentPlugin::<SSGIDenoise>::default());
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            // The textures are only used once the pipeline is ready, so the resolve doesn't read
+            // them before they're written
+            .add_systems(
+                Render,
+                (prepare_pipelines, prepare_textures)
+                    .chain()
+                    .in_set(RenderSet::PrepareResources),
+            )
+            .add_systems(
+                Render,
+                (prepare_uniforms, prepare_bind_groups)
+                    .chain()
+                    .in_set(RenderSet::PrepareBindGroups),
+            )
+            .init_resource::<SpecializedRenderPipelines<SSGIDenoiseLayout>>()
+            .init_resource::<PerViewBindGroups<SSGIDenoiseConfig>>()
+            .add_render_graph_node::<SSGIDenoiseNode>(Core3d, SSGIDenoiseLabel)
+            .add_render_graph_edges(
+                Core3d,
+                (SSGIGenerateSHLabel, SSGIDenoiseLabel, SSGIResolveLabel),
+            );
+    }
+
+    fn finish(&self, app: &mut App) {
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+        render_app.init_resource::<SSGIDenoiseLayout>();
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
+pub struct SSGIDenoiseLabel;
+
+pub struct SSGIDenoiseNode {
+    query: QueryState<
+        (
+            &'static ViewUniformOffset,
+            &'static SSGIDenoiseTextures,
+            &'static SSGIDenoisePipeline,
+        ),
+        With<ExtractedView>,
+    >,
+}
+
+impl FromWorld for SSGIDenoiseNode {
+    fn from_world(world: &mut World) -> Self {
+        Self {
+            query: QueryState::new(world),
+        }
+    }
+}
+
+impl Node for SSGIDenoiseNode {
+    fn update(&mut self, world: &mut World) {
+        self.query.update_archetypes(world);
+    }
+
+    fn run(
+        &self,
+        graph_context: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let view_entity = graph_context.view_entity();
+
+        let Ok((view_uniform_offset, denoise_textures, pipeline)) =
+            self.query.get_manual(world, view_entity)
+        else {
+            return Ok(());
+        };
+
+        let pipeline_cache = world.resource::<PipelineCache>();
+
+        let Some(pipeline) = pipeline_cache.get_render_pipeline(pipeline.pipeline_id) else {
+            return Ok(());
+        };
+
+        let Some(view_bind_groups) = world
+            .resource::<PerViewBindGroups<SSGIDenoiseConfig>>()
+            .0
+            .get(&view_entity)
+        else {
+            return Ok(());
+        };
+
+        for iteration in 0..denoise_textures.iterations as usize {
+            let Some(bind_group) = view_bind_groups.bind_group(iteration) else {
+                return Ok(());
+            };
+
+            let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+                label: Some("ssgi_denoise_pass"),
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view: &denoise_textures.write(iteration).default_view,
+                    resolve_target: None,
+                    ops: Operations::default(),
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            render_pass.set_render_pipeline(pipeline);
+            render_pass.set_bind_group(
+                0,
+                bind_group,
+                &[
+                    view_uniform_offset.offset,
+                    view_bind_groups.offsets[iteration],
+                ],
+            );
+            render_pass.draw(0..3, 0..1);
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Resource)]
+pub struct SSGIDenoiseLayout {
+    pub layout: BindGroupLayout,
+    pub shader: Handle<Shader>,
+}
+
+#[derive(Component)]
+struct SSGIDenoisePipeline {
+    pipeline_id: CachedRenderPipelineId,
+}
+
+impl FromWorld for SSGIDenoiseLayout {
+    fn from_world(world: &mut World) -> Self {
+        let entries = vec![
+            view_layout_entry(0),
+            globals_layout_entry(9),
+            ftexture_layout_entry(102, TextureViewDimension::D2), // Prepass Downsample Normals
+            ftexture_layout_entry(103, TextureViewDimension::D2), // Prepass Downsample Depth
+            utexture_layout_entry(105, TextureViewDimension::D2), // SH
+            dynamic_uniform_layout_entry(109, SSGIDenoiseConfig::min_size()),
+            ftexture_layout_entry(BLUE_NOISE_ENTRY_N, TextureViewDimension::D2Array), // Blue Noise
+        ];
+
+        let layout = world
+            .resource::<RenderDevice>()
+            .create_bind_group_layout(Some("ssgi_denoise_bind_group_layout"), &entries);
+
+        #[cfg(not(all(feature = "file_watcher")))]
+        let shader = SHADER_HANDLE;
+        #[cfg(all(feature = "file_watcher"))]
+        let shader = {
+            let asset_server = world.resource_mut::<bevy::prelude::AssetServer>();
+            asset_server.load("shaders/ssgi_denoise.wgsl")
+        };
+
+        Self { layout, shader }
+    }
+}
+
+impl SpecializedRenderPipeline for SSGIDenoiseLayout {
+    type Key = SSGIPipelineKey;
+
+    fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
+        let mut shader_defs = Vec::new();
+
+        shader_defs.extend_from_slice(&[
+            ShaderDefVal::UInt("BLUE_NOISE_GROUP_N".to_string(), 0),
+            shader_def_uint!(BLUE_NOISE_ENTRY_N),
+            shader_def_uint!(BLUE_NOISE_DIMS),
+        ]);
+
+        // For the probe positions to match the ones the SH was generated at
+        key.shader_defs(&mut shader_defs);
+
+        RenderPipelineDescriptor {
+            label: Some("ssgi_denoise_pipeline".into()),
+            layout: vec![self.layout.clone()],
+            vertex: fullscreen_shader_vertex_state(),
+            fragment: Some(FragmentState {
+                shader: self.shader.clone(),
+                shader_defs,
+                entry_point: "fragment".into(),
+                targets: vec![Some(ColorTargetState {
+                    format: SH_DATA_FORMAT,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            push_constant_ranges: vec![],
+        }
+    }
+}
+
+/// The textures the à-trous iterations ping-pong between. The first iteration reads the SH that
+/// was just generated, the last one writes the SH the resolve reads.
+#[derive(Component)]
+pub struct SSGIDenoiseTextures {
+    pub textures: [CachedTexture; 2],
+    pub iterations: u32,
+}
+
+impl SSGIDenoiseTextures {
+    /// Texture written by an iteration
+    pub fn write(&self, iteration: usize) -> &CachedTexture {
+        &self.textures[iteration % 2]
+    }
+
+    /// Texture holding the filtered SH after the last iteration
+    pub fn output(&self) -> &CachedTexture {
+        self.write(self.iterations as usize - 1)
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn prepare_textures(
+    mut commands: Commands,
+    render_device: Res<RenderDevice>,
+    pipeline_cache: Res<PipelineCache>,
+    mut texture_cache: ResMut<TextureCache>,
+    views: Query<
+        (
+            Entity,
+            &ExtractedCamera,
+            &SSGIPass,
+            &SSGIDenoise,
+            &SSGIDenoisePipeline,
+        ),
+        With<SSGIGenerateSH>,
+    >,
+) {
+    for (entity, camera, ssgi_pass, ssgi_denoise, pipeline) in &views {
+        if pipeline_cache
+            .get_render_pipeline(pipeline.pipeline_id)
+            .is_none()
+        {
+            continue;
+        }
+        if let Some(physical_viewport_size) = camera.physical_viewport_size {
+            let texture_descriptor = TextureDescriptor {
+                label: Some("ssgi_sh_denoise"),
+                size: Extent3d {
+                    depth_or_array_layers: 1,
+                    width: physical_viewport_size.x / ssgi_pass.render_scale,
+                    height: physical_viewport_size.y / ssgi_pass.render_scale,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: SH_DATA_FORMAT,
+                usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            };
+
+            commands.entity(entity).insert(SSGIDenoiseTextures {
+                textures: [
+                    texture_cache.get(&render_device, texture_descriptor.clone()),
+                    texture_cache.get(&render_device, texture_descriptor),
+                ],
+                iterations: ssgi_denoise.iterations.max(1),
+            });
+        }
+    }
+}
+
+fn prepare_pipelines(
+    mut commands: Commands,
+    pipeline_cache: Res<PipelineCache>,
+    mut pipelines: ResMut<SpecializedRenderPipelines<SSGIDenoiseLayout>>,
+    layout: Res<SSGIDenoiseLayout>,
+    views: Query<(Entity, &SSGIPass), With<SSGIDenoise>>,
+) {
+    for (entity, ssgi_pass) in &views {
+        // Only the probe placement defs are read by the shader, the others would just specialize
+        // the same pipeline again
+        let key = SSGIPipelineKey {
+            debug_occlusion: false,
+            depth_min_max: false,
+            back_face_depth: false,
+            ..ssgi_pass.key()
+        };
+        let pipeline_id = pipelines.specialize(&pipeline_cache, &layout, key);
+        commands
+            .entity(entity)
+            .insert(SSGIDenoisePipeline { pipeline_id });
+    }
+}
+
+fn prepare_uniforms(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut bind_groups: ResMut<PerViewBindGroups<SSGIDenoiseConfig>>,
+    views: Query<(Entity, &SSGIPass, &SSGIDenoise, &SSGIDenoiseTextures)>,
+) {
+    bind_groups.0.retain(|entity, _| views.contains(*entity));
+
+    for (entity, ssgi_pass, ssgi_denoise, denoise_textures) in &views {
+        let configs = (0..denoise_textures.iterations).map(|iteration| SSGIDenoiseConfig {
+            render_scale: ssgi_pass.render_scale,
+            step_size: 1 << iteration,
+            normal_rejection: ssgi_denoise.normal_rejection,
+            distance_rejection: ssgi_denoise.distance_rejection,
+            luminance_rejection: ssgi_denoise.luminance_rejection,
+            ..default()
+        });
+        bind_groups.0.entry(entity).or_default().write_uniforms(
+            &render_device,
+            &render_queue,
+            configs,
+        );
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn prepare_bind_groups(
+    render_device: Res<RenderDevice>,
+    layout: Res<SSGIDenoiseLayout>,
+    view_uniforms: Res<ViewUniforms>,
+    globals_buffer: Res<GlobalsBuffer>,
+    images: Res<RenderAssets<Image>>,
+    blue_noise: Option<Res<BlueNoise>>,
+    mut bind_groups: ResMut<PerViewBindGroups<SSGIDenoiseConfig>>,
+    views: Query<(
+        Entity,
+        &SSGISHTextures,
+        &SSGIDenoiseTextures,
+        &PrepassDownsampleTextures,
+    )>,
+) {
+    let (Some(view_binding), Some(globals_binding)) = (
+        view_uniforms.uniforms.binding(),
+        globals_buffer.buffer.binding(),
+    ) else {
+        return;
+    };
+    let Some(blue_noise_tex) = blue_noise.and_then(|blue_noise| images.get(&blue_noise.0)) else {
+        return;
+    };
+
+    for (entity, sh_texture, denoise_textures, prepass_downsample_texture) in &views {
+        let Some(view_bind_groups) = bind_groups.0.get_mut(&entity) else {
+            continue;
+        };
+        let Some(config_binding) = view_bind_groups.uniforms.binding() else {
+            continue;
+        };
+
+        let iterations = denoise_textures.iterations as usize;
+        view_bind_groups
+            .bind_groups
+            .resize_with(iterations, Default::default);
+        for iteration in 0..iterations {
+            let input = match iteration {
+                // Use write since it's the one ssgi_generate_sh would have just written to
+                0 => &sh_texture.write,
+                _ => denoise_textures.write(iteration - 1),
+            };
+            let entries = BindGroupEntries::with_indices((
+                (0, view_binding.clone()),
+                (9, globals_binding.clone()),
+                (102, &prepass_downsample_texture.normals.default_view),
+                (103, &prepass_downsample_texture.depth.default_view),
+                (105, &input.default_view),
+                (109, config_binding.clone()),
+                (BLUE_NOISE_ENTRY_N, &blue_noise_tex.texture_view),
+            ));
+            view_bind_groups.bind_groups[iteration].update(
+                &render_device,
+                "ssgi_denoise_bind_group",
+                &layout.layout,
+                &entries,
+            );
+        }
+    }
+}
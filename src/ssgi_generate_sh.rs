@@ -1,6 +1,5 @@
 use bevy::{
     asset::load_internal_asset,
-    core::FrameCount,
     core_pipeline::{
         core_3d::graph::{Core3d, Node3d},
         fullscreen_vertex_shader::fullscreen_shader_vertex_state,
@@ -9,6 +8,7 @@ use bevy::{
     render::{
         camera::ExtractedCamera,
         extract_component::{ExtractComponent, ExtractComponentPlugin},
+        globals::GlobalsBuffer,
         render_asset::RenderAssets,
         render_graph::{Node, NodeRunError, RenderGraphApp, RenderGraphContext, RenderLabel},
         render_resource::{
@@ -19,56 +19,83 @@ use bevy::{
             SpecializedRenderPipelines, TextureDescriptor, TextureDimension, TextureFormat,
             TextureUsages, TextureViewDimension,
         },
-        renderer::{RenderContext, RenderDevice},
-        texture::{CachedTexture, TextureCache},
-        view::{ExtractedView, ViewUniformOffset},
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+        texture::CachedTexture,
+        view::{ExtractedView, ViewUniformOffset, ViewUniforms},
         Render, RenderApp, RenderSet,
     },
 };
 
+#[cfg(all(
+    feature = "disocclusion",
+    not(all(feature = "webgl", target_arch = "wasm32"))
+))]
+use bevy::render::render_resource::{BindGroupEntry, BindingResource};
+#[cfg(feature = "inspector")]
 use bevy_inspector_egui::{inspector_options::ReflectInspectorOptions, InspectorOptions};
+#[cfg(all(
+    feature = "disocclusion",
+    not(all(feature = "webgl", target_arch = "wasm32"))
+))]
+use bevy_mod_taa::disocclusion::DisocclusionTextures;
 
 use crate::{
     bind_group_utils::{
-        ftexture_layout_entry, globals_binding, globals_layout_entry, uniform_buffer,
-        uniform_layout_entry, utexture_layout_entry, view_binding, view_layout_entry,
+        dynamic_uniform_layout_entry, ftexture_layout_entry, globals_layout_entry,
+        utexture_layout_entry, view_layout_entry, PerViewBindGroups,
     },
-    image,
     prepass_downsample::PrepassDownsampleTextures,
-    resource, shader_def_uint,
+    shader_def_uint,
     ssgi::{SSGILabel, SSGIPass, SSGIPipelineKey, SSGITextures},
-    BlueNoise, BLUE_NOISE_DIMS, BLUE_NOISE_ENTRY_N,
+    ssgi_resolve::{SSGIHistoryClamp, SSGIResolve},
+    view_history::ViewHistories,
+    wgsl_uniform, BlueNoise, BLUE_NOISE_DIMS, BLUE_NOISE_ENTRY_N,
 };
 
 const SH_DATA_FORMAT: TextureFormat = TextureFormat::Rgba32Uint;
 const SH_HISTORY_POS_FORMAT: TextureFormat = TextureFormat::Rgba32Float;
+const SH_HISTORY_LENGTH_FORMAT: TextureFormat = TextureFormat::R16Float;
 pub const SHADER_HANDLE: Handle<Shader> = Handle::weak_from_u128(40958237405983745);
 
-#[derive(Component, ExtractComponent, Clone, Reflect, InspectorOptions)]
-#[reflect(Component, InspectorOptions)]
+#[derive(Component, ExtractComponent, Clone, Reflect)]
+#[reflect(Component)]
+#[cfg_attr(feature = "inspector", derive(InspectorOptions))]
+#[cfg_attr(feature = "inspector", reflect(InspectorOptions))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+// Like SSGIResolve, rejects settings files with the removed `hysteresis`
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
 pub struct SSGIGenerateSH {
-    /// How much to blend in previous reprojected probes. 1.0 for only using the current frame,
-    /// lower numbers uses more of the previous accumulation
-    #[inspector(min = 0.05, max = 1.0)]
-    hysteresis: f32,
+    /// Frames a probe counts as accumulated when its history was just rejected, e.g. when it's
+    /// disoccluded. 1.0 only uses the current frame, higher numbers converge slower but are less noisy.
+    #[cfg_attr(feature = "inspector", inspector(min = 1.0, max = 64.0))]
+    pub min_history_length: f32,
+    /// Frames a probe with stable history accumulates at most. The current frame is blended in
+    /// with a weight of 1 / history length, so this is the lowest weight it gets.
+    #[cfg_attr(feature = "inspector", inspector(min = 1.0, max = 64.0))]
+    pub max_history_length: f32,
 }
 
 impl Default for SSGIGenerateSH {
     fn default() -> Self {
-        SSGIGenerateSH { hysteresis: 0.2 }
+        SSGIGenerateSH {
+            min_history_length: 1.0,
+            max_history_length: 5.0,
+        }
     }
 }
 
-#[derive(Component, Clone, Copy, ShaderType, Debug, Default)]
-struct SSGIGenerateSHConfig {
-    cas_w: u32,
-    cas_h: u32,
-    directions: u32,
-    render_scale: u32,
-    cascade_count: u32,
-    hysteresis: f32,
-    _webgl2_padding_1: f32,
-    _webgl2_padding_2: f32,
+wgsl_uniform! {
+    #[derive(Component, Clone, Copy, ShaderType, Debug, Default)]
+    pub struct SSGIGenerateSHConfig {
+        cas_w: u32,
+        cas_h: u32,
+        directions: u32,
+        render_scale: u32,
+        cascade_count: u32,
+        min_history_length: f32,
+        max_history_length: f32,
+        _webgl2_padding_1: f32,
+    }
 }
 
 pub struct SSGIGenerateSHPlugin;
@@ -89,7 +116,14 @@ impl Plugin for SSGIGenerateSHPlugin {
 
         render_app
             .add_systems(Render, prepare_textures.in_set(RenderSet::PrepareResources))
+            .add_systems(
+                Render,
+                (prepare_uniforms, prepare_bind_groups)
+                    .chain()
+                    .in_set(RenderSet::PrepareBindGroups),
+            )
             .init_resource::<SpecializedRenderPipelines<SSGIGenerateSHLayout>>()
+            .init_resource::<PerViewBindGroups<SSGIGenerateSHConfig>>()
             .add_systems(Render, (prepare_pipelines.in_set(RenderSet::Prepare),))
             .add_render_graph_node::<SSGIGenerateSHNode>(Core3d, SSGIGenerateSHLabel)
             .add_render_graph_edges(
@@ -114,12 +148,8 @@ pub struct SSGIGenerateSHNode {
     query: QueryState<
         (
             &'static ViewUniformOffset,
-            &'static SSGITextures,
             &'static SSGISHTextures,
             &'static SSGIGenerateSHPipeline,
-            &'static PrepassDownsampleTextures,
-            &'static SSGIPass,
-            &'static SSGIGenerateSH,
         ),
         With<ExtractedView>,
     >,
@@ -150,56 +180,28 @@ impl Node for SSGIGenerateSHNode {
     ) -> Result<(), NodeRunError> {
         let view_entity = graph_context.view_entity();
 
-        let Ok((
-            view_uniform_offset,
-            ssgi_textures,
-            sh_texture,
-            pipeline,
-            prepass_downsample_texture,
-            ssgi_pass,
-            ssgi_generate_sh,
-        )) = self.query.get_manual(world, view_entity)
+        let Ok((view_uniform_offset, sh_texture, pipeline)) =
+            self.query.get_manual(world, view_entity)
         else {
             return Ok(());
         };
 
-        let ssgi_sh_pipeline = world.resource::<SSGIGenerateSHLayout>();
         let pipeline_cache = world.resource::<PipelineCache>();
 
         let Some(pipeline) = pipeline_cache.get_render_pipeline(pipeline.pipeline_id) else {
             return Ok(());
         };
 
-        let images = world.resource::<RenderAssets<Image>>();
-        let blue_noise_tex = image!(images, &resource!(world, BlueNoise).0);
-
-        let config = SSGIGenerateSHConfig {
-            cas_w: ssgi_textures.data_textures1[0].texture.width(),
-            cas_h: ssgi_textures.data_textures1[0].texture.height(),
-            directions: ssgi_pass.cascade_0_directions,
-            render_scale: ssgi_pass.render_scale,
-            cascade_count: ssgi_pass.cascade_count,
-            hysteresis: ssgi_generate_sh.hysteresis,
-            ..default()
+        let Some(view_bind_groups) = world
+            .resource::<PerViewBindGroups<SSGIGenerateSHConfig>>()
+            .0
+            .get(&view_entity)
+        else {
+            return Ok(());
+        };
+        let Some(bind_group) = view_bind_groups.bind_group(0) else {
+            return Ok(());
         };
-        let uniform = uniform_buffer(config, render_context, "SSGI Generate SH Config Uniform");
-        let bind_group = render_context.render_device().create_bind_group(
-            "ssgi_generate_sh_bind_group",
-            &ssgi_sh_pipeline.layout,
-            &BindGroupEntries::with_indices((
-                (0, view_binding(world)),
-                (9, globals_binding(world)),
-                (101, &ssgi_textures.data_textures1[0].default_view),
-                (111, &ssgi_textures.data_textures2[0].default_view),
-                (102, &prepass_downsample_texture.normals.default_view),
-                (103, &prepass_downsample_texture.depth.default_view),
-                (104, &prepass_downsample_texture.motion.default_view),
-                (105, &sh_texture.read.default_view),
-                (106, &sh_texture.pos_read.default_view),
-                (109, uniform.as_entire_binding()),
-                (BLUE_NOISE_ENTRY_N, &blue_noise_tex.texture_view),
-            )),
-        );
 
         let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
             label: Some("ssgi_generate_sh_pass"),
@@ -214,13 +216,22 @@ impl Node for SSGIGenerateSHNode {
                     resolve_target: None,
                     ops: Operations::default(),
                 }),
+                Some(RenderPassColorAttachment {
+                    view: &sh_texture.length_write.default_view,
+                    resolve_target: None,
+                    ops: Operations::default(),
+                }),
             ],
             depth_stencil_attachment: None,
             timestamp_writes: None,
             occlusion_query_set: None,
         });
         render_pass.set_render_pipeline(pipeline);
-        render_pass.set_bind_group(0, &bind_group, &[view_uniform_offset.offset]);
+        render_pass.set_bind_group(
+            0,
+            bind_group,
+            &[view_uniform_offset.offset, view_bind_groups.offsets[0]],
+        );
         render_pass.draw(0..3, 0..1);
 
         Ok(())
@@ -240,7 +251,8 @@ struct SSGIGenerateSHPipeline {
 
 impl FromWorld for SSGIGenerateSHLayout {
     fn from_world(world: &mut World) -> Self {
-        let entries = vec![
+        #[allow(unused_mut)]
+        let mut entries = vec![
             view_layout_entry(0),
             globals_layout_entry(9),
             utexture_layout_entry(101, TextureViewDimension::D2),
@@ -250,10 +262,17 @@ impl FromWorld for SSGIGenerateSHLayout {
             ftexture_layout_entry(104, TextureViewDimension::D2), // Prepass Downsample Motion
             utexture_layout_entry(105, TextureViewDimension::D2), // Prev SH
             ftexture_layout_entry(106, TextureViewDimension::D2), // Pos Read
-            uniform_layout_entry(109, SSGIGenerateSHConfig::min_size()),
+            ftexture_layout_entry(110, TextureViewDimension::D2), // History Length Read
+            dynamic_uniform_layout_entry(109, SSGIGenerateSHConfig::min_size()),
             ftexture_layout_entry(BLUE_NOISE_ENTRY_N, TextureViewDimension::D2Array), // Blue Noise
         ];
 
+        #[cfg(all(
+            feature = "disocclusion",
+            not(all(feature = "webgl", target_arch = "wasm32"))
+        ))]
+        entries.push(ftexture_layout_entry(107, TextureViewDimension::D2)); // Disocclusion
+
         let layout = world
             .resource::<RenderDevice>()
             .create_bind_group_layout(Some("ssgi_generate_sh_bind_group_layout"), &entries);
@@ -270,8 +289,14 @@ impl FromWorld for SSGIGenerateSHLayout {
     }
 }
 
+#[derive(PartialEq, Eq, Hash, Clone)]
+pub struct SSGIGenerateSHPipelineKey {
+    pub ssgi_key: SSGIPipelineKey,
+    pub history_clamp: SSGIHistoryClamp,
+}
+
 impl SpecializedRenderPipeline for SSGIGenerateSHLayout {
-    type Key = SSGIPipelineKey;
+    type Key = SSGIGenerateSHPipelineKey;
 
     fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
         let mut shader_defs = Vec::new();
@@ -282,7 +307,8 @@ impl SpecializedRenderPipeline for SSGIGenerateSHLayout {
             shader_def_uint!(BLUE_NOISE_DIMS),
         ]);
 
-        key.shader_defs(&mut shader_defs);
+        key.ssgi_key.shader_defs(&mut shader_defs);
+        shader_defs.push(key.history_clamp.shader_def());
 
         RenderPipelineDescriptor {
             label: Some("ssgi_generate_sh_pipeline".into()),
@@ -303,6 +329,11 @@ impl SpecializedRenderPipeline for SSGIGenerateSHLayout {
                         blend: None,
                         write_mask: ColorWrites::ALL,
                     }),
+                    Some(ColorTargetState {
+                        format: SH_HISTORY_LENGTH_FORMAT,
+                        blend: None,
+                        write_mask: ColorWrites::ALL,
+                    }),
                 ],
             }),
             primitive: PrimitiveState::default(),
@@ -319,19 +350,21 @@ pub struct SSGISHTextures {
     pub write: CachedTexture,
     pub pos_read: CachedTexture,
     pub pos_write: CachedTexture,
+    /// Frames accumulated in each probe, for its blend weight next frame
+    pub length_read: CachedTexture,
+    pub length_write: CachedTexture,
 }
 
 fn prepare_textures(
     mut commands: Commands,
-    mut texture_cache: ResMut<TextureCache>,
     render_device: Res<RenderDevice>,
     views: Query<(Entity, &ExtractedCamera, &ExtractedView, &SSGIPass), With<SSGIGenerateSH>>,
-    frame_count: Res<FrameCount>,
+    mut view_histories: ResMut<ViewHistories>,
 ) {
     for (entity, camera, _view, ssgi_pass) in &views {
         if let Some(physical_viewport_size) = camera.physical_viewport_size {
-            let mut texture_descriptor = TextureDescriptor {
-                label: None,
+            let texture_descriptor = TextureDescriptor {
+                label: Some("ssgi_sh"),
                 size: Extent3d {
                     depth_or_array_layers: 1,
                     width: physical_viewport_size.x / ssgi_pass.render_scale,
@@ -346,8 +379,8 @@ fn prepare_textures(
                     | TextureUsages::COPY_DST,
                 view_formats: &[],
             };
-            let mut sh_history_pos_texture_descriptor = TextureDescriptor {
-                label: None,
+            let sh_history_pos_texture_descriptor = TextureDescriptor {
+                label: Some("ssgi_sh_history_pos"),
                 size: Extent3d {
                     depth_or_array_layers: 1,
                     width: physical_viewport_size.x / ssgi_pass.render_scale,
@@ -362,33 +395,41 @@ fn prepare_textures(
                     | TextureUsages::COPY_DST,
                 view_formats: &[],
             };
+            let sh_history_length_texture_descriptor = TextureDescriptor {
+                label: Some("ssgi_sh_history_length"),
+                size: Extent3d {
+                    depth_or_array_layers: 1,
+                    width: physical_viewport_size.x / ssgi_pass.render_scale,
+                    height: physical_viewport_size.y / ssgi_pass.render_scale,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: SH_HISTORY_LENGTH_FORMAT,
+                usage: TextureUsages::RENDER_ATTACHMENT
+                    | TextureUsages::TEXTURE_BINDING
+                    | TextureUsages::COPY_DST,
+                view_formats: &[],
+            };
 
-            texture_descriptor.label = Some("ssgi_sh_a");
-            let ssgi_sh_texture_a = texture_cache.get(&render_device, texture_descriptor.clone());
-            texture_descriptor.label = Some("ssgi_sh_b");
-            let ssgi_sh_texture_b = texture_cache.get(&render_device, texture_descriptor.clone());
-
-            sh_history_pos_texture_descriptor.label = Some("ssgi_sh_history_pos_a");
-            let ssgi_sh_history_pos_texture_a =
-                texture_cache.get(&render_device, sh_history_pos_texture_descriptor.clone());
-            sh_history_pos_texture_descriptor.label = Some("ssgi_sh_history_pos_b");
-            let ssgi_sh_history_pos_texture_b =
-                texture_cache.get(&render_device, sh_history_pos_texture_descriptor.clone());
-
-            let textures = if frame_count.0 % 2 == 0 {
-                SSGISHTextures {
-                    write: ssgi_sh_texture_a,
-                    read: ssgi_sh_texture_b,
-                    pos_write: ssgi_sh_history_pos_texture_a,
-                    pos_read: ssgi_sh_history_pos_texture_b,
-                }
-            } else {
-                SSGISHTextures {
-                    write: ssgi_sh_texture_b,
-                    read: ssgi_sh_texture_a,
-                    pos_write: ssgi_sh_history_pos_texture_b,
-                    pos_read: ssgi_sh_history_pos_texture_a,
-                }
+            let sh_textures = view_histories.ping_pong(&render_device, entity, &texture_descriptor);
+            let pos_textures = view_histories.ping_pong(
+                &render_device,
+                entity,
+                &sh_history_pos_texture_descriptor,
+            );
+            let length_textures = view_histories.ping_pong(
+                &render_device,
+                entity,
+                &sh_history_length_texture_descriptor,
+            );
+            let textures = SSGISHTextures {
+                write: sh_textures.write,
+                read: sh_textures.read,
+                pos_write: pos_textures.write,
+                pos_read: pos_textures.read,
+                length_write: length_textures.write,
+                length_read: length_textures.read,
             };
             commands.entity(entity).insert(textures);
         }
@@ -400,13 +441,143 @@ pub fn prepare_pipelines(
     pipeline_cache: Res<PipelineCache>,
     mut pipelines: ResMut<SpecializedRenderPipelines<SSGIGenerateSHLayout>>,
     layout: Res<SSGIGenerateSHLayout>,
-    views: Query<(Entity, &SSGIPass)>,
+    views: Query<(Entity, &SSGIPass, Option<&SSGIResolve>)>,
 ) {
-    for (entity, ssgi_pass) in &views {
+    for (entity, ssgi_pass, ssgi_resolve) in &views {
+        let key = SSGIGenerateSHPipelineKey {
+            ssgi_key: ssgi_pass.key(),
+            // Set on the resolve, so both histories are clamped the same way
+            history_clamp: ssgi_resolve
+                .map(|resolve| resolve.history_clamp)
+                .unwrap_or_default(),
+        };
         let pipeline_id: CachedRenderPipelineId =
-            pipelines.specialize(&pipeline_cache, &layout, ssgi_pass.key());
+            pipelines.specialize(&pipeline_cache, &layout, key);
         commands
             .entity(entity)
             .insert(SSGIGenerateSHPipeline { pipeline_id });
     }
 }
+
+fn prepare_uniforms(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut bind_groups: ResMut<PerViewBindGroups<SSGIGenerateSHConfig>>,
+    view_histories: Res<ViewHistories>,
+    views: Query<(Entity, &SSGITextures, &SSGIPass, &SSGIGenerateSH)>,
+) {
+    bind_groups.0.retain(|entity, _| views.contains(*entity));
+
+    for (entity, ssgi_textures, ssgi_pass, ssgi_generate_sh) in &views {
+        // Without a valid history, only the current frame is used
+        let (min_history_length, max_history_length) = if view_histories
+            .get(entity)
+            .is_some_and(|history| history.valid)
+        {
+            let min_history_length = ssgi_generate_sh.min_history_length.max(1.0);
+            (
+                min_history_length,
+                ssgi_generate_sh.max_history_length.max(min_history_length),
+            )
+        } else {
+            (1.0, 1.0)
+        };
+        let config = SSGIGenerateSHConfig {
+            cas_w: ssgi_textures.data_textures1[0].texture.width(),
+            cas_h: ssgi_textures.data_textures1[0].texture.height(),
+            directions: ssgi_pass.cascade_0_directions,
+            render_scale: ssgi_pass.render_scale,
+            cascade_count: ssgi_pass.cascade_count,
+            min_history_length,
+            max_history_length,
+            ..default()
+        };
+        bind_groups.0.entry(entity).or_default().write_uniforms(
+            &render_device,
+            &render_queue,
+            [config],
+        );
+    }
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn prepare_bind_groups(
+    render_device: Res<RenderDevice>,
+    layout: Res<SSGIGenerateSHLayout>,
+    view_uniforms: Res<ViewUniforms>,
+    globals_buffer: Res<GlobalsBuffer>,
+    images: Res<RenderAssets<Image>>,
+    blue_noise: Option<Res<BlueNoise>>,
+    mut bind_groups: ResMut<PerViewBindGroups<SSGIGenerateSHConfig>>,
+    views: Query<(
+        Entity,
+        &SSGITextures,
+        &SSGISHTextures,
+        &PrepassDownsampleTextures,
+    )>,
+    #[cfg(all(
+        feature = "disocclusion",
+        not(all(feature = "webgl", target_arch = "wasm32"))
+    ))]
+    disocclusion_textures: Query<&DisocclusionTextures>,
+) {
+    let (Some(view_binding), Some(globals_binding)) = (
+        view_uniforms.uniforms.binding(),
+        globals_buffer.buffer.binding(),
+    ) else {
+        return;
+    };
+    let Some(blue_noise_tex) = blue_noise.and_then(|blue_noise| images.get(&blue_noise.0)) else {
+        return;
+    };
+
+    for (entity, ssgi_textures, sh_texture, prepass_downsample_texture) in &views {
+        let Some(view_bind_groups) = bind_groups.0.get_mut(&entity) else {
+            continue;
+        };
+        let Some(config_binding) = view_bind_groups.uniforms.binding() else {
+            continue;
+        };
+
+        #[allow(unused_mut)]
+        let mut entries = BindGroupEntries::with_indices((
+            (0, view_binding.clone()),
+            (9, globals_binding.clone()),
+            (101, &ssgi_textures.data_textures1[0].default_view),
+            (111, &ssgi_textures.data_textures2[0].default_view),
+            (102, &prepass_downsample_texture.normals.default_view),
+            (103, &prepass_downsample_texture.depth.default_view),
+            (104, &prepass_downsample_texture.motion.default_view),
+            (105, &sh_texture.read.default_view),
+            (106, &sh_texture.pos_read.default_view),
+            (110, &sh_texture.length_read.default_view),
+            (109, config_binding),
+            (BLUE_NOISE_ENTRY_N, &blue_noise_tex.texture_view),
+        ))
+        .to_vec();
+
+        #[cfg(all(
+            feature = "disocclusion",
+            not(all(feature = "webgl", target_arch = "wasm32"))
+        ))]
+        {
+            let Ok(disocclusion_textures) = disocclusion_textures.get(entity) else {
+                continue;
+            };
+            entries.push(BindGroupEntry {
+                binding: 107,
+                resource: BindingResource::TextureView(&disocclusion_textures.output.default_view),
+            });
+        }
+
+        view_bind_groups
+            .bind_groups
+            .resize_with(1, Default::default);
+        view_bind_groups.bind_groups[0].update(
+            &render_device,
+            "ssgi_generate_sh_bind_group",
+            &layout.layout,
+            &entries,
+        );
+    }
+}
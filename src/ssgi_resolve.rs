@@ -1,14 +1,15 @@
 use bevy::{
     asset::load_internal_asset,
-    core::FrameCount,
     core_pipeline::{
         core_3d::graph::{Core3d, Node3d},
         fullscreen_vertex_shader::fullscreen_shader_vertex_state,
+        prepass::{DeferredPrepass, ViewPrepassTextures},
     },
     prelude::*,
     render::{
         camera::ExtractedCamera,
         extract_component::{ExtractComponent, ExtractComponentPlugin},
+        globals::GlobalsBuffer,
         render_asset::RenderAssets,
         render_graph::{Node, NodeRunError, RenderGraphApp, RenderGraphContext, RenderLabel},
         render_resource::{
@@ -19,46 +20,87 @@ use bevy::{
             SpecializedRenderPipelines, TextureDescriptor, TextureDimension, TextureFormat,
             TextureUsages, TextureViewDimension,
         },
-        renderer::{RenderContext, RenderDevice},
-        texture::{CachedTexture, TextureCache},
-        view::{ExtractedView, ViewUniformOffset},
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+        texture::CachedTexture,
+        view::{ExtractedView, ViewUniformOffset, ViewUniforms},
         Render, RenderApp, RenderSet,
     },
 };
 
+#[cfg(all(
+    feature = "disocclusion",
+    not(all(feature = "webgl", target_arch = "wasm32"))
+))]
+use bevy::render::render_resource::{BindGroupEntry, BindingResource};
+#[cfg(feature = "inspector")]
 use bevy_inspector_egui::{inspector_options::ReflectInspectorOptions, InspectorOptions};
+#[cfg(all(
+    feature = "disocclusion",
+    not(all(feature = "webgl", target_arch = "wasm32"))
+))]
+use bevy_mod_taa::disocclusion::DisocclusionTextures;
 
 use crate::{
     bind_group_utils::{
-        fsampler_layout_entry, ftexture_layout_entry, globals_binding, globals_layout_entry,
-        linear_sampler, uniform_buffer, uniform_layout_entry, utexture_layout_entry, view_binding,
-        view_layout_entry,
+        dynamic_uniform_layout_entry, fsampler_layout_entry, ftexture_layout_entry,
+        globals_layout_entry, utexture_layout_entry, view_layout_entry, PerViewBindGroups,
+        SSGISamplers,
     },
-    image,
     prepass_downsample::PrepassDownsampleTextures,
-    resource, shader_def_uint,
+    shader_def_uint,
     ssgi::{SSGIPass, SSGIPipelineKey, SSGITextures},
+    ssgi_denoise::SSGIDenoiseTextures,
     ssgi_generate_sh::{SSGIGenerateSHLabel, SSGISHTextures},
-    BlueNoise, BLUE_NOISE_DIMS, BLUE_NOISE_ENTRY_N,
+    view_history::ViewHistories,
+    wgsl_uniform, BlueNoise, BLUE_NOISE_DIMS, BLUE_NOISE_ENTRY_N,
 };
 
 const SH_RESOLVE_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
 
 pub const SHADER_HANDLE: Handle<Shader> = Handle::weak_from_u128(523704598327409748);
 
-#[derive(Component, ExtractComponent, Clone, Reflect, InspectorOptions)]
-#[reflect(Component, InspectorOptions)]
+#[derive(Component, ExtractComponent, Clone, Reflect)]
+#[reflect(Component)]
+#[cfg_attr(feature = "inspector", derive(InspectorOptions))]
+#[cfg_attr(feature = "inspector", reflect(InspectorOptions))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+// Unknown fields are errors so settings files with the removed `hysteresis` fail to load instead of
+// silently losing their tuning
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
 pub struct SSGIResolve {
     /// How much differences in position affect interpolation between probes when resolving to full resolution
-    #[inspector(min = 0.0)]
+    #[cfg_attr(feature = "inspector", inspector(min = 0.0))]
     pub distance_rejection: f32,
     /// How much differences in normals affect interpolation between probes when resolving to full resolution
-    #[inspector(min = 0.0)]
+    #[cfg_attr(feature = "inspector", inspector(min = 0.0))]
     pub normal_rejection: f32,
-    /// How much to blend in previous reprojected resolved frame. 1.0 for only using the current frame,
-    /// lower numbers uses more of the previous accumulation
-    #[inspector(min = 0.05, max = 1.0)]
-    hysteresis: f32,
+    /// Frames a pixel counts as accumulated when its history was just rejected, e.g. when it's
+    /// disoccluded. 1.0 only uses the current frame, higher numbers converge slower but are less noisy.
+    #[cfg_attr(feature = "inspector", inspector(min = 1.0, max = 64.0))]
+    pub min_history_length: f32,
+    /// Frames a pixel with stable history accumulates at most. The current frame is blended in
+    /// with a weight of 1 / history length, so this is the lowest weight it gets.
+    #[cfg_attr(feature = "inspector", inspector(min = 1.0, max = 64.0))]
+    pub max_history_length: f32,
+    /// Strength of the indirect specular. 0.0 to disable. Only used with deferred rendering, forward
+    /// cameras skip it.
+    /// Replaces the [`EnvironmentMapLight`] specular in this crate's deferred lighting pass, enable
+    /// [`crate::ssgi::SSGIPass::environment_fallback`] for off-screen reflections to still see the
+    /// environment map. With Bevy's default deferred lighting both are added, lower one of them
+    /// to compensate.
+    #[cfg_attr(feature = "inspector", inspector(min = 0.0, max = 10.0))]
+    pub specular: f32,
+    /// How many GGX samples of the SH are taken per pixel for the indirect specular
+    #[cfg_attr(feature = "inspector", inspector(min = 1, max = 16))]
+    pub specular_samples: u32,
+    /// How the history is limited to what's in the current frame before blending, in both the
+    /// resolve and the SH accumulation. Without it, light that's gone fades out over many frames.
+    pub history_clamp: SSGIHistoryClamp,
+    /// Probes interpolated for each pixel. Larger kernels are smoother and less likely to reject
+    /// all their probes at geometry edges, but cost more.
+    pub upsample_kernel: SSGIUpsampleKernel,
+    /// What pixels get when none of the probes around them match their surface
+    pub upsample_fallback: SSGIUpsampleFallback,
 }
 
 impl Default for SSGIResolve {
@@ -66,25 +108,107 @@ impl Default for SSGIResolve {
         SSGIResolve {
             distance_rejection: 2.0,
             normal_rejection: 100.0,
-            hysteresis: 0.1,
+            min_history_length: 1.0,
+            max_history_length: 10.0,
+            specular: 1.0,
+            specular_samples: 2,
+            history_clamp: SSGIHistoryClamp::default(),
+            upsample_kernel: SSGIUpsampleKernel::default(),
+            upsample_fallback: SSGIUpsampleFallback::default(),
         }
     }
 }
 
-#[derive(Component, Clone, Copy, ShaderType, Debug, Default)]
-struct SSGIResolveConfig {
-    cas_w: u32,
-    cas_h: u32,
-    directions: u32,
-    render_scale: u32,
-    cascade_count: u32,
-    distance_rejection: f32,
-    normal_rejection: f32,
-    hysteresis: f32,
-    rough_specular: f32,
-    rough_specular_sharpness: f32,
-    _webgl2_padding_1: f32,
-    _webgl2_padding_2: f32,
+/// How the history of [`SSGIResolve`] and [`crate::ssgi_generate_sh::SSGIGenerateSH`] is limited
+/// to the neighborhood of the current frame, in YCoCg. The resolve uses the 3x3 probes around each
+/// pixel, the SH accumulation the directions gathered by each probe.
+#[derive(Reflect, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SSGIHistoryClamp {
+    /// The history is used as is
+    #[default]
+    None,
+    /// Clamped to the min/max of the neighborhood. Stable, but lets bright outliers through.
+    MinMax,
+    /// Clipped towards the mean of the neighborhood, within its standard deviation. Rejects
+    /// more of the stale history than [`SSGIHistoryClamp::MinMax`], but can flicker with few
+    /// directions.
+    Variance,
+}
+
+impl SSGIHistoryClamp {
+    pub(crate) fn shader_def(&self) -> ShaderDefVal {
+        match self {
+            SSGIHistoryClamp::None => "HISTORY_CLAMP_NONE".into(),
+            SSGIHistoryClamp::MinMax => "HISTORY_CLAMP_MIN_MAX".into(),
+            SSGIHistoryClamp::Variance => "HISTORY_CLAMP_VARIANCE".into(),
+        }
+    }
+}
+
+/// The probes [`SSGIResolve`] interpolates for each pixel, weighted by their distance on screen
+/// and how well they match the surface of the pixel. If all of them are rejected, the closest
+/// matching probe within 2 probes of the pixel is used instead.
+#[derive(Reflect, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SSGIUpsampleKernel {
+    /// The 2x2 probes around the pixel, bilinearly weighted
+    #[default]
+    Bilinear2x2,
+    /// The 3x3 probes around the pixel's closest probe, with a tent 1.5 probes wide
+    Tent3x3,
+    /// The 4x4 probes around the pixel, with a tent 2 probes wide
+    Tent4x4,
+}
+
+impl SSGIUpsampleKernel {
+    pub(crate) fn shader_def(&self) -> ShaderDefVal {
+        match self {
+            SSGIUpsampleKernel::Bilinear2x2 => "UPSAMPLE_KERNEL_2X2".into(),
+            SSGIUpsampleKernel::Tent3x3 => "UPSAMPLE_KERNEL_3X3".into(),
+            SSGIUpsampleKernel::Tent4x4 => "UPSAMPLE_KERNEL_4X4".into(),
+        }
+    }
+}
+
+/// What [`SSGIResolve`] uses for a pixel when no probe around it matches its surface, e.g. thin
+/// geometry that falls between the probes
+#[derive(Reflect, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SSGIUpsampleFallback {
+    /// The SH of the probe around the pixel that matches it best, even though it was rejected
+    #[default]
+    BestNeighbor,
+    /// The reprojected resolve of the last frame, so the pixel keeps what it had. Uses
+    /// [`SSGIUpsampleFallback::BestNeighbor`] where there's no history.
+    PreviousResolve,
+}
+
+impl SSGIUpsampleFallback {
+    pub(crate) fn shader_def(&self) -> ShaderDefVal {
+        match self {
+            SSGIUpsampleFallback::BestNeighbor => "UPSAMPLE_FALLBACK_BEST_NEIGHBOR".into(),
+            SSGIUpsampleFallback::PreviousResolve => "UPSAMPLE_FALLBACK_PREVIOUS_RESOLVE".into(),
+        }
+    }
+}
+
+wgsl_uniform! {
+    #[derive(Component, Clone, Copy, ShaderType, Debug, Default)]
+    pub struct SSGIResolveConfig {
+        cas_w: u32,
+        cas_h: u32,
+        directions: u32,
+        render_scale: u32,
+        cascade_count: u32,
+        distance_rejection: f32,
+        normal_rejection: f32,
+        min_history_length: f32,
+        max_history_length: f32,
+        specular: f32,
+        specular_samples: u32,
+        history_valid: u32,
+    }
 }
 
 pub struct SSGIResolvePlugin;
@@ -98,6 +222,9 @@ impl Plugin for SSGIResolvePlugin {
         );
 
         app.register_type::<SSGIResolve>()
+            .register_type::<SSGIHistoryClamp>()
+            .register_type::<SSGIUpsampleKernel>()
+            .register_type::<SSGIUpsampleFallback>()
             .add_plugins(ExtractComponentPlugin::<SSGIResolve>::default());
         let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
             return;
@@ -105,7 +232,14 @@ impl Plugin for SSGIResolvePlugin {
 
         render_app
             .add_systems(Render, prepare_textures.in_set(RenderSet::PrepareResources))
+            .add_systems(
+                Render,
+                (prepare_uniforms, prepare_bind_groups)
+                    .chain()
+                    .in_set(RenderSet::PrepareBindGroups),
+            )
             .init_resource::<SpecializedRenderPipelines<SSGIResolveLayout>>()
+            .init_resource::<PerViewBindGroups<SSGIResolveConfig>>()
             .add_render_graph_node::<SSGIResolveNode>(Core3d, SSGIResolveLabel)
             .add_systems(Render, (prepare_pipelines.in_set(RenderSet::Prepare),))
             .add_render_graph_edges(
@@ -119,7 +253,9 @@ impl Plugin for SSGIResolvePlugin {
             Ok(render_app) => render_app,
             Err(_) => return,
         };
-        render_app.init_resource::<SSGIResolveLayout>();
+        render_app
+            .init_resource::<SSGIResolveLayout>()
+            .init_resource::<SSGISamplers>();
     }
 }
 
@@ -130,15 +266,8 @@ pub struct SSGIResolveNode {
     query: QueryState<
         (
             &'static ViewUniformOffset,
-            &'static SSGITextures,
             &'static SSGIResolveTextures,
             &'static SSGIResolvePipeline,
-            &'static SSGISHTextures,
-            &'static PrepassDownsampleTextures,
-            &'static SSGIPass,
-            &'static SSGIResolve,
-            // todo webgl &'static DisocclusionTextures,
-            // todo webgl &'static DynamicUniformIndex<DisocclusionUniforms>,
         ),
         With<ExtractedView>,
     >,
@@ -169,84 +298,44 @@ impl Node for SSGIResolveNode {
     ) -> Result<(), NodeRunError> {
         let view_entity = graph_context.view_entity();
 
-        let Ok((
-            view_uniform_offset,
-            ssgi_textures,
-            resolve_textures,
-            resolve_pipeline,
-            sh_texture,
-            prepass_downsample_texture,
-            ssgi_pass,
-            ssgi_resolve,
-            // todo webgl disocclusion_textures,
-            // todo webgl disocclusion_uniform_index,
-        )) = self.query.get_manual(world, view_entity)
+        let Ok((view_uniform_offset, resolve_textures, resolve_pipeline)) =
+            self.query.get_manual(world, view_entity)
         else {
             return Ok(());
         };
 
-        let ssgi_sh_pipeline = world.resource::<SSGIResolveLayout>();
         let pipeline_cache = world.resource::<PipelineCache>();
 
         let Some(pipeline) = pipeline_cache.get_render_pipeline(resolve_pipeline.pipeline_id)
         else {
             return Ok(());
         };
-        let linear_sampler = linear_sampler(render_context.render_device());
 
-        let images = world.resource::<RenderAssets<Image>>();
-        let blue_noise_tex = image!(images, &resource!(world, BlueNoise).0);
-
-        // todo webgl let disocclusion_uniforms = world.resource::<ComponentUniforms<DisocclusionUniforms>>();
-        //let Some(disocclusion_uniforms) = disocclusion_uniforms.binding() else {
-        //    return Ok(());
-        //};
-
-        let config = SSGIResolveConfig {
-            cas_w: ssgi_textures.data_textures1[0].texture.width(),
-            cas_h: ssgi_textures.data_textures1[0].texture.height(),
-            directions: ssgi_pass.cascade_0_directions,
-            render_scale: ssgi_pass.render_scale,
-            cascade_count: ssgi_pass.cascade_count,
-            rough_specular: ssgi_pass.rough_specular,
-            rough_specular_sharpness: ssgi_pass.rough_specular_sharpness,
-            distance_rejection: ssgi_resolve.distance_rejection,
-            normal_rejection: ssgi_resolve.normal_rejection,
-            hysteresis: ssgi_resolve.hysteresis,
-            _webgl2_padding_1: 0.0,
-            _webgl2_padding_2: 0.0,
+        let Some(view_bind_groups) = world
+            .resource::<PerViewBindGroups<SSGIResolveConfig>>()
+            .0
+            .get(&view_entity)
+        else {
+            return Ok(());
+        };
+        let Some(bind_group) = view_bind_groups.bind_group(0) else {
+            return Ok(());
         };
-
-        let uniform = uniform_buffer(config, render_context, "SSGI Resolve Config Uniform");
-        let bind_group = render_context.render_device().create_bind_group(
-            "ssgi_resolve_bind_group",
-            &ssgi_sh_pipeline.layout,
-            &BindGroupEntries::with_indices((
-                (0, view_binding(world)),
-                (9, globals_binding(world)),
-                (101, &ssgi_textures.data_textures1[0].default_view),
-                (102, &prepass_downsample_texture.normals.default_view),
-                (103, &prepass_downsample_texture.depth.default_view),
-                (104, &prepass_downsample_texture.motion.default_view),
-                // Use write since it's the one ssgi_generate_sh would have just written to
-                (105, &sh_texture.write.default_view),
-                (106, &resolve_textures.read.default_view),
-                //(107, &disocclusion_textures.output.default_view),
-                (108, &linear_sampler),
-                (109, uniform.as_entire_binding()),
-                (110, &sh_texture.pos_write.default_view),
-                //(111, disocclusion_uniforms),
-                (BLUE_NOISE_ENTRY_N, &blue_noise_tex.texture_view),
-            )),
-        );
 
         let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
             label: Some("ssgi_resolve_pass"),
-            color_attachments: &[Some(RenderPassColorAttachment {
-                view: &resolve_textures.write.default_view,
-                resolve_target: None,
-                ops: Operations::default(),
-            })],
+            color_attachments: &[
+                Some(RenderPassColorAttachment {
+                    view: &resolve_textures.write.default_view,
+                    resolve_target: None,
+                    ops: Operations::default(),
+                }),
+                Some(RenderPassColorAttachment {
+                    view: &resolve_textures.specular_write.default_view,
+                    resolve_target: None,
+                    ops: Operations::default(),
+                }),
+            ],
             depth_stencil_attachment: None,
             timestamp_writes: None,
             occlusion_query_set: None,
@@ -254,11 +343,8 @@ impl Node for SSGIResolveNode {
         render_pass.set_render_pipeline(pipeline);
         render_pass.set_bind_group(
             0,
-            &bind_group,
-            &[
-                view_uniform_offset.offset,
-                //disocclusion_uniform_index.index(),
-            ],
+            bind_group,
+            &[view_uniform_offset.offset, view_bind_groups.offsets[0]],
         );
         render_pass.draw(0..3, 0..1);
 
@@ -279,7 +365,8 @@ struct SSGIResolvePipeline {
 
 impl FromWorld for SSGIResolveLayout {
     fn from_world(world: &mut World) -> Self {
-        let entries = vec![
+        #[allow(unused_mut)]
+        let mut entries = vec![
             view_layout_entry(0),
             globals_layout_entry(9),
             utexture_layout_entry(101, TextureViewDimension::D2),
@@ -288,24 +375,20 @@ impl FromWorld for SSGIResolveLayout {
             ftexture_layout_entry(104, TextureViewDimension::D2), // Prepass Downsample Motion
             utexture_layout_entry(105, TextureViewDimension::D2), // SH Texture
             ftexture_layout_entry(106, TextureViewDimension::D2), // Read Resolve
-            //ftexture_layout_entry(107, TextureViewDimension::D2), // Disocclusion
-            fsampler_layout_entry(108), // Linear Sampler
-            // Disocclusion Parameters
-            //BindGroupLayoutEntry {
-            //    binding: 111,
-            //    ty: BindingType::Buffer {
-            //        ty: BufferBindingType::Uniform,
-            //        has_dynamic_offset: true,
-            //        min_binding_size: Some(DisocclusionUniforms::min_size()),
-            //    },
-            //    visibility: ShaderStages::FRAGMENT,
-            //    count: None,
-            //},
-            uniform_layout_entry(109, SSGIResolveConfig::min_size()),
+            fsampler_layout_entry(108),                           // Linear Sampler
+            dynamic_uniform_layout_entry(109, SSGIResolveConfig::min_size()),
             ftexture_layout_entry(110, TextureViewDimension::D2), // Pos / Reflection Texture
+            ftexture_layout_entry(112, TextureViewDimension::D2), // Read Specular Resolve
+            utexture_layout_entry(113, TextureViewDimension::D2), // Deferred gbuffer
             ftexture_layout_entry(BLUE_NOISE_ENTRY_N, TextureViewDimension::D2Array), // Blue Noise
         ];
 
+        #[cfg(all(
+            feature = "disocclusion",
+            not(all(feature = "webgl", target_arch = "wasm32"))
+        ))]
+        entries.push(ftexture_layout_entry(107, TextureViewDimension::D2)); // Disocclusion
+
         #[cfg(not(all(feature = "file_watcher")))]
         let shader = SHADER_HANDLE;
         #[cfg(all(feature = "file_watcher"))]
@@ -322,8 +405,17 @@ impl FromWorld for SSGIResolveLayout {
     }
 }
 
+#[derive(PartialEq, Eq, Hash, Clone)]
+pub struct SSGIResolvePipelineKey {
+    pub ssgi_key: SSGIPipelineKey,
+    pub deferred: bool,
+    pub history_clamp: SSGIHistoryClamp,
+    pub upsample_kernel: SSGIUpsampleKernel,
+    pub upsample_fallback: SSGIUpsampleFallback,
+}
+
 impl SpecializedRenderPipeline for SSGIResolveLayout {
-    type Key = SSGIPipelineKey;
+    type Key = SSGIResolvePipelineKey;
 
     fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
         let mut shader_defs = Vec::new();
@@ -334,7 +426,14 @@ impl SpecializedRenderPipeline for SSGIResolveLayout {
             shader_def_uint!(BLUE_NOISE_DIMS),
         ]);
 
-        key.shader_defs(&mut shader_defs);
+        key.ssgi_key.shader_defs(&mut shader_defs);
+
+        if key.deferred {
+            shader_defs.push("DEFERRED_PREPASS".into());
+        }
+        shader_defs.push(key.history_clamp.shader_def());
+        shader_defs.push(key.upsample_kernel.shader_def());
+        shader_defs.push(key.upsample_fallback.shader_def());
 
         RenderPipelineDescriptor {
             label: Some("ssgi_resolve_pipeline".into()),
@@ -344,11 +443,18 @@ impl SpecializedRenderPipeline for SSGIResolveLayout {
                 shader: self.shader.clone(),
                 shader_defs,
                 entry_point: "fragment".into(),
-                targets: vec![Some(ColorTargetState {
-                    format: SH_RESOLVE_FORMAT,
-                    blend: None,
-                    write_mask: ColorWrites::ALL,
-                })],
+                targets: vec![
+                    Some(ColorTargetState {
+                        format: SH_RESOLVE_FORMAT,
+                        blend: None,
+                        write_mask: ColorWrites::ALL,
+                    }),
+                    Some(ColorTargetState {
+                        format: SH_RESOLVE_FORMAT,
+                        blend: None,
+                        write_mask: ColorWrites::ALL,
+                    }),
+                ],
             }),
             primitive: PrimitiveState::default(),
             depth_stencil: None,
@@ -362,14 +468,16 @@ impl SpecializedRenderPipeline for SSGIResolveLayout {
 pub struct SSGIResolveTextures {
     pub read: CachedTexture,
     pub write: CachedTexture,
+    /// Indirect specular radiance, needs to be multiplied by the env brdf
+    pub specular_read: CachedTexture,
+    pub specular_write: CachedTexture,
 }
 
 fn prepare_textures(
     mut commands: Commands,
-    mut texture_cache: ResMut<TextureCache>,
     render_device: Res<RenderDevice>,
     views: Query<(Entity, &ExtractedCamera, &ExtractedView), With<SSGIResolve>>,
-    frame_count: Res<FrameCount>,
+    mut view_histories: ResMut<ViewHistories>,
 ) {
     for (entity, camera, _view) in &views {
         if let Some(physical_viewport_size) = camera.physical_viewport_size {
@@ -386,27 +494,23 @@ fn prepare_textures(
                 format: SH_RESOLVE_FORMAT,
                 usage: TextureUsages::RENDER_ATTACHMENT
                     | TextureUsages::TEXTURE_BINDING
+                    | TextureUsages::COPY_SRC
                     | TextureUsages::COPY_DST,
                 view_formats: &[],
             };
 
-            texture_descriptor.label = Some("ssgi_resolve_a");
-            let ssgi_resolve_texture_a =
-                texture_cache.get(&render_device, texture_descriptor.clone());
-            texture_descriptor.label = Some("ssgi_resolve_b");
-            let ssgi_resolve_texture_b =
-                texture_cache.get(&render_device, texture_descriptor.clone());
-
-            let textures = if frame_count.0 % 2 == 0 {
-                SSGIResolveTextures {
-                    write: ssgi_resolve_texture_a,
-                    read: ssgi_resolve_texture_b,
-                }
-            } else {
-                SSGIResolveTextures {
-                    write: ssgi_resolve_texture_b,
-                    read: ssgi_resolve_texture_a,
-                }
+            texture_descriptor.label = Some("ssgi_resolve");
+            let resolve_textures =
+                view_histories.ping_pong(&render_device, entity, &texture_descriptor);
+            texture_descriptor.label = Some("ssgi_resolve_specular");
+            let specular_textures =
+                view_histories.ping_pong(&render_device, entity, &texture_descriptor);
+
+            let textures = SSGIResolveTextures {
+                write: resolve_textures.write,
+                read: resolve_textures.read,
+                specular_write: specular_textures.write,
+                specular_read: specular_textures.read,
             };
             commands.entity(entity).insert(textures);
         }
@@ -418,13 +522,173 @@ pub fn prepare_pipelines(
     pipeline_cache: Res<PipelineCache>,
     mut pipelines: ResMut<SpecializedRenderPipelines<SSGIResolveLayout>>,
     layout: Res<SSGIResolveLayout>,
-    views: Query<(Entity, &SSGIPass)>,
+    views: Query<(Entity, &SSGIPass, &SSGIResolve, Has<DeferredPrepass>)>,
 ) {
-    for (entity, ssgi_pass) in &views {
+    for (entity, ssgi_pass, ssgi_resolve, deferred) in &views {
+        let key = SSGIResolvePipelineKey {
+            ssgi_key: ssgi_pass.key(),
+            deferred,
+            history_clamp: ssgi_resolve.history_clamp,
+            upsample_kernel: ssgi_resolve.upsample_kernel,
+            upsample_fallback: ssgi_resolve.upsample_fallback,
+        };
         let pipeline_id: CachedRenderPipelineId =
-            pipelines.specialize(&pipeline_cache, &layout, ssgi_pass.key());
+            pipelines.specialize(&pipeline_cache, &layout, key);
         commands
             .entity(entity)
             .insert(SSGIResolvePipeline { pipeline_id });
     }
 }
+
+fn prepare_uniforms(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut bind_groups: ResMut<PerViewBindGroups<SSGIResolveConfig>>,
+    view_histories: Res<ViewHistories>,
+    views: Query<(Entity, &SSGITextures, &SSGIPass, &SSGIResolve)>,
+) {
+    bind_groups.0.retain(|entity, _| views.contains(*entity));
+
+    for (entity, ssgi_textures, ssgi_pass, ssgi_resolve) in &views {
+        let history_valid = view_histories
+            .get(entity)
+            .is_some_and(|history| history.valid);
+        // Without a valid history, only the current frame is used
+        let (min_history_length, max_history_length) = if history_valid {
+            let min_history_length = ssgi_resolve.min_history_length.max(1.0);
+            (
+                min_history_length,
+                ssgi_resolve.max_history_length.max(min_history_length),
+            )
+        } else {
+            (1.0, 1.0)
+        };
+        let config = SSGIResolveConfig {
+            cas_w: ssgi_textures.data_textures1[0].texture.width(),
+            cas_h: ssgi_textures.data_textures1[0].texture.height(),
+            directions: ssgi_pass.cascade_0_directions,
+            render_scale: ssgi_pass.render_scale,
+            cascade_count: ssgi_pass.cascade_count,
+            distance_rejection: ssgi_resolve.distance_rejection,
+            normal_rejection: ssgi_resolve.normal_rejection,
+            min_history_length,
+            max_history_length,
+            specular: ssgi_resolve.specular,
+            specular_samples: ssgi_resolve.specular_samples.max(1),
+            history_valid: history_valid as u32,
+        };
+        bind_groups.0.entry(entity).or_default().write_uniforms(
+            &render_device,
+            &render_queue,
+            [config],
+        );
+    }
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn prepare_bind_groups(
+    render_device: Res<RenderDevice>,
+    layout: Res<SSGIResolveLayout>,
+    samplers: Res<SSGISamplers>,
+    view_uniforms: Res<ViewUniforms>,
+    globals_buffer: Res<GlobalsBuffer>,
+    images: Res<RenderAssets<Image>>,
+    blue_noise: Option<Res<BlueNoise>>,
+    mut bind_groups: ResMut<PerViewBindGroups<SSGIResolveConfig>>,
+    views: Query<(
+        Entity,
+        &SSGITextures,
+        &SSGIResolveTextures,
+        &SSGISHTextures,
+        &PrepassDownsampleTextures,
+        Option<&ViewPrepassTextures>,
+        Option<&SSGIDenoiseTextures>,
+    )>,
+    #[cfg(all(
+        feature = "disocclusion",
+        not(all(feature = "webgl", target_arch = "wasm32"))
+    ))]
+    disocclusion_textures: Query<&DisocclusionTextures>,
+) {
+    let (Some(view_binding), Some(globals_binding)) = (
+        view_uniforms.uniforms.binding(),
+        globals_buffer.buffer.binding(),
+    ) else {
+        return;
+    };
+    let Some(blue_noise_tex) = blue_noise.and_then(|blue_noise| images.get(&blue_noise.0)) else {
+        return;
+    };
+
+    for (
+        entity,
+        ssgi_textures,
+        resolve_textures,
+        sh_texture,
+        prepass_downsample_texture,
+        prepass_textures,
+        denoise_textures,
+    ) in &views
+    {
+        let Some(view_bind_groups) = bind_groups.0.get_mut(&entity) else {
+            continue;
+        };
+        let Some(config_binding) = view_bind_groups.uniforms.binding() else {
+            continue;
+        };
+
+        // Forward doesn't have a gbuffer for the specular roughness, bind a placeholder instead
+        let deferred_view = match prepass_textures.and_then(|textures| textures.deferred.as_ref()) {
+            Some(deferred) => &deferred.texture.default_view,
+            None => &sh_texture.write.default_view,
+        };
+        // Use write since it's the one ssgi_generate_sh would have just written to, unless it's
+        // been filtered by SSGIDenoise
+        let sh_view = match denoise_textures {
+            Some(denoise_textures) => &denoise_textures.output().default_view,
+            None => &sh_texture.write.default_view,
+        };
+        #[allow(unused_mut)]
+        let mut entries = BindGroupEntries::with_indices((
+            (0, view_binding.clone()),
+            (9, globals_binding.clone()),
+            (101, &ssgi_textures.data_textures1[0].default_view),
+            (102, &prepass_downsample_texture.normals.default_view),
+            (103, &prepass_downsample_texture.depth.default_view),
+            (104, &prepass_downsample_texture.motion.default_view),
+            (105, sh_view),
+            (106, &resolve_textures.read.default_view),
+            (108, &samplers.linear),
+            (109, config_binding),
+            (110, &sh_texture.pos_write.default_view),
+            (112, &resolve_textures.specular_read.default_view),
+            (113, deferred_view),
+            (BLUE_NOISE_ENTRY_N, &blue_noise_tex.texture_view),
+        ))
+        .to_vec();
+
+        #[cfg(all(
+            feature = "disocclusion",
+            not(all(feature = "webgl", target_arch = "wasm32"))
+        ))]
+        {
+            let Ok(disocclusion_textures) = disocclusion_textures.get(entity) else {
+                continue;
+            };
+            entries.push(BindGroupEntry {
+                binding: 107,
+                resource: BindingResource::TextureView(&disocclusion_textures.output.default_view),
+            });
+        }
+
+        view_bind_groups
+            .bind_groups
+            .resize_with(1, Default::default);
+        view_bind_groups.bind_groups[0].update(
+            &render_device,
+            "ssgi_resolve_bind_group",
+            &layout.layout,
+            &entries,
+        );
+    }
+}
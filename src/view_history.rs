@@ -0,0 +1,265 @@
+use std::f32::consts::FRAC_PI_4;
+
+use bevy::{
+    prelude::*,
+    render::{
+        camera::ExtractedCamera,
+        extract_component::{ExtractComponent, ExtractComponentPlugin},
+        render_resource::{TextureDescriptor, TextureViewDescriptor},
+        renderer::RenderDevice,
+        texture::CachedTexture,
+        view::ExtractedView,
+        Extract, ExtractSchedule, Render, RenderApp, RenderSet,
+    },
+    utils::HashMap,
+};
+#[cfg(feature = "inspector")]
+use bevy_inspector_egui::{inspector_options::ReflectInspectorOptions, InspectorOptions};
+
+/// Tracks the history of each view separately, so views that aren't rendered every frame, or are
+/// rendered at different cadences, ping-pong their own history textures instead of following the
+/// global [`bevy::core::FrameCount`].
+pub struct ViewHistoryPlugin;
+impl Plugin for ViewHistoryPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<SSGICameraCutDetection>()
+            .add_plugins((
+                ExtractComponentPlugin::<SSGIHistoryReset>::default(),
+                ExtractComponentPlugin::<SSGICameraCutDetection>::default(),
+            ))
+            .add_systems(First, remove_history_resets);
+
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .init_resource::<ViewHistories>()
+            .add_systems(ExtractSchedule, extract_view_histories)
+            .add_systems(
+                Render,
+                advance_view_histories.in_set(RenderSet::ManageViews),
+            );
+    }
+}
+
+/// Add to a camera to drop its SSGI history on the next frame it's rendered, e.g. on a cut or a
+/// teleport, so the previous shot isn't blended in. Removed once the camera has been rendered.
+#[derive(Component, ExtractComponent, Clone, Copy, Debug, Default)]
+pub struct SSGIHistoryReset;
+
+/// Drops the SSGI history of a camera when it moves or turns further than this between two frames
+/// it's rendered, the same as adding [`SSGIHistoryReset`]. Not part of the bundles, add it to the
+/// cameras that can teleport.
+///
+/// `max_translation` is in world units and isn't scaled with the scene, the default assumes a
+/// scene in meters where the camera moves at most a couple of meters per frame.
+#[derive(Component, ExtractComponent, Clone, Reflect)]
+#[reflect(Component)]
+#[cfg_attr(feature = "inspector", derive(InspectorOptions))]
+#[cfg_attr(feature = "inspector", reflect(InspectorOptions))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct SSGICameraCutDetection {
+    /// Distance in world units the camera can move in a frame before it counts as a cut, scale it
+    /// with the scene
+    #[cfg_attr(feature = "inspector", inspector(min = 0.0))]
+    pub max_translation: f32,
+    /// Angle in radians the camera can turn in a frame before it counts as a cut
+    #[cfg_attr(feature = "inspector", inspector(min = 0.0))]
+    pub max_rotation: f32,
+}
+
+impl Default for SSGICameraCutDetection {
+    fn default() -> Self {
+        SSGICameraCutDetection {
+            max_translation: 2.0,
+            max_rotation: FRAC_PI_4,
+        }
+    }
+}
+
+impl SSGICameraCutDetection {
+    /// If the camera moving from `previous` to `current` counts as a cut
+    pub fn is_cut(&self, previous: &GlobalTransform, current: &GlobalTransform) -> bool {
+        let (_, previous_rotation, previous_translation) = previous.to_scale_rotation_translation();
+        let (_, rotation, translation) = current.to_scale_rotation_translation();
+        previous_translation.distance(translation) > self.max_translation
+            || previous_rotation.angle_between(rotation) > self.max_rotation
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ViewHistory {
+    /// Number of frames this view has been rendered, ping-ponged history textures alternate on it
+    pub frame_index: u32,
+    /// If the history textures hold the last frame rendered by this view. False on the first frame
+    /// of a view, on frames its history textures are recreated, and after a reset or camera cut.
+    pub valid: bool,
+}
+
+impl ViewHistory {
+    /// The history of the next frame rendered by the view, with the current frame as its history
+    pub fn next(self) -> Self {
+        ViewHistory {
+            frame_index: self.frame_index.wrapping_add(1),
+            valid: true,
+        }
+    }
+
+    /// Index of the ping-ponged texture written this frame, the other one holds the history
+    pub fn write_index(&self) -> usize {
+        (self.frame_index % 2) as usize
+    }
+}
+
+/// Textures written this frame, and read from the last frame the view was rendered
+pub struct HistoryTextures {
+    pub write: CachedTexture,
+    pub read: CachedTexture,
+}
+
+struct HistoryTextureSet {
+    descriptor: TextureDescriptor<'static>,
+    textures: Vec<CachedTexture>,
+    /// Requested since the view was last advanced. Sets that aren't are dropped
+    used: bool,
+}
+
+#[derive(Default)]
+struct ViewHistoryState {
+    history: ViewHistory,
+    textures: HashMap<&'static str, HistoryTextureSet>,
+    /// Camera transform of the last frame the view was rendered, for [`SSGICameraCutDetection`]
+    transform: Option<GlobalTransform>,
+}
+
+/// The [`ViewHistory`] and history textures of each view. The textures are owned by the view
+/// instead of coming from the [`bevy::render::texture::TextureCache`], which would hand them to
+/// whichever view asks first, and free them if a view isn't rendered for a few frames.
+#[derive(Resource, Default)]
+pub struct ViewHistories(HashMap<Entity, ViewHistoryState>);
+
+impl ViewHistories {
+    /// The history of the view this frame. None if no history textures were requested for it.
+    pub fn get(&self, entity: Entity) -> Option<ViewHistory> {
+        self.0.get(&entity).map(|state| state.history)
+    }
+
+    /// A pair of textures ping-ponged across the frames the view is rendered, identified by the
+    /// label of the descriptor. Created on first use, and recreated when the descriptor changes,
+    /// which invalidates the history of the view for this frame.
+    pub fn ping_pong(
+        &mut self,
+        render_device: &RenderDevice,
+        entity: Entity,
+        descriptor: &TextureDescriptor<'static>,
+    ) -> HistoryTextures {
+        let state = self.0.entry(entity).or_default();
+        let write_index = state.history.write_index();
+        let textures = history_textures(state, render_device, descriptor, 2);
+        HistoryTextures {
+            write: textures[write_index].clone(),
+            read: textures[1 - write_index].clone(),
+        }
+    }
+
+    /// A texture kept across the frames the view is rendered, for history that's written in place.
+    /// Created and recreated like [`ViewHistories::ping_pong`].
+    pub fn persistent(
+        &mut self,
+        render_device: &RenderDevice,
+        entity: Entity,
+        descriptor: &TextureDescriptor<'static>,
+    ) -> CachedTexture {
+        let state = self.0.entry(entity).or_default();
+        history_textures(state, render_device, descriptor, 1)[0].clone()
+    }
+}
+
+fn history_textures<'a>(
+    state: &'a mut ViewHistoryState,
+    render_device: &RenderDevice,
+    descriptor: &TextureDescriptor<'static>,
+    count: usize,
+) -> &'a [CachedTexture] {
+    let set = state
+        .textures
+        .entry(descriptor.label.unwrap_or_default())
+        .or_insert_with(|| HistoryTextureSet {
+            descriptor: descriptor.clone(),
+            textures: Vec::new(),
+            used: false,
+        });
+    if set.textures.len() != count || set.descriptor != *descriptor {
+        set.descriptor = descriptor.clone();
+        set.textures = (0..count)
+            .map(|_| {
+                let texture = render_device.create_texture(descriptor);
+                CachedTexture {
+                    default_view: texture.create_view(&TextureViewDescriptor::default()),
+                    texture,
+                }
+            })
+            .collect();
+        state.history.valid = false;
+    }
+    set.used = true;
+    &set.textures
+}
+
+/// Removes [`SSGIHistoryReset`] from cameras that were rendered with it last frame
+fn remove_history_resets(
+    mut commands: Commands,
+    resets: Query<(Entity, &Camera), With<SSGIHistoryReset>>,
+) {
+    for (entity, camera) in &resets {
+        if camera.is_active {
+            commands.entity(entity).remove::<SSGIHistoryReset>();
+        }
+    }
+}
+
+/// Drops the history of views whose camera was despawned
+fn extract_view_histories(
+    mut histories: ResMut<ViewHistories>,
+    cameras: Extract<Query<(), With<Camera>>>,
+) {
+    histories.0.retain(|entity, _| cameras.contains(*entity));
+}
+
+/// Moves the history of each view that's rendered this frame on by a frame. Views that aren't
+/// rendered keep their history as is, so it's still valid when they're rendered again.
+#[allow(clippy::type_complexity)]
+pub fn advance_view_histories(
+    mut histories: ResMut<ViewHistories>,
+    views: Query<
+        (
+            Entity,
+            &ExtractedView,
+            Has<SSGIHistoryReset>,
+            Option<&SSGICameraCutDetection>,
+        ),
+        With<ExtractedCamera>,
+    >,
+) {
+    for (entity, view, reset, cut_detection) in &views {
+        let Some(state) = histories.0.get_mut(&entity) else {
+            continue;
+        };
+        // Textures that weren't requested the last time the view was rendered aren't used anymore
+        state
+            .textures
+            .retain(|_, set| std::mem::take(&mut set.used));
+        let cut = match (cut_detection, &state.transform) {
+            (Some(cut_detection), Some(previous)) => {
+                cut_detection.is_cut(previous, &view.transform)
+            }
+            _ => false,
+        };
+        state.history = state.history.next();
+        state.history.valid = !(reset || cut);
+        state.transform = Some(view.transform);
+    }
+    histories.0.retain(|_, state| !state.textures.is_empty());
+}
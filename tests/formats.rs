@@ -0,0 +1,233 @@
+use bevy::math::Vec3;
+use bevy_ridiculous_ssgi::formats::*;
+
+// Small xorshift so the sweeps are deterministic without extra dependencies
+struct Rng(u32);
+impl Rng {
+    fn next_u32(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+    // Spread over many exponents, not just [0, 1)
+    fn next_f32(&mut self, max: f32) -> f32 {
+        let t = (self.next_u32() >> 8) as f32 / (1 << 24) as f32;
+        let e = (self.next_u32() % 40) as i32 - 24;
+        (t * 2.0f32.powi(e)).min(max)
+    }
+}
+
+fn max_error(max_component: f32, mantissa_bits: i32) -> f32 {
+    // Half a step of the shared exponent, plus the smallest step for tiny values
+    max_component * 2.0f32.powi(-mantissa_bits) + 2.0f32.powi(-24)
+}
+
+#[test]
+fn rgb9e5_round_trip() {
+    let mut rng = Rng(0x9E3779B9);
+    for _ in 0..100_000 {
+        let v = Vec3::new(
+            rng.next_f32(MAX_RGB9E5_),
+            rng.next_f32(MAX_RGB9E5_),
+            rng.next_f32(MAX_RGB9E5_),
+        );
+        let packed = vec3_to_rgb9e5(v);
+        let decoded = rgb9e5_to_vec3(packed);
+        let error = max_error(v.max_element(), RGB9E5_MANTISSA_BITS);
+        assert!(
+            (decoded - v).abs().max_element() <= error,
+            "{v:?} decoded to {decoded:?}"
+        );
+        // Decoded values are exactly representable, so they pack to the same bits
+        assert_eq!(vec3_to_rgb9e5(decoded), packed, "{v:?}");
+    }
+}
+
+#[test]
+fn xyz8e5_round_trip() {
+    let mut rng = Rng(0x85EBCA6B);
+    let sign = |rng: &mut Rng| if rng.next_u32() & 1 == 0 { 1.0 } else { -1.0 };
+    for _ in 0..100_000 {
+        let v = Vec3::new(
+            rng.next_f32(MAX_XYZ8E5_) * sign(&mut rng),
+            rng.next_f32(MAX_XYZ8E5_) * sign(&mut rng),
+            rng.next_f32(MAX_XYZ8E5_) * sign(&mut rng),
+        );
+        let packed = vec3_to_xyz8e5(v);
+        let decoded = xyz8e5_to_vec3(packed);
+        let error = max_error(v.abs().max_element(), XYZ8E5_MANTISSA_BITS);
+        assert!(
+            (decoded - v).abs().max_element() <= error,
+            "{v:?} decoded to {decoded:?}"
+        );
+        assert_eq!(vec3_to_xyz8e5(decoded), packed, "{v:?}");
+    }
+}
+
+#[test]
+fn rgb9e5_edge_cases() {
+    assert_eq!(rgb9e5_to_vec3(vec3_to_rgb9e5(Vec3::ZERO)), Vec3::ZERO);
+    assert_eq!(
+        rgb9e5_to_vec3(vec3_to_rgb9e5(Vec3::splat(MAX_RGB9E5_))),
+        Vec3::splat(MAX_RGB9E5_)
+    );
+    // Out of range values clamp
+    assert_eq!(
+        rgb9e5_to_vec3(vec3_to_rgb9e5(Vec3::new(-1.0, 1.0e9, f32::INFINITY))),
+        Vec3::new(0.0, MAX_RGB9E5_, MAX_RGB9E5_)
+    );
+    // Denormals round to 0.0
+    let denormal = f32::from_bits(1);
+    assert_eq!(
+        rgb9e5_to_vec3(vec3_to_rgb9e5(Vec3::splat(denormal))),
+        Vec3::ZERO
+    );
+    // Smallest value that doesn't round to 0.0
+    assert_eq!(
+        rgb9e5_to_vec3(vec3_to_rgb9e5(Vec3::splat(EPSILON_RGB9E5_))),
+        Vec3::splat(EPSILON_RGB9E5_)
+    );
+    // NaN becomes 0.0, like max() on the GPU
+    assert_eq!(
+        rgb9e5_to_vec3(vec3_to_rgb9e5(Vec3::new(f32::NAN, 1.0, 2.0))),
+        Vec3::new(0.0, 1.0, 2.0)
+    );
+    // Rounding up to the next exponent
+    assert_eq!(
+        rgb9e5_to_vec3(vec3_to_rgb9e5(Vec3::splat(1.0 - 1.0e-4))),
+        Vec3::ONE
+    );
+}
+
+#[test]
+fn xyz8e5_edge_cases() {
+    assert_eq!(xyz8e5_to_vec3(vec3_to_xyz8e5(Vec3::ZERO)), Vec3::ZERO);
+    assert_eq!(
+        xyz8e5_to_vec3(vec3_to_xyz8e5(Vec3::splat(MAX_XYZ8E5_))),
+        Vec3::splat(MAX_XYZ8E5_)
+    );
+    assert_eq!(
+        xyz8e5_to_vec3(vec3_to_xyz8e5(Vec3::new(
+            -MAX_XYZ8E5_,
+            1.0e9,
+            f32::NEG_INFINITY
+        ))),
+        Vec3::new(-MAX_XYZ8E5_, MAX_XYZ8E5_, -MAX_XYZ8E5_)
+    );
+    // Signs are kept, including for -0.0
+    let decoded = xyz8e5_to_vec3(vec3_to_xyz8e5(Vec3::new(-0.0, -0.5, 0.25)));
+    assert_eq!(decoded, Vec3::new(0.0, -0.5, 0.25));
+    assert!(decoded.x.is_sign_negative());
+    let denormal = f32::from_bits(1);
+    assert_eq!(
+        xyz8e5_to_vec3(vec3_to_xyz8e5(Vec3::splat(denormal))),
+        Vec3::ZERO
+    );
+    assert_eq!(
+        xyz8e5_to_vec3(vec3_to_xyz8e5(Vec3::splat(EPSILON_XYZ8E5_))),
+        Vec3::splat(EPSILON_XYZ8E5_)
+    );
+    // NaN becomes the max value, like min() on the GPU
+    let decoded = xyz8e5_to_vec3(vec3_to_xyz8e5(Vec3::new(f32::NAN, -1.0, 2.0)));
+    assert_eq!(decoded, Vec3::new(MAX_XYZ8E5_, 0.0, 0.0));
+}
+
+#[test]
+fn max_constants() {
+    let max = |mantissa: i32, values: i32, exp: u32| {
+        mantissa as f32 / values as f32 * (1u32 << exp) as f32
+    };
+    assert_eq!(
+        max(MAX_RGB9E5_MANTISSA, RGB9E5_MANTISSA_VALUES, MAX_RGB9E5_EXP),
+        MAX_RGB9E5_
+    );
+    assert_eq!(
+        max(MAX_XYZ8E5_MANTISSA, XYZ8E5_MANTISSA_VALUES, MAX_XYZ8E5_EXP),
+        MAX_XYZ8E5_
+    );
+    assert_eq!(
+        EPSILON_RGB9E5_,
+        2.0f32.powi(-RGB9E5_MANTISSA_BITS - RGB9E5_EXP_BIAS)
+    );
+    assert_eq!(
+        EPSILON_XYZ8E5_,
+        2.0f32.powi(-XYZ8E5_MANTISSA_BITS - XYZ8E5_EXP_BIAS)
+    );
+}
+
+/// `const NAME = value;` lines from a WGSL file
+fn wgsl_constants(src: &str) -> Vec<(&str, &str)> {
+    src.lines()
+        .filter_map(|line| line.trim().strip_prefix("const "))
+        .filter_map(|line| {
+            let (name, value) = line.split_once('=')?;
+            Some((name.trim(), value.trim().trim_end_matches(';').trim()))
+        })
+        .collect()
+}
+
+fn check_constants(src: &str, rust: &[(&str, f64)]) {
+    let wgsl = wgsl_constants(src);
+    assert_eq!(
+        wgsl.len(),
+        rust.len(),
+        "Constants added or removed: {wgsl:?}"
+    );
+    for (name, value) in wgsl {
+        let Some((_, rust_value)) = rust.iter().find(|(n, _)| *n == name) else {
+            panic!("{name} is missing in formats.rs");
+        };
+        let wgsl_value: f64 = value.trim_end_matches('u').parse().unwrap();
+        assert_eq!(
+            wgsl_value as f32, *rust_value as f32,
+            "{name} doesn't match"
+        );
+    }
+}
+
+#[test]
+fn rgb9e5_constants_match_wgsl() {
+    check_constants(
+        include_str!("../src/rgb9e5.wgsl"),
+        &[
+            ("RGB9E5_EXPONENT_BITS", RGB9E5_EXPONENT_BITS as f64),
+            ("RGB9E5_MANTISSA_BITS", RGB9E5_MANTISSA_BITS as f64),
+            ("RGB9E5_MANTISSA_BITSU", RGB9E5_MANTISSA_BITSU as f64),
+            ("RGB9E5_EXP_BIAS", RGB9E5_EXP_BIAS as f64),
+            (
+                "RGB9E5_MAX_VALID_BIASED_EXP",
+                RGB9E5_MAX_VALID_BIASED_EXP as f64,
+            ),
+            ("MAX_RGB9E5_EXP", MAX_RGB9E5_EXP as f64),
+            ("RGB9E5_MANTISSA_VALUES", RGB9E5_MANTISSA_VALUES as f64),
+            ("MAX_RGB9E5_MANTISSA", MAX_RGB9E5_MANTISSA as f64),
+            ("MAX_RGB9E5_MANTISSAU", MAX_RGB9E5_MANTISSAU as f64),
+            ("MAX_RGB9E5_", MAX_RGB9E5_ as f64),
+            ("EPSILON_RGB9E5_", EPSILON_RGB9E5_ as f64),
+        ],
+    );
+}
+
+#[test]
+fn xyz8e5_constants_match_wgsl() {
+    check_constants(
+        include_str!("../src/xyz8e5.wgsl"),
+        &[
+            ("XYZ8E5_EXPONENT_BITS", XYZ8E5_EXPONENT_BITS as f64),
+            ("XYZ8E5_MANTISSA_BITS", XYZ8E5_MANTISSA_BITS as f64),
+            ("XYZ8E5_MANTISSA_BITSU", XYZ8E5_MANTISSA_BITSU as f64),
+            ("XYZ8E5_EXP_BIAS", XYZ8E5_EXP_BIAS as f64),
+            (
+                "XYZ8E5_MAX_VALID_BIASED_EXP",
+                XYZ8E5_MAX_VALID_BIASED_EXP as f64,
+            ),
+            ("MAX_XYZ8E5_EXP", MAX_XYZ8E5_EXP as f64),
+            ("XYZ8E5_MANTISSA_VALUES", XYZ8E5_MANTISSA_VALUES as f64),
+            ("MAX_XYZ8E5_MANTISSA", MAX_XYZ8E5_MANTISSA as f64),
+            ("MAX_XYZ8E5_MANTISSAU", MAX_XYZ8E5_MANTISSAU as f64),
+            ("MAX_XYZ8E5_", MAX_XYZ8E5_ as f64),
+            ("EPSILON_XYZ8E5_", EPSILON_XYZ8E5_ as f64),
+        ],
+    );
+}
@@ -0,0 +1,47 @@
+#![cfg(feature = "serde")]
+
+use bevy_ridiculous_ssgi::{
+    settings_asset::SSGISettingsAsset, ssgi::SSGIPass, ssgi_denoise::SSGIDenoise,
+    ssgi_resolve::SSGIResolve,
+};
+
+fn parse(ron: &str) -> Result<SSGISettingsAsset, ron::error::SpannedError> {
+    ron::de::from_str(ron)
+}
+
+#[test]
+fn missing_fields_use_defaults() {
+    let settings = parse("(ssgi_resolve: Some((max_history_length: 7.0)))").unwrap();
+    let ssgi_resolve = settings.ssgi_resolve.unwrap();
+    assert_eq!(ssgi_resolve.max_history_length, 7.0);
+    assert!(settings.ssgi_pass.is_none());
+}
+
+#[test]
+fn removed_hysteresis_fails_to_load() {
+    let error = parse("(ssgi_resolve: Some((hysteresis: 0.1)))")
+        .err()
+        .unwrap();
+    assert!(error.to_string().contains("hysteresis"), "{error}");
+    assert!(parse("(ssgi_generate_sh: Some((hysteresis: 0.1)))").is_err());
+}
+
+#[test]
+fn round_trip() {
+    let ssgi_denoise = SSGIDenoise {
+        iterations: 3,
+        ..Default::default()
+    };
+    let settings = SSGISettingsAsset::from_components(
+        Some(&SSGIPass::default()),
+        None,
+        Some(&SSGIResolve::default()),
+        Some(&ssgi_denoise),
+        None,
+        None,
+    );
+    let parsed = parse(&settings.to_ron().unwrap()).unwrap();
+    assert!(parsed.ssgi_pass.is_some());
+    assert!(parsed.ssgi_generate_sh.is_none());
+    assert_eq!(parsed.ssgi_denoise.unwrap().iterations, 3);
+}
@@ -0,0 +1,674 @@
+//! Composes every shader with each combination of shader defs its pipelines can specialize with
+//! and validates the result with naga, so shader errors show up in `cargo test` without a GPU.
+
+use std::{collections::HashMap, sync::OnceLock};
+
+use bevy::{
+    log::LogPlugin,
+    prelude::*,
+    render::{
+        render_resource::{Shader, ShaderDefVal, ShaderImport},
+        settings::WgpuSettings,
+        RenderPlugin,
+    },
+    winit::WinitPlugin,
+};
+use bevy_ridiculous_ssgi::{ssgi::SSGIPipelineKey, BLUE_NOISE_DIMS, BLUE_NOISE_ENTRY_N};
+use naga::valid::Capabilities;
+use naga_oil::compose::{Composer, NagaModuleDescriptor, ShaderDefValue, ShaderType};
+
+const TONEMAP_METHODS: [&str; 8] = [
+    "TONEMAP_METHOD_NONE",
+    "TONEMAP_METHOD_REINHARD",
+    "TONEMAP_METHOD_REINHARD_LUMINANCE",
+    "TONEMAP_METHOD_ACES_FITTED",
+    "TONEMAP_METHOD_AGX",
+    "TONEMAP_METHOD_SOMEWHAT_BORING_DISPLAY_TRANSFORM",
+    "TONEMAP_METHOD_BLENDER_FILMIC",
+    "TONEMAP_METHOD_TONY_MC_MAPFACE",
+];
+
+const SHADOW_FILTER_METHODS: [&str; 3] = [
+    "SHADOW_FILTER_METHOD_HARDWARE_2X2",
+    "SHADOW_FILTER_METHOD_CASTANO_13",
+    "SHADOW_FILTER_METHOD_JIMENEZ_14",
+];
+
+struct Variant {
+    webgl: bool,
+    shader_defs: Vec<ShaderDefVal>,
+}
+
+/// All the importable shader modules: bevy's, loaded by its plugins without a renderer, and ours
+fn shader_modules() -> &'static HashMap<ShaderImport, Shader> {
+    static MODULES: OnceLock<HashMap<ShaderImport, Shader>> = OnceLock::new();
+    MODULES.get_or_init(|| {
+        let mut app = App::new();
+        app.add_plugins(
+            DefaultPlugins
+                .set(RenderPlugin {
+                    render_creation: WgpuSettings {
+                        backends: None,
+                        ..default()
+                    }
+                    .into(),
+                    ..default()
+                })
+                .disable::<WinitPlugin>()
+                .disable::<LogPlugin>(),
+        );
+        // Some modules, like bevy_render::maths, are only loaded when the plugins are finished
+        app.finish();
+        app.cleanup();
+
+        let mut modules: HashMap<_, _> = app
+            .world
+            .resource::<Assets<Shader>>()
+            .iter()
+            .map(|(_, shader)| (shader.import_path().clone(), shader.clone()))
+            .collect();
+
+        for shader in [
+            Shader::from_wgsl(include_str!("../src/rgb9e5.wgsl"), "src/rgb9e5.wgsl"),
+            Shader::from_wgsl(include_str!("../src/xyz8e5.wgsl"), "src/xyz8e5.wgsl"),
+            Shader::from_wgsl(include_str!("../src/sampling.wgsl"), "src/sampling.wgsl"),
+            Shader::from_wgsl(
+                include_str!("../src/ssgi_common.wgsl"),
+                "src/ssgi_common.wgsl",
+            ),
+            Shader::from_wgsl(
+                include_str!("../src/depth_reduction.wgsl"),
+                "src/depth_reduction.wgsl",
+            ),
+        ] {
+            modules.insert(shader.import_path().clone(), shader);
+        }
+        modules
+    })
+}
+
+fn add_import(composer: &mut Composer, import: &ShaderImport) -> Result<(), String> {
+    let name = import.module_name();
+    if composer.contains_module(&name) {
+        return Ok(());
+    }
+    let Some(shader) = shader_modules().get(import) else {
+        return Err(format!("Import {name} not found"));
+    };
+    for import in shader.imports() {
+        add_import(composer, import)?;
+    }
+    composer
+        .add_composable_module(shader.into())
+        .map(|_| ())
+        .map_err(|e| e.emit_to_string(composer))
+}
+
+/// Same as the defs the `PipelineCache` adds to every shader
+fn global_shader_defs(webgl: bool) -> Vec<ShaderDefVal> {
+    if webgl {
+        vec![
+            "NO_ARRAY_TEXTURES_SUPPORT".into(),
+            "NO_CUBE_ARRAY_TEXTURES_SUPPORT".into(),
+            "SIXTEEN_BYTE_ALIGNMENT".into(),
+            ShaderDefVal::UInt("AVAILABLE_STORAGE_BUFFER_BINDINGS".to_string(), 0),
+        ]
+    } else {
+        // wgpu's default max_storage_buffers_per_shader_stage
+        vec![ShaderDefVal::UInt(
+            "AVAILABLE_STORAGE_BUFFER_BINDINGS".to_string(),
+            8,
+        )]
+    }
+}
+
+fn compose(composer: &mut Composer, shader: &Shader, variant: &Variant) -> Result<(), String> {
+    for import in shader.imports() {
+        add_import(composer, import)?;
+    }
+
+    let shader_defs = global_shader_defs(variant.webgl)
+        .into_iter()
+        .chain(variant.shader_defs.iter().cloned())
+        .map(|def| match def {
+            ShaderDefVal::Bool(name, v) => (name, ShaderDefValue::Bool(v)),
+            ShaderDefVal::Int(name, v) => (name, ShaderDefValue::Int(v)),
+            ShaderDefVal::UInt(name, v) => (name, ShaderDefValue::UInt(v)),
+        })
+        .collect();
+
+    // make_naga_module() also validates the composed module with the composer's capabilities
+    composer
+        .make_naga_module(NagaModuleDescriptor {
+            source: shader.source.as_str(),
+            file_path: &shader.path,
+            shader_type: ShaderType::Wgsl,
+            shader_defs,
+            additional_imports: &shader.additional_imports,
+        })
+        .map(|_| ())
+        .map_err(|e| e.emit_to_string(composer))
+}
+
+fn validate_shader(source: &'static str, path: &str, variants: Vec<Variant>) {
+    let shader = Shader::from_wgsl(source, path.to_string());
+
+    // Composing is slow enough with this many variants to be worth spreading over threads
+    let threads = std::thread::available_parallelism().map_or(4, |n| n.get());
+    let chunk_size = variants.len().div_ceil(threads).max(1);
+    let errors: Vec<String> = std::thread::scope(|s| {
+        let handles: Vec<_> = variants
+            .chunks(chunk_size)
+            .map(|variants| {
+                let shader = &shader;
+                s.spawn(move || {
+                    let mut native = Composer::default().with_capabilities(Capabilities::all());
+                    let mut webgl = Composer::default().with_capabilities(Capabilities::empty());
+                    variants
+                        .iter()
+                        .filter_map(|variant| {
+                            let composer = if variant.webgl {
+                                &mut webgl
+                            } else {
+                                &mut native
+                            };
+                            compose(composer, shader, variant).err().map(|e| {
+                                let defs: Vec<_> = variant
+                                    .shader_defs
+                                    .iter()
+                                    .map(|def| format!("{def:?}"))
+                                    .collect();
+                                format!(
+                                    "webgl: {}, defs: [{}]\n{e}",
+                                    variant.webgl,
+                                    defs.join(", ")
+                                )
+                            })
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    });
+
+    assert!(
+        errors.is_empty(),
+        "{path} failed in {} variant(s):\n{}",
+        errors.len(),
+        errors.join("\n")
+    );
+}
+
+fn blue_noise_defs(group: u32) -> Vec<ShaderDefVal> {
+    vec![
+        ShaderDefVal::UInt("BLUE_NOISE_GROUP_N".to_string(), group),
+        ShaderDefVal::UInt("BLUE_NOISE_ENTRY_N".to_string(), BLUE_NOISE_ENTRY_N),
+        ShaderDefVal::UInt("BLUE_NOISE_DIMS".to_string(), BLUE_NOISE_DIMS),
+    ]
+}
+
+/// Native with and without disocclusion, and WebGL2, which never uses it
+const TARGETS: [(bool, bool); 3] = [(false, false), (false, true), (true, false)];
+
+fn ssgi_keys() -> Vec<SSGIPipelineKey> {
+    let mut keys = Vec::new();
+    for jitter_probe_position in [false, true] {
+        for jitter_probe_direction in [false, true] {
+            for noise_frame_period in [1, 8, 64] {
+                for debug_occlusion in [false, true] {
+                    keys.push(SSGIPipelineKey {
+                        jitter_probe_position,
+                        jitter_probe_direction,
+                        noise_frame_period,
+                        debug_occlusion,
+                        depth_min_max: false,
+                        back_face_depth: false,
+                    });
+                }
+            }
+        }
+    }
+    keys
+}
+
+fn ssgi_key_defs(key: &SSGIPipelineKey, disocclusion: bool) -> Vec<ShaderDefVal> {
+    let mut shader_defs = Vec::new();
+    key.shader_defs(&mut shader_defs);
+    // Depends on the features this test is built with, so it's added per target instead
+    shader_defs.retain(|def| !matches!(def, ShaderDefVal::Bool(name, _) if name == "DISOCCLUSION"));
+    if disocclusion {
+        shader_defs.push("DISOCCLUSION".into());
+    }
+    shader_defs
+}
+
+/// Variants for the pipelines specialized on [`SSGIPipelineKey`]
+fn ssgi_variants(extra: impl Fn(bool) -> Vec<Vec<ShaderDefVal>>) -> Vec<Variant> {
+    let mut variants = Vec::new();
+    for (webgl, disocclusion) in TARGETS {
+        for key in ssgi_keys() {
+            for extra_defs in extra(webgl) {
+                let mut shader_defs = ssgi_key_defs(&key, disocclusion);
+                shader_defs.extend(blue_noise_defs(0));
+                shader_defs.extend(extra_defs);
+                variants.push(Variant { webgl, shader_defs });
+            }
+        }
+    }
+    variants
+}
+
+/// Variants for the fullscreen pipelines that only add `WEBGL2`, and `DISOCCLUSION` if given
+fn fullscreen_variants(webgl2_def: bool, uses_disocclusion: bool) -> Vec<Variant> {
+    TARGETS
+        .into_iter()
+        .filter(|(_, disocclusion)| uses_disocclusion || !disocclusion)
+        .map(|(webgl, disocclusion)| {
+            let mut shader_defs = Vec::new();
+            if webgl && webgl2_def {
+                shader_defs.push("WEBGL2".into());
+            }
+            if disocclusion {
+                shader_defs.push("DISOCCLUSION".into());
+            }
+            Variant { webgl, shader_defs }
+        })
+        .collect()
+}
+
+#[test]
+fn ssgi_shader() {
+    validate_shader(
+        include_str!("../assets/shaders/ssgi.wgsl"),
+        "assets/shaders/ssgi.wgsl",
+        ssgi_variants(|webgl| {
+            let targets: Vec<Vec<ShaderDefVal>> = if webgl {
+                vec![vec![
+                    "DEFERRED_PREPASS".into(),
+                    "WEBGL2".into(),
+                    "SIXTEEN_BYTE_ALIGNMENT".into(),
+                ]]
+            } else {
+                // Compute is used wherever it's supported, the fragment path is the fallback
+                vec![
+                    vec!["DEFERRED_PREPASS".into()],
+                    vec!["DEFERRED_PREPASS".into(), "COMPUTE".into()],
+                ]
+            };
+            // Only ssgi.wgsl uses the min/max and back face depth, so they're not part of ssgi_keys()
+            targets
+                .into_iter()
+                .flat_map(|defs| {
+                    let mut min_max = defs.clone();
+                    min_max.push("DEPTH_MIN_MAX".into());
+                    [defs, min_max]
+                })
+                .flat_map(|defs| {
+                    let mut back_face = defs.clone();
+                    back_face.push("BACK_FACE_DEPTH".into());
+                    [defs, back_face]
+                })
+                .collect()
+        }),
+    );
+}
+
+const HISTORY_CLAMPS: [&str; 3] = [
+    "HISTORY_CLAMP_NONE",
+    "HISTORY_CLAMP_MIN_MAX",
+    "HISTORY_CLAMP_VARIANCE",
+];
+
+const UPSAMPLE_KERNELS: [&str; 3] = [
+    "UPSAMPLE_KERNEL_2X2",
+    "UPSAMPLE_KERNEL_3X3",
+    "UPSAMPLE_KERNEL_4X4",
+];
+
+const UPSAMPLE_FALLBACKS: [&str; 2] = [
+    "UPSAMPLE_FALLBACK_BEST_NEIGHBOR",
+    "UPSAMPLE_FALLBACK_PREVIOUS_RESOLVE",
+];
+
+#[test]
+fn ssgi_generate_sh_shader() {
+    validate_shader(
+        include_str!("../assets/shaders/ssgi_generate_sh.wgsl"),
+        "assets/shaders/ssgi_generate_sh.wgsl",
+        ssgi_variants(|_| {
+            HISTORY_CLAMPS
+                .into_iter()
+                .map(|history_clamp| vec![history_clamp.into()])
+                .collect()
+        }),
+    );
+}
+
+#[test]
+fn ssgi_resolve_shader() {
+    validate_shader(
+        include_str!("../assets/shaders/ssgi_resolve.wgsl"),
+        "assets/shaders/ssgi_resolve.wgsl",
+        // Forward and deferred, with each history clamp, and each upsampling kernel & fallback
+        ssgi_variants(|_| {
+            let mut options: Vec<Vec<ShaderDefVal>> = HISTORY_CLAMPS
+                .into_iter()
+                .map(|history_clamp| {
+                    vec![
+                        history_clamp.into(),
+                        UPSAMPLE_KERNELS[0].into(),
+                        UPSAMPLE_FALLBACKS[0].into(),
+                    ]
+                })
+                .collect();
+            for kernel in UPSAMPLE_KERNELS {
+                for fallback in UPSAMPLE_FALLBACKS {
+                    options.push(vec![
+                        HISTORY_CLAMPS[0].into(),
+                        kernel.into(),
+                        fallback.into(),
+                    ]);
+                }
+            }
+            options
+                .into_iter()
+                .flat_map(|defs| {
+                    let mut deferred = defs.clone();
+                    deferred.push("DEFERRED_PREPASS".into());
+                    [defs, deferred]
+                })
+                .collect()
+        }),
+    );
+}
+
+#[test]
+fn ssgi_denoise_shader() {
+    validate_shader(
+        include_str!("../assets/shaders/ssgi_denoise.wgsl"),
+        "assets/shaders/ssgi_denoise.wgsl",
+        ssgi_variants(|_| vec![vec![]]),
+    );
+}
+
+#[test]
+fn deferred_lighting_shader() {
+    let mut tonemapping: Vec<Vec<ShaderDefVal>> = vec![vec![]];
+    for method in TONEMAP_METHODS {
+        for deband_dither in [false, true] {
+            let mut shader_defs: Vec<ShaderDefVal> =
+                vec!["TONEMAP_IN_SHADER".into(), method.into()];
+            if deband_dither {
+                shader_defs.push("DEBAND_DITHER".into());
+            }
+            tonemapping.push(shader_defs);
+        }
+    }
+
+    let mut variants = Vec::new();
+    for webgl in [false, true] {
+        for tonemap_defs in &tonemapping {
+            for ssao in [false, true] {
+                for environment_map in [false, true] {
+                    for normal_prepass in [false, true] {
+                        for shadow_filter_method in SHADOW_FILTER_METHODS {
+                            let mut shader_defs: Vec<ShaderDefVal> = vec![
+                                "DEFERRED_LIGHTING_PIPELINE".into(),
+                                "DEPTH_PREPASS".into(),
+                                "MOTION_VECTOR_PREPASS".into(),
+                                "DEFERRED_PREPASS".into(),
+                                shadow_filter_method.into(),
+                            ];
+                            shader_defs.extend(tonemap_defs.iter().cloned());
+                            if ssao {
+                                shader_defs.push("SCREEN_SPACE_AMBIENT_OCCLUSION".into());
+                            }
+                            if environment_map {
+                                shader_defs.push("ENVIRONMENT_MAP".into());
+                                // Only change anything with an environment map
+                                shader_defs.push("SSGI_SPECULAR".into());
+                                shader_defs.push("SSGI_ENVIRONMENT_DIFFUSE".into());
+                            }
+                            if normal_prepass {
+                                shader_defs.push("NORMAL_PREPASS".into());
+                            }
+                            if webgl {
+                                shader_defs.push("WEBGL2".into());
+                                shader_defs.push("SIXTEEN_BYTE_ALIGNMENT".into());
+                            }
+                            shader_defs.extend(blue_noise_defs(1));
+                            variants.push(Variant { webgl, shader_defs });
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    validate_shader(
+        include_str!("../assets/shaders/deferred_lighting.wgsl"),
+        "assets/shaders/deferred_lighting.wgsl",
+        variants,
+    );
+}
+
+#[test]
+fn prepass_convert_shader() {
+    let mut variants = fullscreen_variants(true, false);
+    for variant in fullscreen_variants(true, false) {
+        let mut shader_defs = variant.shader_defs;
+        shader_defs.push("FORWARD_PREPASS".into());
+        variants.push(Variant {
+            webgl: variant.webgl,
+            shader_defs,
+        });
+    }
+    validate_shader(
+        include_str!("../src/prepass_convert.wgsl"),
+        "src/prepass_convert.wgsl",
+        variants,
+    );
+}
+
+const DEPTH_REDUCTIONS: [&str; 5] = [
+    "DEPTH_REDUCTION_POINT",
+    "DEPTH_REDUCTION_CLOSEST",
+    "DEPTH_REDUCTION_FARTHEST",
+    "DEPTH_REDUCTION_CHECKERBOARD",
+    "DEPTH_REDUCTION_MIN_MAX",
+];
+
+#[test]
+fn prepass_downsample_shader() {
+    let mut variants = Vec::new();
+    for variant in fullscreen_variants(false, false) {
+        for depth_reduction in DEPTH_REDUCTIONS {
+            let mut shader_defs = variant.shader_defs.clone();
+            shader_defs.push(depth_reduction.into());
+            variants.push(Variant {
+                webgl: variant.webgl,
+                shader_defs,
+            });
+        }
+    }
+    validate_shader(
+        include_str!("../src/prepass_downsample.wgsl"),
+        "src/prepass_downsample.wgsl",
+        variants,
+    );
+}
+
+#[test]
+fn prepass_downsample_spd_shader() {
+    let mut variants = Vec::new();
+    for mip_levels in 2..=7 {
+        for depth_reduction in DEPTH_REDUCTIONS {
+            variants.push(Variant {
+                webgl: false,
+                shader_defs: vec![
+                    ShaderDefVal::UInt("MIP_LEVELS".into(), mip_levels),
+                    depth_reduction.into(),
+                ],
+            });
+        }
+    }
+    validate_shader(
+        include_str!("../src/prepass_downsample_spd.wgsl"),
+        "src/prepass_downsample_spd.wgsl",
+        variants,
+    );
+}
+
+#[test]
+fn back_face_depth_shader() {
+    let mut variants = Vec::new();
+    for webgl in [false, true] {
+        for skinned in [false, true] {
+            for morph_targets in [false, true] {
+                for multisampled in [false, true] {
+                    for deferred in [false, true] {
+                        // Some of the defs MeshPipeline::specialize adds
+                        let mut shader_defs: Vec<ShaderDefVal> = vec![
+                            "MESH_PIPELINE".into(),
+                            "VERTEX_POSITIONS".into(),
+                            "DEPTH_PREPASS".into(),
+                            "NORMAL_PREPASS".into(),
+                            "MOTION_VECTOR_PREPASS".into(),
+                        ];
+                        if skinned {
+                            shader_defs.push("SKINNED".into());
+                        }
+                        if morph_targets {
+                            shader_defs.push("MORPH_TARGETS".into());
+                        }
+                        if multisampled {
+                            shader_defs.push("MULTISAMPLED".into());
+                        }
+                        if deferred {
+                            shader_defs.push("DEFERRED_PREPASS".into());
+                        }
+                        if webgl {
+                            shader_defs.push("WEBGL2".into());
+                            // Added by bevy's MeshRenderPlugin where storage buffers aren't supported
+                            shader_defs.push(ShaderDefVal::UInt(
+                                "PER_OBJECT_BUFFER_BATCH_SIZE".into(),
+                                128,
+                            ));
+                        }
+                        variants.push(Variant { webgl, shader_defs });
+                    }
+                }
+            }
+        }
+    }
+    validate_shader(
+        include_str!("../src/back_face_depth.wgsl"),
+        "src/back_face_depth.wgsl",
+        variants,
+    );
+}
+
+#[test]
+fn copy_frame_shader() {
+    validate_shader(
+        include_str!("../src/copy_frame.wgsl"),
+        "src/copy_frame.wgsl",
+        fullscreen_variants(false, false)
+            .into_iter()
+            .flat_map(|variant| {
+                // The first pass copies the viewport of the view out of the main texture
+                [
+                    Variant {
+                        webgl: variant.webgl,
+                        shader_defs: vec!["VIEWPORT".into()],
+                    },
+                    variant,
+                ]
+            })
+            .collect(),
+    );
+}
+
+#[test]
+fn composite_shader() {
+    validate_shader(
+        include_str!("../assets/shaders/ssgi_composite.wgsl"),
+        "assets/shaders/ssgi_composite.wgsl",
+        fullscreen_variants(true, false),
+    );
+}
+
+#[test]
+fn debug_view_shader() {
+    validate_shader(
+        include_str!("../assets/shaders/ssgi_debug.wgsl"),
+        "assets/shaders/ssgi_debug.wgsl",
+        fullscreen_variants(true, true),
+    );
+}
+
+#[test]
+fn forward_material_shader() {
+    let mut variants = Vec::new();
+    for webgl in [false, true] {
+        // Some of the defs MeshPipeline::specialize and PrepassPipeline::specialize add
+        let mut common_defs: Vec<ShaderDefVal> = vec![
+            "VERTEX_OUTPUT_INSTANCE_INDEX".into(),
+            "VERTEX_POSITIONS".into(),
+            "VERTEX_NORMALS".into(),
+            "VERTEX_UVS".into(),
+            "VERTEX_TANGENTS".into(),
+            "DEPTH_PREPASS".into(),
+            "NORMAL_PREPASS".into(),
+            "MOTION_VECTOR_PREPASS".into(),
+        ];
+        if webgl {
+            common_defs.push("WEBGL2".into());
+            common_defs.push(ShaderDefVal::UInt(
+                "PER_OBJECT_BUFFER_BATCH_SIZE".into(),
+                128,
+            ));
+        }
+
+        // The prepass pipeline, the same as bevy's pbr.wgsl is used with in the deferred prepass
+        let mut shader_defs = common_defs.clone();
+        shader_defs.extend([
+            "PREPASS_PIPELINE".into(),
+            "PREPASS_FRAGMENT".into(),
+            "DEFERRED_PREPASS".into(),
+            "NORMAL_PREPASS_OR_DEFERRED_PREPASS".into(),
+            "MOTION_VECTOR_PREPASS_OR_DEFERRED_PREPASS".into(),
+        ]);
+        variants.push(Variant { webgl, shader_defs });
+
+        // The main pass
+        for tonemap_in_shader in [false, true] {
+            for environment_map in [false, true] {
+                for shadow_filter_method in SHADOW_FILTER_METHODS {
+                    let mut shader_defs = common_defs.clone();
+                    shader_defs.extend(["MESH_PIPELINE".into(), shadow_filter_method.into()]);
+                    if tonemap_in_shader {
+                        shader_defs.extend([
+                            "TONEMAP_IN_SHADER".into(),
+                            "TONEMAP_METHOD_TONY_MC_MAPFACE".into(),
+                            "DEBAND_DITHER".into(),
+                        ]);
+                    }
+                    if environment_map {
+                        shader_defs.push("ENVIRONMENT_MAP".into());
+                    }
+                    variants.push(Variant { webgl, shader_defs });
+                }
+            }
+        }
+    }
+    validate_shader(
+        include_str!("../assets/shaders/ssgi_forward.wgsl"),
+        "assets/shaders/ssgi_forward.wgsl",
+        variants,
+    );
+}
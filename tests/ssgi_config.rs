@@ -0,0 +1,97 @@
+use bevy::math::UVec2;
+use bevy_ridiculous_ssgi::{
+    prepass_downsample::PrepassDownsample,
+    ssgi::{SSGIConfigError, SSGIPass, SSGIThickness},
+};
+
+const VIEWPORT: UVec2 = UVec2::new(1920, 1080);
+
+#[test]
+fn default_is_valid() {
+    let ssgi_pass = SSGIPass::default();
+    assert_eq!(
+        ssgi_pass.validate(&PrepassDownsample::default(), VIEWPORT),
+        Ok(())
+    );
+}
+
+#[test]
+fn invalid_fields() {
+    let prepass_downsample = PrepassDownsample::default();
+    let check = |f: fn(&mut SSGIPass)| {
+        let mut ssgi_pass = SSGIPass::default();
+        f(&mut ssgi_pass);
+        ssgi_pass.validate(&prepass_downsample, VIEWPORT)
+    };
+
+    assert_eq!(
+        check(|s| s.render_scale = 3),
+        Err(SSGIConfigError::RenderScale(3))
+    );
+    assert_eq!(
+        check(|s| s.render_scale = 0),
+        Err(SSGIConfigError::RenderScale(0))
+    );
+    assert_eq!(
+        check(|s| s.cascade_0_directions = 6),
+        Err(SSGIConfigError::Cascade0Directions(6))
+    );
+    assert_eq!(
+        check(|s| s.cascade_count = 1),
+        Err(SSGIConfigError::CascadeCount(1))
+    );
+    assert!(matches!(
+        check(|s| s.mip_max = 5.0),
+        Err(SSGIConfigError::MipRange { .. })
+    ));
+    assert!(matches!(
+        check(|s| s.mip_min = 4.5),
+        Err(SSGIConfigError::MipRange { .. })
+    ));
+    assert_eq!(
+        check(|s| s.thickness = SSGIThickness::Constant(0.0)),
+        Err(SSGIConfigError::Thickness(0.0))
+    );
+}
+
+#[test]
+fn viewport_too_small() {
+    let ssgi_pass = SSGIPass::default();
+    assert_eq!(
+        ssgi_pass.validate(&PrepassDownsample::default(), UVec2::new(100, 100)),
+        Err(SSGIConfigError::ViewportTooSmall {
+            viewport_size: UVec2::new(100, 100),
+            required: UVec2::splat(128),
+        })
+    );
+}
+
+#[test]
+fn clamp_makes_valid() {
+    let prepass_downsample = PrepassDownsample::default();
+    let viewport = UVec2::new(320, 240);
+    let mut ssgi_pass = SSGIPass {
+        render_scale: 7,
+        cascade_0_directions: 3,
+        cascade_count: 9,
+        mip_min: 6.0,
+        mip_max: 8.0,
+        thickness: SSGIThickness::Constant(-1.0),
+        ..Default::default()
+    };
+    ssgi_pass.clamp(&prepass_downsample, viewport);
+    assert_eq!(ssgi_pass.validate(&prepass_downsample, viewport), Ok(()));
+    assert_eq!(ssgi_pass.render_scale, 6);
+    assert_eq!(ssgi_pass.cascade_0_directions, 4);
+    assert_eq!(ssgi_pass.thickness, SSGIThickness::Infinite);
+
+    let mut ssgi_pass = SSGIPass {
+        depth_mip_min: f32::INFINITY,
+        mip_min: f32::NAN,
+        mip_max: f32::NAN,
+        ..Default::default()
+    };
+    ssgi_pass.clamp(&prepass_downsample, viewport);
+    assert_eq!(ssgi_pass.validate(&prepass_downsample, viewport), Ok(()));
+    assert_eq!(ssgi_pass.mip_max, SSGIPass::default().mip_max);
+}
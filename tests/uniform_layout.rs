@@ -0,0 +1,81 @@
+//! Checks that the uniform structs that are duplicated in Rust and WGSL have the same layout
+
+use bevy_ridiculous_ssgi::{
+    bind_group_utils::WgslUniform, debug_view::SSGIDebugConfig, ssgi::SSGIConfig,
+    ssgi_denoise::SSGIDenoiseConfig, ssgi_generate_sh::SSGIGenerateSHConfig,
+    ssgi_resolve::SSGIResolveConfig,
+};
+
+/// Parses just the struct out of the shader, since the rest of it needs naga_oil for the imports
+fn wgsl_struct_layout(source: &str, name: &str) -> (Vec<(String, u64)>, u64) {
+    let start = source
+        .find(&format!("struct {name} {{"))
+        .unwrap_or_else(|| panic!("struct {name} not found"));
+    let end = start + source[start..].find('}').unwrap() + 1;
+
+    let module = naga::front::wgsl::parse_str(&source[start..end])
+        .unwrap_or_else(|e| panic!("{}", e.emit_to_string(&source[start..end])));
+    let ty = module
+        .types
+        .iter()
+        .map(|(_, ty)| ty)
+        .find(|ty| ty.name.as_deref() == Some(name))
+        .unwrap();
+    let naga::TypeInner::Struct { members, span } = &ty.inner else {
+        panic!("{name} is not a struct");
+    };
+    let fields = members
+        .iter()
+        .map(|member| (member.name.clone().unwrap(), member.offset as u64))
+        .collect();
+    (fields, *span as u64)
+}
+
+fn check_layout<T: WgslUniform>(source: &str) {
+    let name = T::WGSL_STRUCT;
+    let (wgsl_fields, wgsl_size) = wgsl_struct_layout(source, name);
+    let rust_fields: Vec<_> = T::field_offsets()
+        .into_iter()
+        .map(|(field, offset)| (field.to_string(), offset))
+        .collect();
+    let rust_size = T::min_size().get();
+
+    assert_eq!(rust_fields, wgsl_fields, "{name} fields don't match");
+    assert_eq!(rust_size, wgsl_size, "{name} size doesn't match");
+    // WebGL2 needs uniform buffers to be a multiple of 16 bytes
+    assert_eq!(
+        rust_size % 16,
+        0,
+        "{name} is {rust_size} bytes, pad it to a multiple of 16 with _webgl2_padding_ fields"
+    );
+}
+
+#[test]
+fn ssgi_config_layout() {
+    check_layout::<SSGIConfig>(include_str!("../assets/shaders/ssgi.wgsl"));
+}
+
+#[test]
+fn ssgi_generate_sh_config_layout() {
+    check_layout::<SSGIGenerateSHConfig>(include_str!("../assets/shaders/ssgi_generate_sh.wgsl"));
+}
+
+#[test]
+fn ssgi_resolve_config_layout() {
+    check_layout::<SSGIResolveConfig>(include_str!("../assets/shaders/ssgi_resolve.wgsl"));
+    // Takes the place of the padding, so the struct stays 48 bytes
+    assert_eq!(
+        SSGIResolveConfig::field_offsets().last(),
+        Some(&("history_valid", 44))
+    );
+}
+
+#[test]
+fn ssgi_denoise_config_layout() {
+    check_layout::<SSGIDenoiseConfig>(include_str!("../assets/shaders/ssgi_denoise.wgsl"));
+}
+
+#[test]
+fn ssgi_debug_config_layout() {
+    check_layout::<SSGIDebugConfig>(include_str!("../assets/shaders/ssgi_debug.wgsl"));
+}
@@ -0,0 +1,259 @@
+use std::f32::consts::FRAC_PI_2;
+
+use bevy::{
+    core_pipeline::core_3d::graph::Core3d,
+    prelude::*,
+    render::{
+        camera::ExtractedCamera,
+        render_graph::RenderSubGraph,
+        render_resource::{
+            Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
+        },
+        renderer::RenderDevice,
+        view::ExtractedView,
+    },
+    tasks::block_on,
+};
+use bevy_ridiculous_ssgi::view_history::{
+    advance_view_histories, SSGICameraCutDetection, SSGIHistoryReset, ViewHistories, ViewHistory,
+};
+
+#[test]
+fn first_frame_is_invalid() {
+    let history = ViewHistory::default();
+    assert!(!history.valid);
+    assert_eq!(history.write_index(), 0);
+
+    let next = history.next();
+    assert!(next.valid);
+    assert_eq!(next.frame_index, 1);
+    assert_eq!(next.write_index(), 1);
+    assert_eq!(next.next().write_index(), 0);
+}
+
+#[test]
+fn frame_index_wraps() {
+    let history = ViewHistory {
+        frame_index: u32::MAX,
+        valid: true,
+    };
+    assert_eq!(history.write_index(), 1);
+    assert_eq!(history.next().frame_index, 0);
+    assert_eq!(history.next().write_index(), 0);
+}
+
+#[test]
+fn camera_cuts() {
+    let cut_detection = SSGICameraCutDetection::default();
+    let start = GlobalTransform::from(Transform::from_xyz(0.0, 1.0, 4.0));
+    let cut =
+        |transform: Transform| cut_detection.is_cut(&start, &GlobalTransform::from(transform));
+
+    assert!(!cut(Transform::from_xyz(0.0, 1.0, 4.0)));
+    // Walking and turning a bit in a frame
+    assert!(!cut(
+        Transform::from_xyz(0.05, 1.0, 3.95).with_rotation(Quat::from_rotation_y(0.05))
+    ));
+    // Teleporting
+    assert!(cut(Transform::from_xyz(0.0, 1.0, 40.0)));
+    // Looking the other way
+    assert!(cut(
+        Transform::from_xyz(0.0, 1.0, 4.0).with_rotation(Quat::from_rotation_y(FRAC_PI_2))
+    ));
+}
+
+#[test]
+fn camera_cut_thresholds() {
+    let cut_detection = SSGICameraCutDetection {
+        max_translation: 0.5,
+        max_rotation: 0.1,
+    };
+    let start = GlobalTransform::IDENTITY;
+    assert!(!cut_detection.is_cut(&start, &Transform::from_xyz(0.4, 0.0, 0.0).into()));
+    assert!(cut_detection.is_cut(&start, &Transform::from_xyz(0.6, 0.0, 0.0).into()));
+    assert!(!cut_detection.is_cut(
+        &start,
+        &Transform::from_rotation(Quat::from_rotation_x(0.05)).into()
+    ));
+    assert!(cut_detection.is_cut(
+        &start,
+        &Transform::from_rotation(Quat::from_rotation_x(0.2)).into()
+    ));
+}
+
+#[test]
+#[ignore = "needs a GPU adapter to create the history textures, run with --ignored"]
+fn views_ping_pong_on_their_own_frames() {
+    let render_device = render_device();
+    let (mut world, mut schedule) = render_world();
+    // Two viewports rendered every frame, and a render to texture camera that's only rendered
+    // every third frame
+    let views = [
+        world.spawn_empty().id(),
+        world.spawn_empty().id(),
+        world.spawn_empty().id(),
+    ];
+    // Texture each view last wrote, the one it has to read from this frame
+    let mut written = [None; 3];
+
+    for frame in 0..12 {
+        let rendered = [true, true, frame % 3 == 0];
+        for (&entity, &rendered) in views.iter().zip(&rendered) {
+            set_rendered(&mut world, entity, rendered);
+        }
+        schedule.run(&mut world);
+
+        let mut histories = world.resource_mut::<ViewHistories>();
+        for ((&entity, &rendered), last_written) in
+            views.iter().zip(&rendered).zip(written.iter_mut())
+        {
+            if !rendered {
+                continue;
+            }
+            let textures = histories.ping_pong(&render_device, entity, &descriptor("history", 4));
+            let history = histories.get(entity).unwrap();
+            match *last_written {
+                Some(last_written) => {
+                    assert!(history.valid, "frame {frame}");
+                    assert_eq!(textures.read.texture.id(), last_written, "frame {frame}");
+                    assert_ne!(textures.write.texture.id(), last_written, "frame {frame}");
+                }
+                None => assert!(!history.valid),
+            }
+            *last_written = Some(textures.write.texture.id());
+        }
+    }
+
+    let histories = world.resource::<ViewHistories>();
+    assert_eq!(histories.get(views[0]).unwrap().frame_index, 11);
+    assert_eq!(histories.get(views[1]).unwrap().frame_index, 11);
+    assert_eq!(histories.get(views[2]).unwrap().frame_index, 3);
+}
+
+#[test]
+#[ignore = "needs a GPU adapter to create the history textures, run with --ignored"]
+fn history_is_invalidated() {
+    let render_device = render_device();
+    let (mut world, mut schedule) = render_world();
+    let view = world.spawn_empty().id();
+    set_rendered(&mut world, view, true);
+
+    // Runs a frame of the view, requesting the given textures
+    let mut frame = |world: &mut World, ping_pong_width: u32, persistent: bool| {
+        schedule.run(world);
+        let mut histories = world.resource_mut::<ViewHistories>();
+        let ping_pong = histories.ping_pong(
+            &render_device,
+            view,
+            &descriptor("history", ping_pong_width),
+        );
+        let persistent = persistent
+            .then(|| histories.persistent(&render_device, view, &descriptor("persistent", 4)));
+        (histories.get(view).unwrap(), ping_pong, persistent)
+    };
+
+    let (history, _, first_persistent) = frame(&mut world, 4, true);
+    assert!(!history.valid);
+    let first_persistent = first_persistent.unwrap().texture.id();
+    let (history, _, persistent) = frame(&mut world, 4, true);
+    assert!(history.valid);
+    assert_eq!(persistent.unwrap().texture.id(), first_persistent);
+
+    // A resized texture has no history
+    let (history, last, _) = frame(&mut world, 8, true);
+    assert!(!history.valid);
+    let (history, textures, _) = frame(&mut world, 8, true);
+    assert!(history.valid);
+    assert_eq!(textures.read.texture.id(), last.write.texture.id());
+
+    // Textures that aren't requested for a frame are dropped, and recreated when requested again
+    let (history, ..) = frame(&mut world, 8, false);
+    assert!(history.valid);
+    let (history, _, persistent) = frame(&mut world, 8, true);
+    assert!(!history.valid);
+    assert_ne!(persistent.unwrap().texture.id(), first_persistent);
+
+    world.entity_mut(view).insert(SSGIHistoryReset);
+    let (history, ..) = frame(&mut world, 8, true);
+    assert!(!history.valid);
+    world.entity_mut(view).remove::<SSGIHistoryReset>();
+    let (history, ..) = frame(&mut world, 8, true);
+    assert!(history.valid);
+}
+
+/// A device on whatever adapter is available, including software ones like llvmpipe
+fn render_device() -> RenderDevice {
+    let instance = wgpu::Instance::default();
+    let adapter = block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))
+        .expect("No adapter to create the history textures with");
+    let (device, _queue) = block_on(adapter.request_device(
+        &wgpu::DeviceDescriptor {
+            label: None,
+            required_features: wgpu::Features::empty(),
+            required_limits: adapter.limits(),
+        },
+        None,
+    ))
+    .unwrap();
+    RenderDevice::from(device)
+}
+
+/// A world with the view histories, and a schedule that advances them like the render schedule
+fn render_world() -> (World, Schedule) {
+    let mut world = World::new();
+    world.init_resource::<ViewHistories>();
+    let mut schedule = Schedule::default();
+    schedule.add_systems(advance_view_histories);
+    (world, schedule)
+}
+
+/// Adds or removes the components of an extracted camera view, like extraction would depending on
+/// whether the camera is rendered this frame
+fn set_rendered(world: &mut World, entity: Entity, rendered: bool) {
+    if !rendered {
+        world
+            .entity_mut(entity)
+            .remove::<(ExtractedView, ExtractedCamera)>();
+        return;
+    }
+    world.entity_mut(entity).insert((
+        ExtractedView {
+            projection: Mat4::IDENTITY,
+            transform: GlobalTransform::IDENTITY,
+            view_projection: None,
+            hdr: true,
+            viewport: UVec4::new(0, 0, 4, 4),
+            color_grading: default(),
+        },
+        ExtractedCamera {
+            target: None,
+            physical_viewport_size: Some(UVec2::splat(4)),
+            physical_target_size: Some(UVec2::splat(4)),
+            viewport: None,
+            render_graph: Core3d.intern(),
+            order: 0,
+            output_mode: default(),
+            msaa_writeback: false,
+            clear_color: default(),
+            sorted_camera_index_for_target: 0,
+            exposure: 1.0,
+        },
+    ));
+}
+
+fn descriptor(label: &'static str, width: u32) -> TextureDescriptor<'static> {
+    TextureDescriptor {
+        label: Some(label),
+        size: Extent3d {
+            width,
+            height: 4,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: TextureFormat::Rgba16Float,
+        usage: TextureUsages::TEXTURE_BINDING | TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    }
+}